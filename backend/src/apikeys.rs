@@ -0,0 +1,204 @@
+use actix_web::{error, web, HttpResponse, Responder};
+use rusqlite::{params, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use crate::db::{self, Store};
+use crate::rbac::Role;
+use crate::tokens::{hash_token, random_token};
+use crate::Identity;
+
+/// Prefix that tells `auth_middleware` a Bearer credential is an API key
+/// rather than a JWT.
+pub const API_KEY_PREFIX: &str = "ddk_";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ApiKeyRecord {
+    pub id: i64,
+    pub name: String,
+    pub username: String,
+    /// First characters of the key, enough to recognise it in a list.
+    pub prefix: String,
+    pub role: Role,
+    pub expires_at: Option<i64>,
+    pub last_used_at: Option<i64>,
+    pub revoked: bool,
+    pub created_at: i64,
+}
+
+impl ApiKeyRecord {
+    fn from_row(row: &Row) -> rusqlite::Result<ApiKeyRecord> {
+        Ok(ApiKeyRecord {
+            id: row.get("id")?,
+            name: row.get("name")?,
+            username: row.get("username")?,
+            prefix: row.get("prefix")?,
+            role: row.get("role")?,
+            expires_at: row.get("expires_at")?,
+            last_used_at: row.get("last_used_at")?,
+            revoked: row.get("revoked")?,
+            created_at: row.get("created_at")?,
+        })
+    }
+}
+
+const API_KEY_COLUMNS: &str = "id, name, username, prefix, role, expires_at, last_used_at, revoked, created_at";
+
+impl Store {
+    pub fn create_api_key(&self, name: &str, username: &str, key_hash: &str, prefix: &str, role: Role, expires_at: Option<i64>) -> rusqlite::Result<ApiKeyRecord> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO api_keys (name, username, key_hash, prefix, role, expires_at, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![name, username, key_hash, prefix, role, expires_at, db::now()],
+        )?;
+        conn.query_row(
+            &format!("SELECT {} FROM api_keys WHERE id = ?1", API_KEY_COLUMNS),
+            params![conn.last_insert_rowid()],
+            ApiKeyRecord::from_row,
+        )
+    }
+
+    /// Lists keys owned by `username`, or every key when `username` is `None`.
+    pub fn list_api_keys(&self, username: Option<&str>) -> rusqlite::Result<Vec<ApiKeyRecord>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!(
+            "SELECT {} FROM api_keys WHERE ?1 IS NULL OR username = ?1 ORDER BY id",
+            API_KEY_COLUMNS
+        ))?;
+        let keys = stmt.query_map(params![username], ApiKeyRecord::from_row)?.collect();
+        keys
+    }
+
+    pub fn find_api_key(&self, id: i64) -> rusqlite::Result<Option<ApiKeyRecord>> {
+        self.conn()
+            .query_row(
+                &format!("SELECT {} FROM api_keys WHERE id = ?1", API_KEY_COLUMNS),
+                params![id],
+                ApiKeyRecord::from_row,
+            )
+            .optional()
+    }
+
+    pub fn revoke_api_key(&self, id: i64) -> rusqlite::Result<()> {
+        self.conn().execute("UPDATE api_keys SET revoked = 1 WHERE id = ?1", params![id])?;
+        Ok(())
+    }
+
+    pub fn revoke_user_api_keys(&self, username: &str) -> rusqlite::Result<()> {
+        self.conn().execute("UPDATE api_keys SET revoked = 1 WHERE username = ?1", params![username])?;
+        Ok(())
+    }
+
+    /// Looks up a live key by hash and stamps its last-used time.
+    pub fn use_api_key(&self, key_hash: &str) -> rusqlite::Result<Option<ApiKeyRecord>> {
+        let conn = self.conn();
+        let now = db::now();
+        let key = conn
+            .query_row(
+                &format!(
+                    "SELECT {} FROM api_keys WHERE key_hash = ?1 AND revoked = 0 AND (expires_at IS NULL OR expires_at > ?2)",
+                    API_KEY_COLUMNS
+                ),
+                params![key_hash, now],
+                ApiKeyRecord::from_row,
+            )
+            .optional()?;
+        if let Some(key) = &key {
+            conn.execute("UPDATE api_keys SET last_used_at = ?2 WHERE id = ?1", params![key.id, now])?;
+        }
+        Ok(key)
+    }
+}
+
+/// Resolves an API key to the identity it acts as. The key never grants more
+/// than its owner currently has.
+pub fn verify_api_key(store: &Store, key: &str) -> Option<Identity> {
+    let record = store.use_api_key(&hash_token(key)).ok()??;
+    match store.find_user(&record.username).ok()? {
+        Some(user) if !user.disabled => Some(Identity {
+            username: user.username,
+            role: record.role.min(user.role),
+            session_id: None,
+            api_key_id: Some(record.id),
+        }),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewApiKey {
+    name: String,
+    role: Option<Role>,
+    expires_in_days: Option<i64>,
+}
+
+#[derive(Debug, Serialize)]
+struct ApiKeysResponse {
+    message: String,
+    api_keys: Vec<ApiKeyRecord>,
+}
+
+#[derive(Debug, Serialize)]
+struct CreatedApiKeyResponse {
+    message: String,
+    /// The plain key. It is not stored and cannot be shown again.
+    key: String,
+    api_key: ApiKeyRecord,
+}
+
+pub async fn list_api_keys(store: web::Data<Store>, identity: web::ReqData<Identity>) -> Result<impl Responder, actix_web::Error> {
+    let owner = (identity.role < Role::Admin).then_some(identity.username.as_str());
+    let api_keys = store.list_api_keys(owner).map_err(error::ErrorInternalServerError)?;
+    Ok(web::Json(ApiKeysResponse {
+        message: "API Keys List".to_string(),
+        api_keys,
+    }))
+}
+
+pub async fn create_api_key(store: web::Data<Store>, identity: web::ReqData<Identity>, body: web::Json<NewApiKey>) -> Result<impl Responder, actix_web::Error> {
+    // A key that could mint keys would outlive its own revocation.
+    if identity.api_key_id.is_some() {
+        return Err(error::ErrorForbidden("API keys cannot create API keys, sign in to create one"));
+    }
+    let NewApiKey { name, role, expires_in_days } = body.into_inner();
+    let name = name.trim().to_string();
+    if name.is_empty() {
+        return Err(error::ErrorBadRequest("API key name is required"));
+    }
+    let role = role.unwrap_or(identity.role);
+    if role > identity.role {
+        return Err(error::ErrorForbidden(format!("Cannot create a key with role {} as {}", role, identity.role)));
+    }
+    let expires_at = match expires_in_days {
+        Some(days) if days <= 0 => return Err(error::ErrorBadRequest("expires_in_days must be positive")),
+        Some(days) => match days.checked_mul(24 * 3600).and_then(|secs| secs.checked_add(db::now())) {
+            Some(expires_at) => Some(expires_at),
+            None => return Err(error::ErrorBadRequest("expires_in_days is too large")),
+        },
+        None => None,
+    };
+    let key = format!("{}{}", API_KEY_PREFIX, random_token());
+    let prefix = key[..API_KEY_PREFIX.len() + 8].to_string();
+    let api_key = store
+        .create_api_key(&name, &identity.username, &hash_token(&key), &prefix, role, expires_at)
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Created().json(CreatedApiKeyResponse {
+        message: format!("API key {} created", api_key.name),
+        key,
+        api_key,
+    }))
+}
+
+pub async fn revoke_api_key(store: web::Data<Store>, identity: web::ReqData<Identity>, id: web::Path<i64>) -> Result<impl Responder, actix_web::Error> {
+    let key = store.find_api_key(*id).map_err(error::ErrorInternalServerError)?;
+    // Other users' keys look the same as missing ones to non-admins.
+    let key = match key {
+        Some(key) if key.username == identity.username || identity.role == Role::Admin => key,
+        _ => return Err(error::ErrorNotFound(format!("API key {} not found", id))),
+    };
+    store.revoke_api_key(key.id).map_err(error::ErrorInternalServerError)?;
+    Ok(web::Json(crate::ApiResponse {
+        message: format!("API key {} revoked", key.name),
+        docker_info: None,
+        containers: None,
+    }))
+}
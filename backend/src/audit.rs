@@ -0,0 +1,277 @@
+use std::net::{IpAddr, SocketAddr};
+use actix_web::{error, web, HttpRequest, Responder};
+use rusqlite::{params, Row};
+use serde::{Deserialize, Serialize};
+use crate::db::{self, Store};
+use crate::hosts::DockerHost;
+use crate::proxy_auth::TrustedProxies;
+use crate::Identity;
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct AuditEntry {
+    pub id: i64,
+    pub timestamp: i64,
+    pub username: String,
+    pub action: String,
+    /// Docker host of the container.
+    pub host: Option<String>,
+    pub container_id: Option<String>,
+    pub container_name: Option<String>,
+    pub container_image: Option<String>,
+    pub ip: Option<String>,
+    pub success: bool,
+    pub error: Option<String>,
+}
+
+impl AuditEntry {
+    fn from_row(row: &Row) -> rusqlite::Result<AuditEntry> {
+        Ok(AuditEntry {
+            id: row.get("id")?,
+            timestamp: row.get("timestamp")?,
+            username: row.get("username")?,
+            action: row.get("action")?,
+            host: row.get("host")?,
+            container_id: row.get("container_id")?,
+            container_name: row.get("container_name")?,
+            container_image: row.get("container_image")?,
+            ip: row.get("ip")?,
+            success: row.get("success")?,
+            error: row.get("error")?,
+        })
+    }
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct AuditQuery {
+    pub user: Option<String>,
+    pub host: Option<String>,
+    /// Matches a container id prefix or an exact container name.
+    pub container: Option<String>,
+    pub since: Option<i64>,
+    pub until: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+impl Store {
+    pub fn record_audit(&self, entry: &AuditEntry) -> rusqlite::Result<()> {
+        self.conn().execute(
+            "INSERT INTO audit_log (timestamp, username, action, container_id, container_name, container_image, ip, success, error, host)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10)",
+            params![
+                entry.timestamp,
+                entry.username,
+                entry.action,
+                entry.container_id,
+                entry.container_name,
+                entry.container_image,
+                entry.ip,
+                entry.success,
+                entry.error,
+                entry.host,
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Newest entries first.
+    pub fn query_audit(&self, query: &AuditQuery) -> rusqlite::Result<Vec<AuditEntry>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT * FROM audit_log
+             WHERE (?1 IS NULL OR username = ?1)
+               AND (?2 IS NULL OR container_id LIKE ?2 || '%' OR container_name = ?2)
+               AND (?3 IS NULL OR timestamp >= ?3)
+               AND (?4 IS NULL OR timestamp <= ?4)
+               AND (?6 IS NULL OR host = ?6)
+             ORDER BY id DESC
+             LIMIT ?5",
+        )?;
+        let limit = query.limit.unwrap_or(200).clamp(1, 1000);
+        let entries = stmt
+            .query_map(
+                params![query.user, query.container, query.since, query.until, limit, query.host],
+                AuditEntry::from_row,
+            )?
+            .collect();
+        entries
+    }
+}
+
+/// Addresses recorded by the proxies in front of us, nearest last: the
+/// `Forwarded` `for=` parameters, or else `X-Forwarded-For`.
+fn forwarded_chain(req: &HttpRequest) -> Vec<String> {
+    let values = |name: &str| {
+        req.headers()
+            .get_all(name)
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect::<Vec<_>>()
+    };
+    let forwarded: Vec<String> = values("Forwarded")
+        .into_iter()
+        .filter_map(|element| {
+            element.split(';').find_map(|pair| {
+                let (key, value) = pair.trim().split_once('=')?;
+                key.eq_ignore_ascii_case("for").then(|| value.trim_matches('"').to_string())
+            })
+        })
+        .collect();
+    if !forwarded.is_empty() {
+        return forwarded;
+    }
+    values("X-Forwarded-For").into_iter().map(str::to_string).collect()
+}
+
+fn parse_ip(hop: &str) -> Option<IpAddr> {
+    hop.parse::<SocketAddr>()
+        .map(|addr| addr.ip())
+        .or_else(|_| hop.trim_matches(|c| c == '[' || c == ']').parse())
+        .ok()
+}
+
+/// The requesting client's address. Forwarding headers are read from the
+/// right, one hop per proxy in `TRUSTED_PROXY_CIDRS`; proxies append,
+/// so anything left of the first untrusted hop may be made up.
+pub fn client_ip(req: &HttpRequest) -> Option<String> {
+    let proxies = req.app_data::<web::Data<TrustedProxies>>();
+    let trusts = |ip: IpAddr| proxies.is_some_and(|proxies| proxies.trusts(ip));
+    let mut client = req.peer_addr()?.ip();
+    for hop in forwarded_chain(req).iter().rev() {
+        if !trusts(client) {
+            break;
+        }
+        match parse_ip(hop) {
+            Some(ip) => client = ip,
+            // An obfuscated or `unknown` hop: the proxy that added it is
+            // the last address we can vouch for.
+            None => break,
+        }
+    }
+    Some(client.to_string())
+}
+
+/// Writes an audit entry, logging rather than failing the request if the
+/// store is unavailable.
+pub fn record(store: &Store, entry: AuditEntry) {
+    let entry = AuditEntry { timestamp: db::now(), ..entry };
+    if let Err(e) = store.record_audit(&entry) {
+        log::error!("failed to write audit entry {:?}: {}", entry, e);
+    }
+}
+
+/// The container an entry is about, with the name and image resolved so
+/// entries stay readable after the container is gone.
+pub struct ContainerTarget {
+    id: String,
+    name: Option<String>,
+    image: Option<String>,
+}
+
+impl ContainerTarget {
+    /// Looks `id` up on `host`, keeping just the id if it cannot be found.
+    pub async fn resolve(host: &DockerHost, id: &str) -> ContainerTarget {
+        let inspect = match host.engine() {
+            Ok(engine) => engine.inspect_container(id).await.ok(),
+            Err(_) => None,
+        };
+        ContainerTarget {
+            name: inspect
+                .as_ref()
+                .and_then(|c| c.name.as_ref())
+                .map(|name| name.trim_start_matches('/').to_string()),
+            image: inspect.as_ref().and_then(|c| c.config.as_ref()).and_then(|c| c.image.clone()),
+            id: inspect.and_then(|c| c.id).unwrap_or_else(|| id.to_string()),
+        }
+    }
+}
+
+/// Records a container lifecycle action, resolving the container after the
+/// action ran.
+pub async fn record_container_action<T, E: std::fmt::Display>(
+    store: &Store,
+    req: &HttpRequest,
+    identity: &Identity,
+    host: &DockerHost,
+    action: &str,
+    container_id: &str,
+    result: &Result<T, E>,
+) {
+    let target = ContainerTarget::resolve(host, container_id).await;
+    record_container_target(store, req, identity, host, action, target, result);
+}
+
+/// Records an action on a container resolved beforehand, for actions such as
+/// removal after which it can no longer be looked up.
+pub fn record_container_target<T, E: std::fmt::Display>(
+    store: &Store,
+    req: &HttpRequest,
+    identity: &Identity,
+    host: &DockerHost,
+    action: &str,
+    target: ContainerTarget,
+    result: &Result<T, E>,
+) {
+    record(store, AuditEntry {
+        username: identity.username.clone(),
+        action: action.to_string(),
+        host: Some(host.name().to_string()),
+        container_id: Some(target.id),
+        container_name: target.name,
+        container_image: target.image,
+        ip: client_ip(req),
+        success: result.is_ok(),
+        error: result.as_ref().err().map(|e| e.to_string()),
+        ..Default::default()
+    });
+}
+
+#[derive(Debug, Serialize)]
+struct AuditResponse {
+    message: String,
+    entries: Vec<AuditEntry>,
+}
+
+pub async fn get_audit(store: web::Data<Store>, query: web::Query<AuditQuery>) -> Result<impl Responder, actix_web::Error> {
+    let entries = store.query_audit(&query).map_err(error::ErrorInternalServerError)?;
+    Ok(web::Json(AuditResponse {
+        message: "Audit Log".to_string(),
+        entries,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::TestRequest;
+    use super::*;
+
+    fn client_ip_of(peer: &str, headers: &[(&str, &str)]) -> Option<String> {
+        let mut req = TestRequest::default()
+            .peer_addr(format!("{}:40000", peer).parse().unwrap())
+            .app_data(web::Data::new(TrustedProxies::new(&["10.0.0.0/8"])));
+        for &header in headers {
+            req = req.append_header(header);
+        }
+        client_ip(&req.to_http_request())
+    }
+
+    #[test]
+    fn forwarded_addresses_are_read_from_the_right() {
+        let xff = |value| [("X-Forwarded-For", value)];
+        assert_eq!(client_ip_of("10.0.0.1", &xff("203.0.113.7")).unwrap(), "203.0.113.7");
+        assert_eq!(client_ip_of("10.0.0.1", &xff("198.51.100.9, 203.0.113.7")).unwrap(), "203.0.113.7");
+        // Another trusted proxy in the chain is skipped.
+        assert_eq!(client_ip_of("10.0.0.1", &xff("198.51.100.9, 203.0.113.7, 10.0.0.2")).unwrap(), "203.0.113.7");
+        assert_eq!(client_ip_of("10.0.0.1", &xff("203.0.113.7, unknown")).unwrap(), "10.0.0.1");
+        let split = [("X-Forwarded-For", "198.51.100.9"), ("X-Forwarded-For", "203.0.113.7")];
+        assert_eq!(client_ip_of("10.0.0.1", &split).unwrap(), "203.0.113.7");
+        let forwarded = [("Forwarded", r#"for=198.51.100.9, for="[2001:db8::7]:4711";proto=https"#)];
+        assert_eq!(client_ip_of("10.0.0.1", &forwarded).unwrap(), "2001:db8::7");
+    }
+
+    #[test]
+    fn forwarded_addresses_are_ignored_from_other_peers() {
+        assert_eq!(client_ip_of("192.168.1.5", &[("X-Forwarded-For", "203.0.113.7")]).unwrap(), "192.168.1.5");
+        assert_eq!(client_ip_of("10.0.0.1", &[]).unwrap(), "10.0.0.1");
+    }
+}
@@ -0,0 +1,485 @@
+use std::collections::{BTreeMap, HashMap};
+use std::net::IpAddr;
+use actix_web::{error, web, HttpRequest, HttpResponse, Responder};
+use bollard::container::Config;
+use bollard::errors::Error;
+use bollard::models::{HostConfig, PortBinding, RestartPolicy, RestartPolicyNameEnum};
+use serde::{Deserialize, Serialize};
+use crate::db::Store;
+use crate::hosts::Host;
+use crate::policies::ContainerAccess;
+use crate::rbac::Role;
+use crate::{audit, Identity, MyError};
+
+/// Smallest memory limit the engine accepts.
+const MIN_MEMORY_MB: i64 = 6;
+/// Largest memory limit accepted, 1 PiB, well past any real host.
+const MAX_MEMORY_MB: i64 = 1024 * 1024 * 1024;
+/// Networks anyone who may create containers can attach them to. Others,
+/// and `host` in particular, reach beyond the new container.
+const SHARED_NETWORKS: &[&str] = &["bridge", "default", "none"];
+
+/// A container to create, described the way `docker run` flags are: each
+/// list entry is one `-e`, `-p`, `-v` or `-l` value.
+#[derive(Debug, Deserialize)]
+pub struct NewContainer {
+    image: String,
+    name: Option<String>,
+    /// Split like a shell would, e.g. `sh -c 'echo hi'`.
+    command: Option<String>,
+    /// `KEY=value`.
+    #[serde(default)]
+    env: Vec<String>,
+    /// `[ip:][host_port:]container_port[/protocol]`.
+    #[serde(default)]
+    ports: Vec<String>,
+    /// `/container/path` for an anonymous volume, or
+    /// `volume_or_/host/path:/container/path[:ro]`. Named volumes and bind
+    /// mounts of host paths are limited to admins, since they may hold other
+    /// containers' data.
+    #[serde(default)]
+    volumes: Vec<String>,
+    /// `no`, `always`, `unless-stopped` or `on-failure[:max_retries]`.
+    restart_policy: Option<String>,
+    /// `key=value` or bare `key`.
+    #[serde(default)]
+    labels: Vec<String>,
+    /// Network mode; anything but `SHARED_NETWORKS` is limited to admins.
+    network: Option<String>,
+    memory_mb: Option<i64>,
+    cpus: Option<f64>,
+    /// Start the container once it is created.
+    #[serde(default = "default_start")]
+    start: bool,
+}
+
+fn default_start() -> bool {
+    true
+}
+
+/// Problems with a `NewContainer`, keyed by field name.
+type FieldErrors = BTreeMap<&'static str, String>;
+
+#[derive(Debug, Serialize)]
+struct InvalidContainer {
+    error: &'static str,
+    message: String,
+    fields: FieldErrors,
+}
+
+fn invalid(fields: FieldErrors) -> actix_web::Error {
+    let message = fields
+        .iter()
+        .map(|(field, problem)| format!("{}: {}", field, problem))
+        .collect::<Vec<_>>()
+        .join("; ");
+    let response = HttpResponse::BadRequest().json(InvalidContainer {
+        error: "invalid_container",
+        message: message.clone(),
+        fields,
+    });
+    error::InternalError::from_response(message, response).into()
+}
+
+fn invalid_field(field: &'static str, problem: impl Into<String>) -> actix_web::Error {
+    invalid(FieldErrors::from([(field, problem.into())]))
+}
+
+/// Names as the engine accepts them for containers, volumes and networks.
+fn valid_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars.next().is_some_and(|c| c.is_ascii_alphanumeric())
+        && name.len() > 1
+        && chars.all(|c| c.is_ascii_alphanumeric() || "_.-".contains(c))
+}
+
+fn non_blank(value: &Option<String>) -> Option<&str> {
+    value.as_deref().map(str::trim).filter(|v| !v.is_empty())
+}
+
+/// Splits `command` into arguments, honouring single and double quotes and
+/// backslash escapes.
+fn split_command(command: &str) -> Result<Vec<String>, String> {
+    let mut args = Vec::new();
+    let mut current = String::new();
+    let mut in_arg = false;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_arg = true;
+                loop {
+                    match chars.next() {
+                        Some('\'') => break,
+                        Some(c) => current.push(c),
+                        None => return Err("unterminated single quote".to_string()),
+                    }
+                }
+            }
+            '"' => {
+                in_arg = true;
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(c @ ('"' | '\\' | '$' | '`')) => current.push(c),
+                            Some(c) => {
+                                current.push('\\');
+                                current.push(c);
+                            }
+                            None => return Err("unterminated double quote".to_string()),
+                        },
+                        Some(c) => current.push(c),
+                        None => return Err("unterminated double quote".to_string()),
+                    }
+                }
+            }
+            '\\' => {
+                in_arg = true;
+                current.push(chars.next().ok_or("trailing backslash")?);
+            }
+            c if c.is_whitespace() => {
+                if in_arg {
+                    args.push(std::mem::take(&mut current));
+                    in_arg = false;
+                }
+            }
+            c => {
+                in_arg = true;
+                current.push(c);
+            }
+        }
+    }
+    if in_arg {
+        args.push(current);
+    }
+    Ok(args)
+}
+
+fn parse_port(spec: &str) -> Result<(String, PortBinding), String> {
+    let (rest, protocol) = spec.rsplit_once('/').unwrap_or((spec, "tcp"));
+    if !["tcp", "udp", "sctp"].contains(&protocol) {
+        return Err(format!("unknown protocol '{}'", protocol));
+    }
+    // IPv6 addresses are bracketed: [::1]:8080:80.
+    let (host_ip, rest) = match rest.strip_prefix('[') {
+        Some(bracketed) => {
+            let (ip, rest) = bracketed.split_once("]:").ok_or("expected [ip]:host_port:container_port")?;
+            (Some(ip), rest)
+        }
+        None => (None, rest),
+    };
+    let parts: Vec<&str> = rest.split(':').collect();
+    let (host_ip, host_port, container_port) = match (host_ip, parts.as_slice()) {
+        (None, [container]) => (None, "", *container),
+        (ip, [host, container]) => (ip, *host, *container),
+        (None, [ip, host, container]) => (Some(*ip), *host, *container),
+        _ => return Err("expected [ip:][host_port:]container_port[/protocol]".to_string()),
+    };
+    let container_port: u16 = container_port
+        .parse()
+        .ok()
+        .filter(|&port| port > 0)
+        .ok_or_else(|| format!("invalid container port '{}'", container_port))?;
+    if !host_port.is_empty() && host_port.parse::<u16>().ok().filter(|&port| port > 0).is_none() {
+        return Err(format!("invalid host port '{}'", host_port));
+    }
+    if let Some(ip) = host_ip {
+        ip.parse::<IpAddr>().map_err(|_| format!("invalid IP address '{}'", ip))?;
+    }
+    Ok((
+        format!("{}/{}", container_port, protocol),
+        PortBinding {
+            host_ip: host_ip.map(str::to_string),
+            // Empty lets the engine pick a free port.
+            host_port: Some(host_port.to_string()),
+        },
+    ))
+}
+
+/// A parsed `-v` value.
+enum Volume {
+    Anonymous(String),
+    Bind(String),
+    Named(String),
+}
+
+fn parse_volume(spec: &str) -> Result<Volume, String> {
+    let parts: Vec<&str> = spec.split(':').collect();
+    let (source, target, options) = match parts.as_slice() {
+        [target] => (None, *target, None),
+        [source, target] => (Some(*source), *target, None),
+        [source, target, options] => (Some(*source), *target, Some(*options)),
+        _ => return Err("expected [source:]/container/path[:ro]".to_string()),
+    };
+    if !target.starts_with('/') {
+        return Err(format!("container path '{}' must be absolute", target));
+    }
+    if let Some(options) = options {
+        if let Some(option) = options.split(',').find(|o| !["ro", "rw", "z", "Z"].contains(o)) {
+            return Err(format!("unknown option '{}'", option));
+        }
+    }
+    match source {
+        None => Ok(Volume::Anonymous(target.to_string())),
+        Some(source) if source.starts_with('/') => Ok(Volume::Bind(spec.to_string())),
+        Some(source) if valid_name(source) => Ok(Volume::Named(spec.to_string())),
+        Some(source) => Err(format!("'{}' is neither an absolute host path nor a volume name", source)),
+    }
+}
+
+fn parse_restart_policy(policy: &str) -> Result<RestartPolicy, String> {
+    let (name, retries) = policy.split_once(':').map_or((policy, None), |(name, retries)| (name, Some(retries)));
+    let name = match name {
+        "no" => RestartPolicyNameEnum::NO,
+        "always" => RestartPolicyNameEnum::ALWAYS,
+        "unless-stopped" => RestartPolicyNameEnum::UNLESS_STOPPED,
+        "on-failure" => RestartPolicyNameEnum::ON_FAILURE,
+        _ => return Err(format!("unknown policy '{}'", name)),
+    };
+    let maximum_retry_count = match retries {
+        Some(_) if name != RestartPolicyNameEnum::ON_FAILURE => {
+            return Err("only on-failure takes a retry count".to_string());
+        }
+        Some(retries) => Some(
+            retries
+                .parse::<i64>()
+                .ok()
+                .filter(|&n| n >= 0)
+                .ok_or_else(|| format!("invalid retry count '{}'", retries))?,
+        ),
+        None => None,
+    };
+    Ok(RestartPolicy {
+        name: Some(name),
+        maximum_retry_count,
+    })
+}
+
+/// Applies `parse` to each non-blank line of a list field, keeping the first
+/// problem with its line number.
+fn parse_lines<'a, T>(
+    errors: &mut FieldErrors,
+    field: &'static str,
+    lines: &'a [String],
+    parse: impl Fn(&'a str) -> Result<T, String>,
+) -> Vec<T> {
+    let mut parsed = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        match parse(line) {
+            Ok(value) => parsed.push(value),
+            Err(problem) => {
+                errors.entry(field).or_insert_with(|| format!("line {}: {}", i + 1, problem));
+            }
+        }
+    }
+    parsed
+}
+
+impl NewContainer {
+    /// Checks every field and builds the engine's create request, or returns
+    /// all problems found. Only `admin` callers may use what other
+    /// containers share: host paths, named volumes and networks.
+    fn to_config(&self, admin: bool) -> Result<(Option<String>, Config<String>), FieldErrors> {
+        let mut errors = FieldErrors::new();
+        let image = self.image.trim();
+        if image.is_empty() {
+            errors.insert("image", "is required".to_string());
+        } else if image.contains(char::is_whitespace) {
+            errors.insert("image", "must not contain spaces".to_string());
+        }
+        let name = non_blank(&self.name);
+        if name.is_some_and(|name| !valid_name(name.trim_start_matches('/'))) {
+            errors.insert("name", "may only contain letters, digits, '_', '.' and '-', and must start with a letter or digit".to_string());
+        }
+        let cmd = match non_blank(&self.command).map(split_command) {
+            Some(Ok(args)) => Some(args),
+            Some(Err(problem)) => {
+                errors.insert("command", problem);
+                None
+            }
+            None => None,
+        };
+        let env = parse_lines(&mut errors, "env", &self.env, |line| match line.split_once('=') {
+            Some((key, _)) if !key.is_empty() && !key.contains(char::is_whitespace) => Ok(line.to_string()),
+            _ => Err(format!("'{}' must look like KEY=value", line)),
+        });
+        let ports = parse_lines(&mut errors, "ports", &self.ports, parse_port);
+        let volumes = parse_lines(&mut errors, "volumes", &self.volumes, |line| match parse_volume(line)? {
+            Volume::Bind(_) if !admin => Err("mounting host paths requires the admin role".to_string()),
+            Volume::Named(_) if !admin => Err("mounting named volumes requires the admin role".to_string()),
+            volume => Ok(volume),
+        });
+        let labels = parse_lines(&mut errors, "labels", &self.labels, |line| {
+            let (key, value) = line.split_once('=').unwrap_or((line, ""));
+            if key.trim().is_empty() {
+                return Err(format!("'{}' must look like key=value", line));
+            }
+            Ok((key.trim().to_string(), value.trim().to_string()))
+        });
+        let restart_policy = match non_blank(&self.restart_policy).map(parse_restart_policy) {
+            Some(Ok(policy)) => Some(policy),
+            Some(Err(problem)) => {
+                errors.insert("restart_policy", problem);
+                None
+            }
+            None => None,
+        };
+        let network = non_blank(&self.network);
+        if network.is_some_and(|network| !valid_name(network)) {
+            errors.insert("network", "is not a valid network name".to_string());
+        } else if network.is_some_and(|network| !admin && !SHARED_NETWORKS.contains(&network)) {
+            errors.insert("network", format!("networks other than {} require the admin role", SHARED_NETWORKS.join(", ")));
+        }
+        let memory = match self.memory_mb {
+            Some(mb) if mb < MIN_MEMORY_MB => {
+                errors.insert("memory_mb", format!("must be at least {} MB", MIN_MEMORY_MB));
+                None
+            }
+            Some(mb) => match mb.checked_mul(1024 * 1024).filter(|_| mb <= MAX_MEMORY_MB) {
+                Some(bytes) => Some(bytes),
+                None => {
+                    errors.insert("memory_mb", format!("must be at most {} MB", MAX_MEMORY_MB));
+                    None
+                }
+            },
+            None => None,
+        };
+        if self.cpus.is_some_and(|cpus| !(cpus > 0.0 && cpus.is_finite())) {
+            errors.insert("cpus", "must be a positive number".to_string());
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+
+        let mut exposed_ports = HashMap::new();
+        let mut port_bindings: HashMap<String, Option<Vec<PortBinding>>> = HashMap::new();
+        for (port, binding) in ports {
+            exposed_ports.insert(port.clone(), HashMap::new());
+            port_bindings.entry(port).or_default().get_or_insert_with(Vec::new).push(binding);
+        }
+        let mut anonymous_volumes = HashMap::new();
+        let mut binds = Vec::new();
+        for volume in volumes {
+            match volume {
+                Volume::Anonymous(target) => {
+                    anonymous_volumes.insert(target, HashMap::new());
+                }
+                Volume::Bind(spec) | Volume::Named(spec) => binds.push(spec),
+            }
+        }
+        let config = Config {
+            image: Some(image.to_string()),
+            cmd,
+            env: (!env.is_empty()).then_some(env),
+            labels: Some(labels.into_iter().collect()),
+            exposed_ports: (!exposed_ports.is_empty()).then_some(exposed_ports),
+            volumes: (!anonymous_volumes.is_empty()).then_some(anonymous_volumes),
+            host_config: Some(HostConfig {
+                port_bindings: (!port_bindings.is_empty()).then_some(port_bindings),
+                binds: (!binds.is_empty()).then_some(binds),
+                restart_policy,
+                network_mode: network.map(str::to_string),
+                memory,
+                nano_cpus: self.cpus.map(|cpus| (cpus * 1e9) as i64),
+                ..Default::default()
+            }),
+            ..Default::default()
+        };
+        Ok((name.map(|name| name.trim_start_matches('/').to_string()), config))
+    }
+}
+
+/// The field an engine error from creating a container is about, if any.
+fn field_for(e: &Error) -> Option<&'static str> {
+    match e {
+        Error::DockerResponseServerError { status_code: 409, .. } => Some("name"),
+        Error::DockerResponseServerError { status_code: 404, message } if message.to_lowercase().contains("network") => Some("network"),
+        Error::DockerResponseServerError { status_code: 404, .. } => Some("image"),
+        Error::DockerResponseServerError { status_code: 400, message } if message.contains("reference format") => Some("image"),
+        _ => None,
+    }
+}
+
+fn is_missing_image(e: &Error) -> bool {
+    field_for(e) == Some("image") && matches!(e, Error::DockerResponseServerError { status_code: 404, .. })
+}
+
+#[derive(Debug, Serialize)]
+struct CreatedContainer {
+    message: String,
+    id: String,
+    started: bool,
+    warnings: Vec<String>,
+}
+
+/// Creates a container, pulling its image first if the engine lacks it, and
+/// starts it unless asked not to.
+pub async fn create_container(
+    req: HttpRequest,
+    store: web::Data<Store>,
+    identity: web::ReqData<Identity>,
+    host: Host,
+    body: web::Json<NewContainer>,
+) -> Result<impl Responder, actix_web::Error> {
+    let spec = body.into_inner();
+    let (name, config) = spec.to_config(identity.role == Role::Admin).map_err(invalid)?;
+    // Users limited by policies may only create containers they could
+    // operate afterwards.
+    let access = ContainerAccess::for_identity(&store, &identity)?;
+    if access.role_for(config.labels.as_ref()).is_none_or(|role| role < Role::Operator) {
+        return Err(invalid_field("labels", "your container policies do not allow a container with these labels"));
+    }
+    let engine = host.engine()?;
+    let image = config.image.clone().unwrap_or_default();
+    let mut result = engine.create_container(name.as_deref(), config.clone()).await;
+    if result.as_ref().is_err_and(is_missing_image) {
+        log::info!("pulling {} on Docker host {}", image, host.name());
+        result = match engine.pull_image(&image).await {
+            Ok(()) => engine.create_container(name.as_deref(), config).await,
+            Err(e) => Err(e),
+        };
+    }
+    let target = name.clone().unwrap_or_else(|| image.clone());
+    let created = match result {
+        Ok(created) => created,
+        Err(e) => {
+            let failed: Result<(), &Error> = Err(&e);
+            audit::record_container_action(&store, &req, &identity, &host, "create", &target, &failed).await;
+            return Err(match (field_for(&e), &e) {
+                (Some(field), Error::DockerResponseServerError { message, .. }) => invalid_field(field, message.clone()),
+                (None, Error::DockerResponseServerError { status_code: 400, message }) => {
+                    error::ErrorBadRequest(message.clone())
+                }
+                _ => MyError(e).into(),
+            });
+        }
+    };
+    let ok: Result<(), Error> = Ok(());
+    audit::record_container_action(&store, &req, &identity, &host, "create", &created.id, &ok).await;
+
+    let display_name = name.unwrap_or_else(|| created.id.chars().take(12).collect());
+    let mut message = format!("Container {} created", display_name);
+    let mut started = false;
+    if spec.start {
+        let result = engine.start_container(&created.id).await;
+        audit::record_container_action(&store, &req, &identity, &host, "start", &created.id, &result).await;
+        match result {
+            Ok(()) => {
+                message = format!("Container {} created and started", display_name);
+                started = true;
+            }
+            Err(e) => message = format!("Container {} created but failed to start: {}", display_name, MyError(e)),
+        }
+    }
+    Ok(HttpResponse::Created().json(CreatedContainer {
+        message,
+        id: created.id,
+        started,
+        warnings: created.warnings,
+    }))
+}
@@ -0,0 +1,228 @@
+use std::env;
+use actix_cors::Cors;
+use actix_web::body::MessageBody;
+use actix_web::dev::{RequestHead, ServiceRequest, ServiceResponse};
+use actix_web::http::{header, header::HeaderValue, Method, StatusCode, Uri};
+use actix_web::middleware::Next;
+
+/// Cross-origin policy, read from `CORS_*` variables.
+///
+/// With no `CORS_ALLOWED_ORIGINS` only same-origin requests work, which is
+/// what a frontend served behind the same host as the API needs.
+#[derive(Debug, Clone)]
+pub struct CorsConfig {
+    /// `None` allows any origin (`CORS_ALLOWED_ORIGINS=*`).
+    origins: Option<Vec<String>>,
+    methods: Vec<Method>,
+    headers: Vec<header::HeaderName>,
+    credentials: bool,
+    max_age: usize,
+}
+
+fn list(name: &str, default: &str) -> Vec<String> {
+    env::var(name)
+        .unwrap_or_else(|_| default.to_string())
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+/// Browsers send `Origin` on same-origin POSTs too, so those must pass even
+/// when no cross-origin access is configured.
+fn is_same_origin(origin: &HeaderValue, req: &RequestHead) -> bool {
+    let authority = origin
+        .to_str()
+        .ok()
+        .and_then(|origin| origin.parse::<Uri>().ok())
+        .and_then(|uri| uri.authority().map(|a| a.as_str().to_ascii_lowercase()));
+    let host = req.headers().get(header::HOST).and_then(|h| h.to_str().ok()).map(str::to_ascii_lowercase);
+    authority.is_some() && authority == host
+}
+
+impl CorsConfig {
+    pub fn from_env() -> Result<CorsConfig, String> {
+        let origins = list("CORS_ALLOWED_ORIGINS", "");
+        let origins = if origins.iter().any(|origin| origin == "*") {
+            None
+        } else {
+            for origin in &origins {
+                let valid = origin
+                    .parse::<Uri>()
+                    .map(|uri| uri.scheme().is_some() && uri.host().is_some() && uri.path() == "/" && !origin.ends_with('/'))
+                    .unwrap_or(false);
+                if !valid {
+                    return Err(format!("CORS_ALLOWED_ORIGINS entry '{}' must look like https://host[:port]", origin));
+                }
+            }
+            Some(origins)
+        };
+        let methods = list("CORS_ALLOWED_METHODS", "GET,POST,PUT,DELETE")
+            .iter()
+            .map(|method| method.to_uppercase().parse::<Method>().map_err(|_| format!("invalid CORS method '{}'", method)))
+            .collect::<Result<_, _>>()?;
+        let headers = list("CORS_ALLOWED_HEADERS", "Authorization,Content-Type,X-CSRF-Token")
+            .iter()
+            .map(|name| name.parse::<header::HeaderName>().map_err(|_| format!("invalid CORS header '{}'", name)))
+            .collect::<Result<_, _>>()?;
+        let credentials = env::var("CORS_ALLOW_CREDENTIALS").map(|v| v == "true" || v == "1").unwrap_or(false);
+        if credentials && origins.is_none() {
+            return Err("CORS_ALLOW_CREDENTIALS cannot be combined with CORS_ALLOWED_ORIGINS=*".to_string());
+        }
+        let max_age = env::var("CORS_MAX_AGE").ok().and_then(|v| v.parse().ok()).unwrap_or(3600);
+        match &origins {
+            None => log::warn!("CORS allows any origin"),
+            Some(origins) if origins.is_empty() => log::info!("CORS disabled, only same-origin requests are allowed"),
+            Some(origins) => log::info!("CORS allows {:?}", origins),
+        }
+        Ok(CorsConfig { origins, methods, headers, credentials, max_age })
+    }
+
+    pub fn build(&self) -> Cors {
+        let mut cors = Cors::default()
+            .allowed_methods(self.methods.clone())
+            .allowed_headers(self.headers.clone())
+            // The login form reads it to show how long a lockout lasts.
+            .expose_headers([header::RETRY_AFTER])
+            .max_age(self.max_age)
+            // Refuse, rather than just not annotate, simple requests from
+            // other origins, since those skip the preflight.
+            .block_on_origin_mismatch(true);
+        cors = match &self.origins {
+            None => cors.allow_any_origin(),
+            // Only with an allowlist: any origin function makes actix-cors
+            // ignore `allow_any_origin`.
+            Some(origins) => origins
+                .iter()
+                .fold(cors.allowed_origin_fn(is_same_origin), |cors, origin| cors.allowed_origin(origin)),
+        };
+        if self.credentials {
+            cors = cors.supports_credentials();
+        }
+        cors
+    }
+}
+
+/// Logs cross-origin requests the CORS middleware turned away. Must wrap
+/// outside of it.
+pub async fn log_rejected<B: MessageBody>(req: ServiceRequest, next: Next<B>) -> Result<ServiceResponse<B>, actix_web::Error> {
+    let origin = req.headers().get(header::ORIGIN).and_then(|v| v.to_str().ok()).map(str::to_string);
+    let preflight = req.method() == Method::OPTIONS && req.headers().contains_key(header::ACCESS_CONTROL_REQUEST_METHOD);
+    let method = req.method().clone();
+    let path = req.path().to_string();
+    let peer = req.peer_addr();
+    let res = next.call(req).await?;
+    if let Some(origin) = origin {
+        let rejected = res.status() == StatusCode::BAD_REQUEST
+            && !res.headers().contains_key(header::ACCESS_CONTROL_ALLOW_ORIGIN);
+        if rejected {
+            let kind = if preflight { "preflight" } else { "request" };
+            log::warn!("rejected CORS {} from origin {} ({:?}): {} {}", kind, origin, peer, method, path);
+        }
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use actix_web::test::{self, TestRequest};
+    use actix_web::{middleware::from_fn, web, App, HttpResponse};
+    use super::*;
+
+    fn config(origins: Option<&[&str]>) -> CorsConfig {
+        CorsConfig {
+            origins: origins.map(|origins| origins.iter().map(|o| o.to_string()).collect()),
+            methods: vec![Method::GET, Method::POST],
+            headers: vec![header::AUTHORIZATION, header::CONTENT_TYPE],
+            credentials: false,
+            max_age: 60,
+        }
+    }
+
+    /// Sends `req` through the CORS middleware; returns the status and the
+    /// allowed origin it answered with.
+    async fn send(config: &CorsConfig, req: TestRequest) -> (StatusCode, Option<String>) {
+        let app = test::init_service(
+            App::new()
+                .wrap(config.build())
+                .wrap(from_fn(log_rejected))
+                .route("/containers", web::to(HttpResponse::Ok)),
+        )
+        .await;
+        let res = test::call_service(&app, req.insert_header((header::HOST, "dashboard.example.com")).to_request()).await;
+        let allowed = res
+            .headers()
+            .get(header::ACCESS_CONTROL_ALLOW_ORIGIN)
+            .map(|v| v.to_str().unwrap().to_string());
+        (res.status(), allowed)
+    }
+
+    fn preflight(origin: &str) -> TestRequest {
+        TestRequest::default()
+            .method(Method::OPTIONS)
+            .uri("/containers")
+            .insert_header((header::ORIGIN, origin))
+            .insert_header((header::ACCESS_CONTROL_REQUEST_METHOD, "POST"))
+    }
+
+    fn post(origin: &str) -> TestRequest {
+        TestRequest::post().uri("/containers").insert_header((header::ORIGIN, origin))
+    }
+
+    #[actix_web::test]
+    async fn only_listed_origins_are_allowed() {
+        let config = config(Some(&["https://ops.example.com"]));
+        let (status, allowed) = send(&config, preflight("https://ops.example.com")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(allowed.as_deref(), Some("https://ops.example.com"));
+        let (status, allowed) = send(&config, post("https://ops.example.com")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(allowed.as_deref(), Some("https://ops.example.com"));
+
+        let (status, allowed) = send(&config, preflight("https://evil.example.com")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(allowed, None);
+        // Simple requests skip the preflight, so they are refused outright.
+        let (status, allowed) = send(&config, post("https://evil.example.com")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert_eq!(allowed, None);
+    }
+
+    #[actix_web::test]
+    async fn same_origin_requests_pass_without_a_list() {
+        let config = config(Some(&[]));
+        let (status, _) = send(&config, post("https://dashboard.example.com")).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&config, TestRequest::post().uri("/containers")).await;
+        assert_eq!(status, StatusCode::OK);
+        let (status, _) = send(&config, post("https://ops.example.com")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        let (status, _) = send(&config, post("https://dashboard.example.com.evil.example")).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+
+    #[actix_web::test]
+    async fn a_wildcard_allows_any_origin() {
+        let (status, allowed) = send(&config(None), preflight("https://anything.example")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(allowed.is_some());
+    }
+
+    /// Runs every `from_env` case in one test, since they share variables.
+    #[test]
+    fn invalid_settings_are_refused() {
+        env::remove_var("CORS_ALLOW_CREDENTIALS");
+        for origins in ["https://ops.example.com/", "ops.example.com", "https://ops.example.com/app"] {
+            env::set_var("CORS_ALLOWED_ORIGINS", origins);
+            assert!(CorsConfig::from_env().is_err(), "{}", origins);
+        }
+        env::set_var("CORS_ALLOWED_ORIGINS", "https://ops.example.com, http://localhost:8080");
+        let config = CorsConfig::from_env().unwrap();
+        assert_eq!(config.origins.unwrap(), ["https://ops.example.com", "http://localhost:8080"]);
+        env::set_var("CORS_ALLOWED_ORIGINS", "*");
+        env::set_var("CORS_ALLOW_CREDENTIALS", "true");
+        assert!(CorsConfig::from_env().is_err());
+        env::remove_var("CORS_ALLOWED_ORIGINS");
+        env::remove_var("CORS_ALLOW_CREDENTIALS");
+    }
+}
@@ -0,0 +1,156 @@
+use std::sync::{Mutex, MutexGuard};
+use std::time::{SystemTime, UNIX_EPOCH};
+use rusqlite::Connection;
+
+/// Schema migrations, applied in order. `PRAGMA user_version` records how
+/// many have run, so only append to this list.
+const MIGRATIONS: &[&str] = &[
+    "CREATE TABLE IF NOT EXISTS users (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        username TEXT NOT NULL UNIQUE,
+        password_hash TEXT NOT NULL,
+        disabled INTEGER NOT NULL DEFAULT 0,
+        created_at INTEGER NOT NULL
+    );",
+    // Accounts that predate roles already had full access.
+    "ALTER TABLE users ADD COLUMN role TEXT NOT NULL DEFAULT 'viewer';
+    UPDATE users SET role = 'admin';",
+    "ALTER TABLE users ADD COLUMN tokens_valid_after INTEGER NOT NULL DEFAULT 0;
+    CREATE TABLE refresh_tokens (
+        token_hash TEXT PRIMARY KEY,
+        username TEXT NOT NULL REFERENCES users(username) ON DELETE CASCADE,
+        expires_at INTEGER NOT NULL,
+        revoked INTEGER NOT NULL DEFAULT 0,
+        created_at INTEGER NOT NULL
+    );
+    CREATE TABLE revoked_tokens (
+        jti TEXT PRIMARY KEY,
+        expires_at INTEGER NOT NULL
+    );",
+    "CREATE TABLE api_keys (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        name TEXT NOT NULL,
+        username TEXT NOT NULL REFERENCES users(username) ON DELETE CASCADE,
+        key_hash TEXT NOT NULL UNIQUE,
+        prefix TEXT NOT NULL,
+        role TEXT NOT NULL,
+        expires_at INTEGER,
+        last_used_at INTEGER,
+        revoked INTEGER NOT NULL DEFAULT 0,
+        created_at INTEGER NOT NULL
+    );",
+    "CREATE TABLE audit_log (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        timestamp INTEGER NOT NULL,
+        username TEXT NOT NULL,
+        action TEXT NOT NULL,
+        container_id TEXT,
+        container_name TEXT,
+        container_image TEXT,
+        ip TEXT,
+        success INTEGER NOT NULL,
+        error TEXT
+    );
+    CREATE INDEX audit_log_timestamp ON audit_log(timestamp);",
+    "ALTER TABLE users ADD COLUMN auth_source TEXT NOT NULL DEFAULT 'local';",
+    "ALTER TABLE users ADD COLUMN totp_secret TEXT;
+    ALTER TABLE users ADD COLUMN totp_enabled INTEGER NOT NULL DEFAULT 0;
+    ALTER TABLE users ADD COLUMN totp_last_step INTEGER NOT NULL DEFAULT 0;
+    CREATE TABLE recovery_codes (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        username TEXT NOT NULL REFERENCES users(username) ON DELETE CASCADE,
+        code_hash TEXT NOT NULL,
+        used_at INTEGER
+    );",
+    "CREATE TABLE container_policies (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        username TEXT NOT NULL,
+        project TEXT,
+        labels TEXT,
+        role TEXT NOT NULL,
+        created_at INTEGER NOT NULL
+    );
+    CREATE INDEX container_policies_username ON container_policies (username);",
+    "ALTER TABLE users ADD COLUMN must_change_password INTEGER NOT NULL DEFAULT 0;",
+    "CREATE TABLE sessions (
+        id TEXT PRIMARY KEY,
+        username TEXT NOT NULL REFERENCES users(username) ON DELETE CASCADE,
+        user_agent TEXT,
+        ip TEXT,
+        created_at INTEGER NOT NULL,
+        last_seen_at INTEGER NOT NULL,
+        expires_at INTEGER NOT NULL,
+        revoked INTEGER NOT NULL DEFAULT 0
+    );
+    CREATE INDEX sessions_username ON sessions (username);
+    ALTER TABLE refresh_tokens ADD COLUMN session_id TEXT;",
+    "CREATE TABLE docker_hosts (
+        name TEXT PRIMARY KEY,
+        endpoint TEXT NOT NULL,
+        tls_ca_path TEXT,
+        tls_cert_path TEXT,
+        tls_key_path TEXT,
+        created_at INTEGER NOT NULL
+    );
+    ALTER TABLE audit_log ADD COLUMN host TEXT;",
+    "ALTER TABLE docker_hosts ADD COLUMN ssh_key_path TEXT;
+    ALTER TABLE docker_hosts ADD COLUMN ssh_known_hosts_path TEXT;",
+    // Policies used to outlive their user and carry over to anyone who
+    // later took the same name.
+    "DELETE FROM container_policies WHERE username NOT IN (SELECT username FROM users);
+    CREATE TABLE container_policies_new (
+        id INTEGER PRIMARY KEY AUTOINCREMENT,
+        username TEXT NOT NULL REFERENCES users (username) ON DELETE CASCADE,
+        project TEXT,
+        labels TEXT,
+        role TEXT NOT NULL,
+        created_at INTEGER NOT NULL
+    );
+    INSERT INTO container_policies_new (id, username, project, labels, role, created_at)
+        SELECT id, username, project, labels, role, created_at FROM container_policies;
+    DROP TABLE container_policies;
+    ALTER TABLE container_policies_new RENAME TO container_policies;
+    CREATE INDEX container_policies_username ON container_policies (username);",
+];
+
+/// SQLite-backed persistent state shared by all workers via `web::Data`.
+pub struct Store {
+    conn: Mutex<Connection>,
+}
+
+impl Store {
+    pub fn open(path: &str) -> rusqlite::Result<Store> {
+        let conn = Connection::open(path)?;
+        Self::init(conn)
+    }
+
+    fn init(mut conn: Connection) -> rusqlite::Result<Store> {
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        let version: usize = conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let tx = conn.transaction()?;
+            tx.execute_batch(migration)?;
+            tx.pragma_update(None, "user_version", i + 1)?;
+            tx.commit()?;
+        }
+        Ok(Store { conn: Mutex::new(conn) })
+    }
+
+    pub(crate) fn conn(&self) -> MutexGuard<'_, Connection> {
+        self.conn.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+pub fn now() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs() as i64
+}
+
+pub fn is_constraint_violation(err: &rusqlite::Error) -> bool {
+    matches!(
+        err,
+        rusqlite::Error::SqliteFailure(e, _) if e.code == rusqlite::ErrorCode::ConstraintViolation
+    )
+}
@@ -0,0 +1,504 @@
+use async_trait::async_trait;
+use bollard::container::{
+    Config, CreateContainerOptions, KillContainerOptions, ListContainersOptions, RemoveContainerOptions, RestartContainerOptions,
+    StartContainerOptions, StopContainerOptions,
+};
+use bollard::errors::Error;
+use bollard::image::CreateImageOptions;
+use bollard::models::{ContainerCreateResponse, ContainerInspectResponse, ContainerSummary, SystemInfo};
+use bollard::system::Version;
+use bollard::{ClientVersion, Docker};
+use futures_util::TryStreamExt;
+
+/// The engine operations the dashboard uses. Errors are bollard's, so
+/// handlers report every backend the same way.
+#[async_trait]
+pub trait ContainerEngine: Send + Sync {
+    /// Settles on the newest API version both sides speak.
+    async fn negotiate_version(&self) -> Result<(), Error>;
+    /// The API version requests are sent with.
+    fn api_version(&self) -> ClientVersion;
+    async fn version(&self) -> Result<Version, Error>;
+    async fn info(&self) -> Result<SystemInfo, Error>;
+    /// All containers, including stopped ones.
+    async fn list_containers(&self) -> Result<Vec<ContainerSummary>, Error>;
+    async fn inspect_container(&self, id: &str) -> Result<ContainerInspectResponse, Error>;
+    async fn create_container(&self, name: Option<&str>, config: Config<String>) -> Result<ContainerCreateResponse, Error>;
+    /// Pulls `image`, `latest` unless it names a tag or digest.
+    async fn pull_image(&self, image: &str) -> Result<(), Error>;
+    async fn start_container(&self, id: &str) -> Result<(), Error>;
+    /// Stops a container, killing it after `timeout` seconds or the
+    /// container's own stop timeout if `None`.
+    async fn stop_container(&self, id: &str, timeout: Option<i64>) -> Result<(), Error>;
+    /// Restarts a container, with the same `timeout` as `stop_container`.
+    async fn restart_container(&self, id: &str, timeout: Option<i64>) -> Result<(), Error>;
+    async fn pause_container(&self, id: &str) -> Result<(), Error>;
+    async fn unpause_container(&self, id: &str) -> Result<(), Error>;
+    /// Sends `signal`, such as `SIGKILL` or `SIGHUP`, to the container's
+    /// main process.
+    async fn kill_container(&self, id: &str, signal: &str) -> Result<(), Error>;
+    /// Removes a container, killing it first if `force` is set and dropping
+    /// its anonymous volumes if `volumes` is.
+    async fn remove_container(&self, id: &str, force: bool, volumes: bool) -> Result<(), Error>;
+}
+
+#[async_trait]
+impl ContainerEngine for Docker {
+    async fn negotiate_version(&self) -> Result<(), Error> {
+        // Clones share the version, so this updates `self`.
+        Docker::negotiate_version(self.clone()).await.map(|_| ())
+    }
+
+    fn api_version(&self) -> ClientVersion {
+        self.client_version()
+    }
+
+    async fn version(&self) -> Result<Version, Error> {
+        Docker::version(self).await
+    }
+
+    async fn info(&self) -> Result<SystemInfo, Error> {
+        Docker::info(self).await
+    }
+
+    async fn list_containers(&self) -> Result<Vec<ContainerSummary>, Error> {
+        let options = ListContainersOptions::<String> {
+            all: true,
+            ..Default::default()
+        };
+        Docker::list_containers(self, Some(options)).await
+    }
+
+    async fn inspect_container(&self, id: &str) -> Result<ContainerInspectResponse, Error> {
+        Docker::inspect_container(self, id, None).await
+    }
+
+    async fn create_container(&self, name: Option<&str>, config: Config<String>) -> Result<ContainerCreateResponse, Error> {
+        let options = name.map(|name| CreateContainerOptions { name, platform: None });
+        Docker::create_container(self, options, config).await
+    }
+
+    async fn pull_image(&self, image: &str) -> Result<(), Error> {
+        // Without a tag the engine would pull every tag of the repository.
+        let (from_image, tag) = match image.rsplit_once(':') {
+            Some((repository, tag)) if !image.contains('@') && !tag.contains('/') => (repository, tag),
+            _ if image.contains('@') => (image, ""),
+            _ => (image, "latest"),
+        };
+        let options = CreateImageOptions {
+            from_image,
+            tag,
+            ..Default::default()
+        };
+        Docker::create_image(self, Some(options), None, None).try_collect::<Vec<_>>().await.map(|_| ())
+    }
+
+    async fn start_container(&self, id: &str) -> Result<(), Error> {
+        Docker::start_container(self, id, None::<StartContainerOptions<String>>).await
+    }
+
+    async fn stop_container(&self, id: &str, timeout: Option<i64>) -> Result<(), Error> {
+        Docker::stop_container(self, id, timeout.map(|t| StopContainerOptions { t })).await
+    }
+
+    async fn restart_container(&self, id: &str, timeout: Option<i64>) -> Result<(), Error> {
+        Docker::restart_container(self, id, timeout.map(|t| RestartContainerOptions { t: t as isize })).await
+    }
+
+    async fn pause_container(&self, id: &str) -> Result<(), Error> {
+        Docker::pause_container(self, id).await
+    }
+
+    async fn unpause_container(&self, id: &str) -> Result<(), Error> {
+        Docker::unpause_container(self, id).await
+    }
+
+    async fn kill_container(&self, id: &str, signal: &str) -> Result<(), Error> {
+        Docker::kill_container(self, id, Some(KillContainerOptions { signal })).await
+    }
+
+    async fn remove_container(&self, id: &str, force: bool, volumes: bool) -> Result<(), Error> {
+        let options = RemoveContainerOptions {
+            force,
+            v: volumes,
+            ..Default::default()
+        };
+        Docker::remove_container(self, id, Some(options)).await
+    }
+}
+
+#[cfg(test)]
+pub mod fake {
+    use std::collections::{BTreeMap, BTreeSet, HashMap};
+    use std::io;
+    use std::sync::Mutex;
+    use async_trait::async_trait;
+    use bollard::container::Config;
+    use bollard::errors::Error;
+    use bollard::models::{ContainerConfig, ContainerCreateResponse, ContainerInspectResponse, ContainerState, ContainerStateStatusEnum, ContainerSummary, SystemInfo};
+    use bollard::system::{Version, VersionComponents};
+    use bollard::{ClientVersion, API_DEFAULT_VERSION};
+    use super::ContainerEngine;
+
+    #[derive(Debug, Clone)]
+    pub struct FakeContainer {
+        pub id: String,
+        pub name: String,
+        pub image: String,
+        pub labels: HashMap<String, String>,
+        pub running: bool,
+        pub paused: bool,
+        /// Signals sent with `kill_container`, oldest first.
+        pub signals: Vec<String>,
+        /// What the container was created with, for those created through
+        /// the engine.
+        pub config: Option<Config<String>>,
+    }
+
+    impl FakeContainer {
+        pub fn new(id: &str, name: &str, image: &str) -> FakeContainer {
+            FakeContainer {
+                id: id.to_string(),
+                name: name.to_string(),
+                image: image.to_string(),
+                labels: HashMap::new(),
+                running: false,
+                paused: false,
+                signals: Vec::new(),
+                config: None,
+            }
+        }
+
+        pub fn label(mut self, key: &str, value: &str) -> FakeContainer {
+            self.labels.insert(key.to_string(), value.to_string());
+            self
+        }
+
+        pub fn running(mut self) -> FakeContainer {
+            self.running = true;
+            self
+        }
+
+        fn state(&self) -> &'static str {
+            match (self.running, self.paused) {
+                (true, true) => "paused",
+                (true, false) => "running",
+                _ => "exited",
+            }
+        }
+    }
+
+    /// An engine that keeps its containers in memory. Every call is recorded
+    /// so tests can check what reached the engine.
+    #[derive(Default)]
+    pub struct FakeEngine {
+        containers: Mutex<BTreeMap<String, FakeContainer>>,
+        /// Images present locally.
+        images: Mutex<BTreeSet<String>>,
+        /// Images `pull_image` can fetch.
+        registry: Mutex<BTreeSet<String>>,
+        calls: Mutex<Vec<String>>,
+        /// How many more version checks fail as if the engine were down.
+        outage: Mutex<u32>,
+        /// The API version to report instead of bollard's default.
+        api_version: Option<ClientVersion>,
+        /// Whether version negotiation fails to parse the engine's answer.
+        unversioned: bool,
+        /// The component `version` lists instead of Docker's "Engine".
+        component: Option<String>,
+    }
+
+    impl FakeEngine {
+        /// An engine running `containers`, with their images present.
+        pub fn with(containers: impl IntoIterator<Item = FakeContainer>) -> FakeEngine {
+            let engine = FakeEngine::default();
+            for container in containers {
+                engine.images.lock().unwrap().insert(container.image.clone());
+                engine.containers.lock().unwrap().insert(container.id.clone(), container);
+            }
+            engine
+        }
+
+        pub fn api_version(mut self, major_version: usize, minor_version: usize) -> FakeEngine {
+            self.api_version = Some(ClientVersion { major_version, minor_version });
+            self
+        }
+
+        /// Answers version negotiation with an API version that does not parse.
+        pub fn unversioned(mut self) -> FakeEngine {
+            self.unversioned = true;
+            self
+        }
+
+        pub fn component(mut self, name: &str) -> FakeEngine {
+            self.component = Some(name.to_string());
+            self
+        }
+
+        /// Makes `image` available to pull.
+        pub fn publish(&self, image: &str) {
+            self.registry.lock().unwrap().insert(image.to_string());
+        }
+
+        /// Makes the next `checks` version checks fail to connect.
+        pub fn fail_checks(&self, checks: u32) {
+            *self.outage.lock().unwrap() = checks;
+        }
+
+        pub fn container(&self, id: &str) -> Option<FakeContainer> {
+            self.containers.lock().unwrap().get(id).cloned()
+        }
+
+        pub fn calls(&self) -> Vec<String> {
+            self.calls.lock().unwrap().clone()
+        }
+
+        fn record(&self, call: String) {
+            self.calls.lock().unwrap().push(call);
+        }
+
+        fn update<T>(&self, id: &str, f: impl FnOnce(&mut FakeContainer) -> T) -> Result<T, Error> {
+            let mut containers = self.containers.lock().unwrap();
+            // Docker also resolves names, with or without the leading slash.
+            containers
+                .values_mut()
+                .find(|c| c.id == id || c.name == id.trim_start_matches('/'))
+                .map(f)
+                .ok_or_else(|| not_found(id))
+        }
+    }
+
+    fn conflict(message: String) -> Error {
+        Error::DockerResponseServerError { status_code: 409, message }
+    }
+
+    fn not_found(id: &str) -> Error {
+        Error::DockerResponseServerError {
+            status_code: 404,
+            message: format!("No such container: {}", id),
+        }
+    }
+
+    fn with_timeout(call: String, timeout: Option<i64>) -> String {
+        match timeout {
+            Some(t) => format!("{} t={}", call, t),
+            None => call,
+        }
+    }
+
+    #[async_trait]
+    impl ContainerEngine for FakeEngine {
+        async fn negotiate_version(&self) -> Result<(), Error> {
+            if self.unversioned {
+                return Err(Error::APIVersionParseError {});
+            }
+            Ok(())
+        }
+
+        fn api_version(&self) -> ClientVersion {
+            self.api_version.unwrap_or(*API_DEFAULT_VERSION)
+        }
+
+        async fn version(&self) -> Result<Version, Error> {
+            let mut outage = self.outage.lock().unwrap();
+            if *outage > 0 {
+                *outage -= 1;
+                return Err(io::Error::new(io::ErrorKind::ConnectionRefused, "Connection refused").into());
+            }
+            Ok(Version {
+                version: Some("27.0.0-fake".to_string()),
+                api_version: Some(self.api_version().to_string()),
+                components: Some(vec![VersionComponents {
+                    name: self.component.clone().unwrap_or_else(|| "Engine".to_string()),
+                    version: "27.0.0-fake".to_string(),
+                    details: None,
+                }]),
+                ..Default::default()
+            })
+        }
+
+        async fn info(&self) -> Result<SystemInfo, Error> {
+            let containers = self.containers.lock().unwrap();
+            let running = containers.values().filter(|c| c.running).count() as i64;
+            Ok(SystemInfo {
+                name: Some("fake".to_string()),
+                containers: Some(containers.len() as i64),
+                containers_running: Some(running),
+                containers_stopped: Some(containers.len() as i64 - running),
+                ..Default::default()
+            })
+        }
+
+        async fn list_containers(&self) -> Result<Vec<ContainerSummary>, Error> {
+            self.record("list".to_string());
+            let containers = self.containers.lock().unwrap();
+            Ok(containers
+                .values()
+                .map(|c| ContainerSummary {
+                    id: Some(c.id.clone()),
+                    names: Some(vec![format!("/{}", c.name)]),
+                    image: Some(c.image.clone()),
+                    labels: Some(c.labels.clone()),
+                    state: Some(c.state().to_string()),
+                    ..Default::default()
+                })
+                .collect())
+        }
+
+        async fn inspect_container(&self, id: &str) -> Result<ContainerInspectResponse, Error> {
+            let c = self.update(id, |c| c.clone())?;
+            Ok(ContainerInspectResponse {
+                id: Some(c.id.clone()),
+                name: Some(format!("/{}", c.name)),
+                config: Some(ContainerConfig {
+                    image: Some(c.image.clone()),
+                    labels: Some(c.labels.clone()),
+                    ..Default::default()
+                }),
+                state: Some(ContainerState {
+                    status: Some(match c.state() {
+                        "paused" => ContainerStateStatusEnum::PAUSED,
+                        "running" => ContainerStateStatusEnum::RUNNING,
+                        _ => ContainerStateStatusEnum::EXITED,
+                    }),
+                    running: Some(c.running),
+                    paused: Some(c.paused),
+                    ..Default::default()
+                }),
+                ..Default::default()
+            })
+        }
+
+        async fn create_container(&self, name: Option<&str>, config: Config<String>) -> Result<ContainerCreateResponse, Error> {
+            let image = config.image.clone().unwrap_or_default();
+            self.record(format!("create {}", image));
+            if !self.images.lock().unwrap().contains(&image) {
+                return Err(Error::DockerResponseServerError {
+                    status_code: 404,
+                    message: format!("No such image: {}", image),
+                });
+            }
+            let network = config.host_config.as_ref().and_then(|h| h.network_mode.clone());
+            if let Some(network) = network.filter(|n| !["bridge", "host", "none"].contains(&n.as_str())) {
+                return Err(Error::DockerResponseServerError {
+                    status_code: 404,
+                    message: format!("network {} not found", network),
+                });
+            }
+            let mut containers = self.containers.lock().unwrap();
+            let id = format!("{:012x}", containers.len() + 1);
+            let name = name.map_or_else(|| format!("fake_{}", id), str::to_string);
+            if containers.values().any(|c| c.name == name) {
+                return Err(Error::DockerResponseServerError {
+                    status_code: 409,
+                    message: format!("Conflict. The container name \"/{}\" is already in use", name),
+                });
+            }
+            let container = FakeContainer {
+                id: id.clone(),
+                name,
+                image,
+                labels: config.labels.clone().unwrap_or_default(),
+                running: false,
+                paused: false,
+                signals: Vec::new(),
+                config: Some(config),
+            };
+            containers.insert(id.clone(), container);
+            Ok(ContainerCreateResponse { id, warnings: Vec::new() })
+        }
+
+        async fn pull_image(&self, image: &str) -> Result<(), Error> {
+            self.record(format!("pull {}", image));
+            if !self.registry.lock().unwrap().contains(image) {
+                return Err(Error::DockerResponseServerError {
+                    status_code: 404,
+                    message: format!("pull access denied for {}, repository does not exist", image),
+                });
+            }
+            self.images.lock().unwrap().insert(image.to_string());
+            Ok(())
+        }
+
+        async fn start_container(&self, id: &str) -> Result<(), Error> {
+            self.record(format!("start {}", id));
+            self.update(id, |c| c.running = true)
+        }
+
+        async fn stop_container(&self, id: &str, timeout: Option<i64>) -> Result<(), Error> {
+            self.record(with_timeout(format!("stop {}", id), timeout));
+            self.update(id, |c| {
+                c.running = false;
+                c.paused = false;
+            })
+        }
+
+        async fn restart_container(&self, id: &str, timeout: Option<i64>) -> Result<(), Error> {
+            self.record(with_timeout(format!("restart {}", id), timeout));
+            self.update(id, |c| {
+                c.running = true;
+                c.paused = false;
+            })
+        }
+
+        async fn pause_container(&self, id: &str) -> Result<(), Error> {
+            self.record(format!("pause {}", id));
+            self.update(id, |c| match (c.running, c.paused) {
+                (false, _) => Err(conflict(format!("Container {} is not running", c.id))),
+                (true, true) => Err(conflict(format!("Container {} is already paused", c.id))),
+                (true, false) => {
+                    c.paused = true;
+                    Ok(())
+                }
+            })?
+        }
+
+        async fn unpause_container(&self, id: &str) -> Result<(), Error> {
+            self.record(format!("unpause {}", id));
+            self.update(id, |c| {
+                if !c.paused {
+                    return Err(conflict(format!("Container {} is not paused", c.id)));
+                }
+                c.paused = false;
+                Ok(())
+            })?
+        }
+
+        async fn kill_container(&self, id: &str, signal: &str) -> Result<(), Error> {
+            self.record(format!("kill {} {}", id, signal));
+            self.update(id, |c| {
+                if !c.running {
+                    return Err(conflict(format!("Container {} is not running", c.id)));
+                }
+                c.signals.push(signal.to_string());
+                // Signals like SIGHUP are handled by the process, which keeps running.
+                if ["SIGKILL", "SIGTERM", "SIGINT", "SIGQUIT"].contains(&signal) {
+                    c.running = false;
+                    c.paused = false;
+                }
+                Ok(())
+            })?
+        }
+
+        async fn remove_container(&self, id: &str, force: bool, volumes: bool) -> Result<(), Error> {
+            self.record(format!("remove {} force={} v={}", id, force, volumes));
+            let mut containers = self.containers.lock().unwrap();
+            let container = containers
+                .values()
+                .find(|c| c.id == id || c.name == id.trim_start_matches('/'))
+                .ok_or_else(|| not_found(id))?;
+            if container.running && !force {
+                return Err(Error::DockerResponseServerError {
+                    status_code: 409,
+                    message: format!(
+                        "cannot remove container \"/{}\": container is running: stop the container before removing or force remove",
+                        container.name
+                    ),
+                });
+            }
+            let id = container.id.clone();
+            containers.remove(&id);
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,45 @@
+use actix_web::{web, HttpResponse, Responder};
+use serde::Serialize;
+use crate::db::Store;
+use crate::hosts::{HostReadiness, Hosts};
+
+#[derive(Debug, Serialize)]
+struct Health {
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+/// Liveness: the process is serving and its database answers. Docker is not
+/// consulted, so an outage there does not get the dashboard restarted.
+pub async fn healthz(store: web::Data<Store>) -> impl Responder {
+    match store.conn().query_row("SELECT 1", [], |_| Ok(())) {
+        Ok(()) => HttpResponse::Ok().json(Health { status: "ok", error: None }),
+        Err(e) => HttpResponse::ServiceUnavailable().json(Health {
+            status: "error",
+            error: Some(e.to_string()),
+        }),
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Readiness {
+    status: &'static str,
+    hosts: Vec<HostReadiness>,
+}
+
+/// Readiness: the default Docker host (`DOCKER_HOST`) is reachable. Every
+/// host's state is included so other outages are visible too, but not its
+/// endpoint, credentials or errors, since this route needs no sign-in.
+pub async fn readyz(hosts: web::Data<Hosts>) -> impl Responder {
+    let ready = hosts.default_host().is_up();
+    let body = Readiness {
+        status: if ready { "ready" } else { "unavailable" },
+        hosts: hosts.readiness(),
+    };
+    if ready {
+        HttpResponse::Ok().json(body)
+    } else {
+        HttpResponse::ServiceUnavailable().json(body)
+    }
+}
@@ -0,0 +1,852 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::future::{ready, Ready};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use actix_web::dev::Payload;
+use actix_web::{error, http::header, web, FromRequest, HttpRequest, HttpResponse, Responder};
+use bollard::{ClientVersion, Docker, API_DEFAULT_VERSION};
+use rusqlite::{params, Row};
+use serde::{Deserialize, Serialize};
+use crate::db::{self, Store};
+use crate::engine::ContainerEngine;
+use crate::rbac::Role;
+use crate::ssh::{SshTarget, SshTunnel};
+use crate::tls;
+use crate::Identity;
+
+/// How long a health check waits for the engine to answer.
+const STATUS_TIMEOUT: Duration = Duration::from_secs(3);
+/// Delay between checks of a host that is up.
+const CHECK_INTERVAL: Duration = Duration::from_secs(15);
+/// Reconnect backoff bounds for a host that is down.
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// Oldest engine API the dashboard's requests work with.
+const MIN_API_VERSION: ClientVersion = ClientVersion { major_version: 1, minor_version: 24 };
+
+/// Where and how to reach one Docker engine.
+#[derive(Debug, Clone, Serialize)]
+pub struct HostConfig {
+    pub name: String,
+    /// `unix:///path`, `tcp://host:port`, `http(s)://host:port` or
+    /// `ssh://user@host[:port][/path/to/docker.sock]`.
+    pub endpoint: String,
+    /// CA, client certificate and key for TLS; all three or none.
+    pub tls_ca_path: Option<String>,
+    pub tls_cert_path: Option<String>,
+    pub tls_key_path: Option<String>,
+    /// Private key and known_hosts file for `ssh://` endpoints.
+    pub ssh_key_path: Option<String>,
+    pub ssh_known_hosts_path: Option<String>,
+}
+
+impl HostConfig {
+    fn from_row(row: &Row) -> rusqlite::Result<HostConfig> {
+        Ok(HostConfig {
+            name: row.get("name")?,
+            endpoint: row.get("endpoint")?,
+            tls_ca_path: row.get("tls_ca_path")?,
+            tls_cert_path: row.get("tls_cert_path")?,
+            tls_key_path: row.get("tls_key_path")?,
+            ssh_key_path: row.get("ssh_key_path")?,
+            ssh_known_hosts_path: row.get("ssh_known_hosts_path")?,
+        })
+    }
+
+    /// The host given by `DOCKER_HOST`, named by `DOCKER_HOST_NAME`. As with
+    /// the Docker CLI, a non-empty `DOCKER_TLS_VERIFY` enables TLS with the
+    /// `ca.pem`, `cert.pem` and `key.pem` found in `DOCKER_CERT_PATH`
+    /// (default `~/.docker`). `ssh://` endpoints use `DOCKER_SSH_KEY` and
+    /// `DOCKER_SSH_KNOWN_HOSTS`.
+    fn from_env() -> HostConfig {
+        let tls_verify = env::var("DOCKER_TLS_VERIFY").is_ok_and(|v| !v.is_empty() && v != "0");
+        let cert_dir = env::var("DOCKER_CERT_PATH")
+            .map(PathBuf::from)
+            .unwrap_or_else(|_| PathBuf::from(env::var("HOME").unwrap_or_default()).join(".docker"));
+        let cert_file = |file: &str| tls_verify.then(|| cert_dir.join(file).to_string_lossy().into_owned());
+        HostConfig {
+            name: env::var("DOCKER_HOST_NAME").unwrap_or_else(|_| "local".to_string()),
+            endpoint: env::var("DOCKER_HOST").unwrap_or_else(|_| default_socket()),
+            tls_ca_path: cert_file("ca.pem"),
+            tls_cert_path: cert_file("cert.pem"),
+            tls_key_path: cert_file("key.pem"),
+            ssh_key_path: env::var("DOCKER_SSH_KEY").ok().filter(|v| !v.is_empty()),
+            ssh_known_hosts_path: env::var("DOCKER_SSH_KNOWN_HOSTS").ok().filter(|v| !v.is_empty()),
+        }
+    }
+
+    fn ssh_target(&self) -> Option<Result<SshTarget, String>> {
+        self.endpoint.starts_with("ssh://").then(|| {
+            SshTarget::parse(&self.endpoint, self.ssh_key_path.clone(), self.ssh_known_hosts_path.clone())
+        })
+    }
+
+    fn uses_tls(&self) -> bool {
+        self.tls_ca_path.is_some()
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        let valid_name = !self.name.is_empty()
+            && self.name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_' || c == '.');
+        if !valid_name {
+            return Err("Host names may only contain letters, digits, '-', '_' and '.'".to_string());
+        }
+        if !["unix://", "tcp://", "http://", "https://", "ssh://"].iter().any(|scheme| self.endpoint.starts_with(scheme)) {
+            return Err(format!("Unsupported Docker endpoint '{}'", self.endpoint));
+        }
+        let tls = [&self.tls_ca_path, &self.tls_cert_path, &self.tls_key_path];
+        if tls.iter().any(|path| path.is_some()) && !tls.iter().all(|path| path.is_some()) {
+            return Err("tls_ca_path, tls_cert_path and tls_key_path must be set together".to_string());
+        }
+        if self.uses_tls() && !(self.endpoint.starts_with("tcp://") || self.endpoint.starts_with("https://")) {
+            return Err(format!("TLS needs a tcp:// or https:// endpoint, not '{}'", self.endpoint));
+        }
+        if let Some(target) = self.ssh_target() {
+            target?;
+        } else if self.ssh_key_path.is_some() || self.ssh_known_hosts_path.is_some() {
+            return Err("ssh_key_path and ssh_known_hosts_path only apply to ssh:// endpoints".to_string());
+        }
+        Ok(())
+    }
+
+    /// Builds the client, first opening the SSH tunnel for `ssh://`
+    /// endpoints. Blocks while ssh connects.
+    fn connect(&self) -> Result<(Docker, Option<SshTunnel>), String> {
+        if let Some(target) = self.ssh_target() {
+            let tunnel = target?.open(&self.name)?;
+            let socket = tunnel.socket.to_string_lossy().into_owned();
+            let docker = Docker::connect_with_socket(&socket, 120, API_DEFAULT_VERSION).map_err(|e| describe(&e))?;
+            return Ok((docker, Some(tunnel)));
+        }
+        let docker = if let Some(path) = self.endpoint.strip_prefix("unix://") {
+            Docker::connect_with_socket(path, 120, API_DEFAULT_VERSION)
+        } else if let (Some(ca), Some(cert), Some(key)) = (&self.tls_ca_path, &self.tls_cert_path, &self.tls_key_path) {
+            // bollard's own messages do not say which file is wrong.
+            tls::read_certs(ca).map_err(|e| format!("TLS CA certificate: {}", e))?;
+            tls::read_certs(cert).map_err(|e| format!("TLS client certificate: {}", e))?;
+            tls::read_private_key(key).map_err(|e| format!("TLS client key: {}", e))?;
+            Docker::connect_with_ssl(&self.endpoint, Path::new(key), Path::new(cert), Path::new(ca), 120, API_DEFAULT_VERSION)
+        } else {
+            Docker::connect_with_http(&self.endpoint, 5, API_DEFAULT_VERSION)
+        };
+        docker.map(|docker| (docker, None)).map_err(|e| describe(&e))
+    }
+}
+
+/// The first local engine socket that exists: Docker's, then rootless and
+/// rootful Podman's. Falls back to Docker's so the error names it.
+fn default_socket() -> String {
+    let mut candidates = vec![PathBuf::from("/var/run/docker.sock")];
+    if let Ok(runtime_dir) = env::var("XDG_RUNTIME_DIR") {
+        candidates.push(PathBuf::from(runtime_dir).join("podman/podman.sock"));
+    }
+    candidates.push(PathBuf::from("/run/podman/podman.sock"));
+    let socket = candidates.iter().find(|path| path.exists()).unwrap_or(&candidates[0]);
+    format!("unix://{}", socket.display())
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EngineKind {
+    Docker,
+    Podman,
+}
+
+/// What answers on the other end, as found at connect time.
+#[derive(Debug, Clone, Serialize)]
+pub struct EngineInfo {
+    pub kind: EngineKind,
+    pub version: Option<String>,
+    /// API version negotiated with the engine, which requests are sent with.
+    pub api_version: String,
+}
+
+impl EngineInfo {
+    fn new(version: bollard::system::Version, api_version: ClientVersion) -> EngineInfo {
+        // Podman's Docker-compatible API names its component "Podman Engine".
+        let podman = version
+            .components
+            .iter()
+            .flatten()
+            .any(|component| component.name.to_lowercase().contains("podman"));
+        EngineInfo {
+            kind: if podman { EngineKind::Podman } else { EngineKind::Docker },
+            version: version.version,
+            api_version: api_version.to_string(),
+        }
+    }
+}
+
+/// Formats a Docker client error with its causes, which carry the useful part
+/// of connection failures (e.g. "invalid peer certificate: UnknownIssuer").
+pub fn describe(e: &bollard::errors::Error) -> String {
+    let mut message = e.to_string();
+    let mut source = std::error::Error::source(e);
+    while let Some(cause) = source {
+        let cause_message = cause.to_string();
+        if !message.contains(&cause_message) {
+            message = format!("{}: {}", message, cause_message);
+        }
+        source = cause.source();
+    }
+    message
+}
+
+/// Whether `e` means the engine could not be reached at all, as opposed to
+/// an error reported by the engine.
+pub fn is_unreachable(e: &bollard::errors::Error) -> bool {
+    use bollard::errors::Error::*;
+    matches!(
+        e,
+        HyperLegacyError { .. } | IOError { .. } | RequestTimeoutError | SocketNotFoundError(_) | HyperResponseError { .. }
+    )
+}
+
+impl Store {
+    pub fn list_hosts(&self) -> rusqlite::Result<Vec<HostConfig>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT * FROM docker_hosts ORDER BY name")?;
+        let hosts = stmt.query_map([], HostConfig::from_row)?.collect();
+        hosts
+    }
+
+    pub fn create_host(&self, host: &HostConfig) -> rusqlite::Result<()> {
+        self.conn().execute(
+            "INSERT INTO docker_hosts (name, endpoint, tls_ca_path, tls_cert_path, tls_key_path, ssh_key_path, ssh_known_hosts_path, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                host.name,
+                host.endpoint,
+                host.tls_ca_path,
+                host.tls_cert_path,
+                host.tls_key_path,
+                host.ssh_key_path,
+                host.ssh_known_hosts_path,
+                db::now(),
+            ],
+        )?;
+        Ok(())
+    }
+
+    /// Returns false if no such host exists.
+    pub fn delete_host(&self, name: &str) -> rusqlite::Result<bool> {
+        let n = self.conn().execute("DELETE FROM docker_hosts WHERE name = ?1", params![name])?;
+        Ok(n > 0)
+    }
+}
+
+/// A live client. For `ssh://` hosts it owns the tunnel the client talks
+/// through, so dropping it closes the tunnel.
+struct Client {
+    engine: Arc<dyn ContainerEngine>,
+    _tunnel: Option<SshTunnel>,
+}
+
+/// What the last check of a host found.
+struct Connection {
+    /// Dropped when a check fails, so the next one reconnects from scratch.
+    client: Option<Arc<Client>>,
+    up: bool,
+    engine: Option<EngineInfo>,
+    error: Option<String>,
+    checked_at: Option<i64>,
+    /// When a host that is down is tried again.
+    retry_at: Option<i64>,
+}
+
+/// A registered host and the state of its connection, which `watch` keeps
+/// current. Requests to a host that is down fail fast with a 503.
+pub struct DockerHost {
+    pub config: HostConfig,
+    connection: RwLock<Connection>,
+    /// Reconnected to in place of `config` by hosts made with `Hosts::fixed`.
+    #[cfg(test)]
+    fake: Option<Arc<dyn ContainerEngine>>,
+}
+
+#[derive(Debug, Serialize)]
+struct Unavailable<'a> {
+    error: &'static str,
+    message: String,
+    host: &'a str,
+    retry_after: Option<i64>,
+}
+
+impl DockerHost {
+    fn new(config: HostConfig, client: Option<Client>) -> DockerHost {
+        DockerHost {
+            config,
+            connection: RwLock::new(Connection {
+                client: client.map(Arc::new),
+                up: false,
+                engine: None,
+                error: Some("Not checked yet".to_string()),
+                checked_at: None,
+                retry_at: None,
+            }),
+            #[cfg(test)]
+            fake: None,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.config.name
+    }
+
+    pub fn is_up(&self) -> bool {
+        self.connection.read().unwrap_or_else(|e| e.into_inner()).up
+    }
+
+    pub fn engine(&self) -> Result<Arc<dyn ContainerEngine>, actix_web::Error> {
+        let connection = self.connection.read().unwrap_or_else(|e| e.into_inner());
+        match &connection.client {
+            Some(client) if connection.up => Ok(client.engine.clone()),
+            _ => {
+                let message = format!(
+                    "Docker host {} is unavailable: {}",
+                    self.config.name,
+                    connection.error.as_deref().unwrap_or("not connected")
+                );
+                let retry_after = connection.retry_at.map(|at| (at - db::now()).max(1));
+                let mut response = HttpResponse::ServiceUnavailable();
+                if let Some(seconds) = retry_after {
+                    response.insert_header((header::RETRY_AFTER, seconds.to_string()));
+                }
+                let response = response.json(Unavailable {
+                    error: "docker_unavailable",
+                    message: message.clone(),
+                    host: &self.config.name,
+                    retry_after,
+                });
+                Err(error::InternalError::from_response(message, response).into())
+            }
+        }
+    }
+
+    /// Connects if needed, settling on an API version both sides speak, and
+    /// asks the engine what it is.
+    async fn probe(&self) -> Result<(Arc<Client>, EngineInfo), String> {
+        let timed_out = || format!("no answer within {} seconds", STATUS_TIMEOUT.as_secs());
+        let (client, up) = {
+            let connection = self.connection.read().unwrap_or_else(|e| e.into_inner());
+            (connection.client.clone(), connection.up)
+        };
+        let client = match client {
+            Some(client) => client,
+            None => self.connect().await?,
+        };
+        // Negotiate on every (re)connect, since the engine may have been
+        // replaced.
+        if !up {
+            match actix_web::rt::time::timeout(STATUS_TIMEOUT, client.engine.negotiate_version()).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(bollard::errors::Error::APIVersionParseError {})) => {
+                    log::warn!("Docker host {} does not report its API version, using {}", self.config.name, API_DEFAULT_VERSION);
+                }
+                Ok(Err(e)) => return Err(describe(&e)),
+                Err(_) => return Err(timed_out()),
+            }
+            if client.engine.api_version() < MIN_API_VERSION {
+                return Err(format!(
+                    "engine API {} is older than the oldest supported, {}",
+                    client.engine.api_version(),
+                    MIN_API_VERSION
+                ));
+            }
+        }
+        match actix_web::rt::time::timeout(STATUS_TIMEOUT, client.engine.version()).await {
+            Ok(Ok(version)) => {
+                let engine = EngineInfo::new(version, client.engine.api_version());
+                Ok((client, engine))
+            }
+            Ok(Err(e)) => Err(describe(&e)),
+            Err(_) => Err(timed_out()),
+        }
+    }
+
+    async fn connect(&self) -> Result<Arc<Client>, String> {
+        #[cfg(test)]
+        if let Some(engine) = &self.fake {
+            return Ok(Arc::new(Client { engine: engine.clone(), _tunnel: None }));
+        }
+        let config = self.config.clone();
+        let (docker, tunnel) = web::block(move || config.connect()).await.map_err(|e| e.to_string())??;
+        Ok(Arc::new(Client { engine: Arc::new(docker), _tunnel: tunnel }))
+    }
+
+    /// The engine found by the last successful check.
+    pub fn engine_info(&self) -> Option<EngineInfo> {
+        self.connection.read().unwrap_or_else(|e| e.into_inner()).engine.clone()
+    }
+
+    /// Checks the host and records the outcome. Returns how long to wait
+    /// before the next check, backing off while the host stays down.
+    async fn refresh(&self, backoff: &mut Duration) -> Duration {
+        let result = self.probe().await;
+        let mut connection = self.connection.write().unwrap_or_else(|e| e.into_inner());
+        let was_up = connection.up;
+        connection.checked_at = Some(db::now());
+        match result {
+            Ok((client, engine)) => {
+                if !was_up {
+                    log::info!(
+                        "connected to Docker host {} ({}): {:?} {} over API {}",
+                        self.config.name,
+                        self.config.endpoint,
+                        engine.kind,
+                        engine.version.as_deref().unwrap_or("unknown"),
+                        engine.api_version
+                    );
+                }
+                *connection = Connection {
+                    client: Some(client),
+                    up: true,
+                    engine: Some(engine),
+                    error: None,
+                    checked_at: connection.checked_at,
+                    retry_at: None,
+                };
+                *backoff = MIN_BACKOFF;
+                CHECK_INTERVAL
+            }
+            Err(e) => {
+                let delay = *backoff;
+                if was_up || connection.error.as_deref() != Some(e.as_str()) {
+                    log::warn!("Docker host {} is down, retrying in {}s: {}", self.config.name, delay.as_secs(), e);
+                }
+                connection.client = None;
+                connection.up = false;
+                connection.error = Some(e);
+                connection.retry_at = Some(db::now() + delay.as_secs() as i64);
+                *backoff = (*backoff * 2).min(MAX_BACKOFF);
+                delay
+            }
+        }
+    }
+
+    fn status(&self, default: bool) -> HostStatus {
+        let connection = self.connection.read().unwrap_or_else(|e| e.into_inner());
+        HostStatus {
+            config: self.config.clone(),
+            default,
+            status: if connection.up { "up" } else { "down" },
+            engine: connection.engine.clone(),
+            error: connection.error.clone(),
+            checked_at: connection.checked_at,
+            retry_at: connection.retry_at,
+        }
+    }
+}
+
+/// Checks `host` in the background for as long as it is registered.
+fn watch(host: &Arc<DockerHost>) {
+    let host = Arc::downgrade(host);
+    actix_web::rt::spawn(async move {
+        let mut backoff = MIN_BACKOFF;
+        while let Some(current) = host.upgrade() {
+            let delay = current.refresh(&mut backoff).await;
+            drop(current);
+            actix_web::rt::time::sleep(delay).await;
+        }
+    });
+}
+
+/// Every Docker host the dashboard manages: the one from `DOCKER_HOST`, which
+/// the unprefixed routes use, plus those registered through `POST /hosts`.
+pub struct Hosts {
+    default: Arc<DockerHost>,
+    registered: RwLock<BTreeMap<String, Arc<DockerHost>>>,
+}
+
+impl Hosts {
+    pub fn load(store: &Store) -> Result<Hosts, String> {
+        let default = HostConfig::from_env();
+        default.validate().map_err(|e| format!("DOCKER_HOST: {}", e))?;
+        let registered = store
+            .list_hosts()
+            .map_err(|e| e.to_string())?
+            .into_iter()
+            .map(|config| (config.name.clone(), Arc::new(DockerHost::new(config, None))))
+            .collect();
+        let hosts = Hosts {
+            default: Arc::new(DockerHost::new(default, None)),
+            registered: RwLock::new(registered),
+        };
+        // Connect in the background so a missing daemon does not hold up
+        // or fail startup.
+        hosts.all().iter().for_each(watch);
+        Ok(hosts)
+    }
+
+    pub fn default_host(&self) -> &DockerHost {
+        &self.default
+    }
+
+    pub fn statuses(&self) -> Vec<HostStatus> {
+        self.all()
+            .iter()
+            .map(|host| host.status(Arc::ptr_eq(host, &self.default)))
+            .collect()
+    }
+
+    /// Whether each host is up, without its settings or errors, for callers
+    /// that have not signed in.
+    pub fn readiness(&self) -> Vec<HostReadiness> {
+        self.all()
+            .iter()
+            .map(|host| HostReadiness {
+                name: host.name().to_string(),
+                default: Arc::ptr_eq(host, &self.default),
+                status: if host.is_up() { "up" } else { "down" },
+            })
+            .collect()
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<DockerHost>> {
+        if name == self.default.name() {
+            return Some(self.default.clone());
+        }
+        self.registered.read().unwrap_or_else(|e| e.into_inner()).get(name).cloned()
+    }
+
+    fn all(&self) -> Vec<Arc<DockerHost>> {
+        let registered = self.registered.read().unwrap_or_else(|e| e.into_inner());
+        std::iter::once(self.default.clone()).chain(registered.values().cloned()).collect()
+    }
+}
+
+#[cfg(test)]
+impl Hosts {
+    /// Hosts backed by the given engines, the first being the default. They
+    /// are checked once and not watched.
+    pub async fn fixed(engines: Vec<(&str, Arc<dyn ContainerEngine>)>) -> Hosts {
+        let mut hosts = Vec::new();
+        for (name, engine) in engines {
+            let config = HostConfig {
+                name: name.to_string(),
+                endpoint: format!("unix:///run/{}.sock", name),
+                tls_ca_path: None,
+                tls_cert_path: None,
+                tls_key_path: None,
+                ssh_key_path: None,
+                ssh_known_hosts_path: None,
+            };
+            let mut host = DockerHost::new(config, None);
+            host.fake = Some(engine);
+            host.refresh(&mut MIN_BACKOFF.clone()).await;
+            hosts.push(Arc::new(host));
+        }
+        let default = hosts.remove(0);
+        Hosts {
+            default,
+            registered: RwLock::new(hosts.into_iter().map(|host| (host.name().to_string(), host)).collect()),
+        }
+    }
+}
+
+#[cfg(test)]
+impl DockerHost {
+    /// One check as `watch` makes it, for tests that drive the backoff.
+    pub async fn check(&self, backoff: &mut Duration) -> Duration {
+        self.refresh(backoff).await
+    }
+}
+
+/// The Docker host a request targets: the `{host}` path segment, or the
+/// default host on routes without one.
+pub struct Host(pub Arc<DockerHost>);
+
+impl std::ops::Deref for Host {
+    type Target = DockerHost;
+
+    fn deref(&self) -> &DockerHost {
+        &self.0
+    }
+}
+
+impl FromRequest for Host {
+    type Error = actix_web::Error;
+    type Future = Ready<Result<Host, actix_web::Error>>;
+
+    fn from_request(req: &HttpRequest, _payload: &mut Payload) -> Self::Future {
+        let hosts = req.app_data::<web::Data<Hosts>>().expect("Hosts is not configured");
+        let host = match req.match_info().get("host") {
+            Some(name) => hosts.get(name).ok_or_else(|| error::ErrorNotFound(format!("Docker host {} not found", name))),
+            None => Ok(hosts.default.clone()),
+        };
+        ready(host.map(Host))
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct HostStatus {
+    #[serde(flatten)]
+    config: HostConfig,
+    default: bool,
+    /// `up` or `down`.
+    status: &'static str,
+    engine: Option<EngineInfo>,
+    error: Option<String>,
+    checked_at: Option<i64>,
+    retry_at: Option<i64>,
+}
+
+/// The part of `HostStatus` non-admins see: no endpoint, credential paths
+/// or connection errors, which tend to quote the endpoint.
+#[derive(Debug, Serialize)]
+pub struct HostSummary {
+    name: String,
+    default: bool,
+    /// `up` or `down`.
+    status: &'static str,
+    engine: Option<EngineInfo>,
+    retry_at: Option<i64>,
+}
+
+impl HostStatus {
+    fn summary(self) -> HostSummary {
+        HostSummary {
+            name: self.config.name,
+            default: self.default,
+            status: self.status,
+            engine: self.engine,
+            retry_at: self.retry_at,
+        }
+    }
+}
+
+/// The part of `HostStatus` that `/readyz` may show to anyone.
+#[derive(Debug, Serialize)]
+pub struct HostReadiness {
+    name: String,
+    default: bool,
+    /// `up` or `down`.
+    status: &'static str,
+}
+
+#[derive(Debug, Serialize)]
+struct HostsResponse<T> {
+    message: String,
+    hosts: Vec<T>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewHost {
+    name: String,
+    endpoint: String,
+    tls_ca_path: Option<String>,
+    tls_cert_path: Option<String>,
+    tls_key_path: Option<String>,
+    ssh_key_path: Option<String>,
+    ssh_known_hosts_path: Option<String>,
+}
+
+/// Lists every host with the result of its last check; its settings and
+/// errors only for admins.
+pub async fn list_hosts(hosts: web::Data<Hosts>, identity: web::ReqData<Identity>) -> HttpResponse {
+    let message = "Docker Hosts".to_string();
+    if identity.role >= Role::Admin {
+        HttpResponse::Ok().json(HostsResponse { message, hosts: hosts.statuses() })
+    } else {
+        let hosts = hosts.statuses().into_iter().map(HostStatus::summary).collect();
+        HttpResponse::Ok().json(HostsResponse::<HostSummary> { message, hosts })
+    }
+}
+
+pub async fn create_host(store: web::Data<Store>, hosts: web::Data<Hosts>, body: web::Json<NewHost>) -> Result<impl Responder, actix_web::Error> {
+    let NewHost { name, endpoint, tls_ca_path, tls_cert_path, tls_key_path, ssh_key_path, ssh_known_hosts_path } = body.into_inner();
+    let non_empty = |value: Option<String>| value.map(|v| v.trim().to_string()).filter(|v| !v.is_empty());
+    let config = HostConfig {
+        name: name.trim().to_string(),
+        endpoint: endpoint.trim().to_string(),
+        tls_ca_path: non_empty(tls_ca_path),
+        tls_cert_path: non_empty(tls_cert_path),
+        tls_key_path: non_empty(tls_key_path),
+        ssh_key_path: non_empty(ssh_key_path),
+        ssh_known_hosts_path: non_empty(ssh_known_hosts_path),
+    };
+    config.validate().map_err(error::ErrorBadRequest)?;
+    if hosts.get(&config.name).is_some() {
+        return Err(error::ErrorConflict(format!("Docker host {} already exists", config.name)));
+    }
+    // Refuse settings that cannot work, such as missing certificate files.
+    let (docker, tunnel) = web::block({
+        let config = config.clone();
+        move || config.connect()
+    })
+    .await?
+    .map_err(|e| error::ErrorBadRequest(format!("Cannot set up Docker host: {}", e)))?;
+    // The check above can race with another request for the same name.
+    store.create_host(&config).map_err(|e| {
+        if db::is_constraint_violation(&e) {
+            error::ErrorConflict(format!("Docker host {} already exists", config.name))
+        } else {
+            error::ErrorInternalServerError(e)
+        }
+    })?;
+    let host = Arc::new(DockerHost::new(config, Some(Client { engine: Arc::new(docker), _tunnel: tunnel })));
+    host.refresh(&mut MIN_BACKOFF.clone()).await;
+    watch(&host);
+    hosts
+        .registered
+        .write()
+        .unwrap_or_else(|e| e.into_inner())
+        .insert(host.name().to_string(), host.clone());
+    Ok(HttpResponse::Created().json(host.status(false)))
+}
+
+pub async fn delete_host(store: web::Data<Store>, hosts: web::Data<Hosts>, name: web::Path<String>) -> Result<impl Responder, actix_web::Error> {
+    if *name == hosts.default.name() {
+        return Err(error::ErrorBadRequest("The default Docker host is configured by DOCKER_HOST and cannot be removed"));
+    }
+    if !store.delete_host(&name).map_err(error::ErrorInternalServerError)? {
+        return Err(error::ErrorNotFound(format!("Docker host {} not found", name)));
+    }
+    hosts.registered.write().unwrap_or_else(|e| e.into_inner()).remove(name.as_str());
+    Ok(web::Json(crate::ApiResponse {
+        message: format!("Docker host {} removed", name),
+        docker_info: None,
+        containers: None,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::net::TcpStream;
+    use std::process::{Command, Stdio};
+    use actix_web::{App, HttpServer};
+    use crate::engine::fake::FakeEngine;
+    use super::*;
+
+    async fn single(engine: FakeEngine) -> Arc<DockerHost> {
+        Hosts::fixed(vec![("fake", Arc::new(engine) as Arc<dyn ContainerEngine>)]).await.default
+    }
+
+    #[actix_web::test]
+    async fn engines_older_than_the_minimum_api_are_refused() {
+        let host = single(FakeEngine::default().api_version(1, 23)).await;
+        let status = host.status(true);
+        assert_eq!(status.status, "down");
+        assert_eq!(status.error.as_deref(), Some("engine API 1.23 is older than the oldest supported, 1.24"));
+        let response = host.engine().err().unwrap().error_response();
+        assert_eq!(response.status(), actix_web::http::StatusCode::SERVICE_UNAVAILABLE);
+        let body = actix_web::body::to_bytes(response.into_body()).await.unwrap();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["message"], "Docker host fake is unavailable: engine API 1.23 is older than the oldest supported, 1.24");
+
+        let host = single(FakeEngine::default().api_version(1, 24)).await;
+        assert!(host.is_up());
+        assert_eq!(host.engine_info().unwrap().api_version, "1.24");
+    }
+
+    #[actix_web::test]
+    async fn engines_without_a_readable_api_version_get_the_default() {
+        let host = single(FakeEngine::default().unversioned()).await;
+        assert!(host.is_up(), "{:?}", host.status(true).error);
+        assert_eq!(host.engine_info().unwrap().api_version, API_DEFAULT_VERSION.to_string());
+    }
+
+    #[actix_web::test]
+    async fn podman_is_told_apart_by_its_components() {
+        let host = single(FakeEngine::default().component("Podman Engine")).await;
+        assert_eq!(host.engine_info().unwrap().kind, EngineKind::Podman);
+        let host = single(FakeEngine::default()).await;
+        assert_eq!(host.engine_info().unwrap().kind, EngineKind::Docker);
+    }
+
+    fn keygen(path: &Path) -> String {
+        let status = Command::new("ssh-keygen")
+            .args(["-q", "-t", "ed25519", "-N", "", "-f"])
+            .arg(path)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        fs::read_to_string(path.with_extension("pub")).unwrap()
+    }
+
+    fn ssh_host(endpoint: String, key: &Path, known_hosts: &Path) -> HostConfig {
+        HostConfig {
+            name: "ssh-it".to_string(),
+            endpoint,
+            tls_ca_path: None,
+            tls_cert_path: None,
+            tls_key_path: None,
+            ssh_key_path: Some(key.display().to_string()),
+            ssh_known_hosts_path: Some(known_hosts.display().to_string()),
+        }
+    }
+
+    /// Talks to a stand-in Docker socket through a real `sshd`, with key
+    /// authentication and host key checking. Needs OpenSSH's server (at
+    /// `SSHD_PATH`, default `/usr/sbin/sshd`; as root, `/run/sshd` must
+    /// exist), so it only runs with `cargo test -- --ignored`.
+    #[actix_web::test]
+    #[ignore]
+    async fn connects_through_an_ssh_tunnel() {
+        let dir = tempfile::tempdir().unwrap();
+        let docker_socket = dir.path().join("docker.sock");
+        let docker = HttpServer::new(|| App::new().route("/_ping", web::get().to(|| async { "OK" })))
+            .workers(1)
+            .disable_signals()
+            .bind_uds(&docker_socket)
+            .unwrap()
+            .run();
+        let docker_handle = docker.handle();
+        actix_web::rt::spawn(docker);
+
+        let host_key = keygen(&dir.path().join("host_key"));
+        let client_key = keygen(&dir.path().join("client_key"));
+        let other_key = keygen(&dir.path().join("other_key"));
+        fs::write(dir.path().join("authorized_keys"), &client_key).unwrap();
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let known_host = |key: &str| format!("[127.0.0.1]:{} {}", port, key.split_whitespace().take(2).collect::<Vec<_>>().join(" "));
+        let known_hosts = dir.path().join("known_hosts");
+        fs::write(&known_hosts, known_host(&host_key)).unwrap();
+        let wrong_known_hosts = dir.path().join("wrong_known_hosts");
+        fs::write(&wrong_known_hosts, known_host(&other_key)).unwrap();
+        let sshd_config = dir.path().join("sshd_config");
+        fs::write(
+            &sshd_config,
+            format!(
+                "Port {port}\nListenAddress 127.0.0.1\nHostKey {dir}/host_key\nAuthorizedKeysFile {dir}/authorized_keys\n\
+                 PasswordAuthentication no\nKbdInteractiveAuthentication no\nUsePAM no\nStrictModes no\nPidFile none\n\
+                 AllowStreamLocalForwarding yes\n",
+                port = port,
+                dir = dir.path().display()
+            ),
+        )
+        .unwrap();
+        let mut sshd = Command::new(env::var("SSHD_PATH").unwrap_or_else(|_| "/usr/sbin/sshd".to_string()))
+            .args(["-D", "-e", "-f"])
+            .arg(&sshd_config)
+            .stderr(Stdio::null())
+            .spawn()
+            .expect("cannot start sshd");
+        let started = std::time::Instant::now();
+        while TcpStream::connect(("127.0.0.1", port)).is_err() {
+            assert!(started.elapsed() < Duration::from_secs(10), "sshd did not start");
+            std::thread::sleep(Duration::from_millis(100));
+        }
+
+        let user = String::from_utf8(Command::new("id").arg("-un").output().unwrap().stdout).unwrap();
+        let endpoint = format!("ssh://{}@127.0.0.1:{}{}", user.trim(), port, docker_socket.display());
+        let key = dir.path().join("client_key");
+        let config = ssh_host(endpoint.clone(), &key, &known_hosts);
+        let (docker, tunnel) = web::block(move || config.connect()).await.unwrap().unwrap();
+        assert!(tunnel.is_some());
+        assert_eq!(docker.ping().await.unwrap(), "OK");
+        drop((docker, tunnel));
+
+        // A host key other than the one on record is refused, not prompted for.
+        let config = ssh_host(endpoint, &key, &wrong_known_hosts);
+        let error = web::block(move || config.connect()).await.unwrap().err().unwrap();
+        assert!(error.contains("Host key verification failed"), "{}", error);
+
+        let _ = sshd.kill();
+        let _ = sshd.wait();
+        docker_handle.stop(false).await;
+    }
+}
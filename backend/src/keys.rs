@@ -0,0 +1,280 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use actix_web::{web, Responder};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{Algorithm, DecodingKey, EncodingKey};
+use ring::rsa::PublicKeyComponents;
+use ring::signature::{Ed25519KeyPair, KeyPair, RsaKeyPair};
+use serde_json::json;
+
+/// `kid` given to the key built from the legacy `JWT_SECRET` variable.
+/// Tokens without a `kid` header are checked against it.
+const LEGACY_KID: &str = "default";
+
+struct SigningKey {
+    algorithm: Algorithm,
+    encoding: EncodingKey,
+    decoding: DecodingKey,
+    /// Public JWK for asymmetric keys; shared secrets are never published.
+    jwk: Option<serde_json::Value>,
+}
+
+/// Every key the dashboard accepts tokens from, plus the one it signs with.
+///
+/// Keys come from `JWT_KEYS` (`kid:ALG:/path/to/key.pem,...`) or, without
+/// it, the legacy `JWT_SECRET`. `JWT_ACCEPT_LEGACY_SECRET=true` keeps the
+/// secret trusted next to `JWT_KEYS` while moving off it. `JWT_SIGNING_KEY`
+/// picks the signing `kid`; it defaults to the first `JWT_KEYS` entry. To
+/// rotate, add the new key, point `JWT_SIGNING_KEY` at it and drop the old
+/// entry once its tokens have expired.
+pub struct KeySet {
+    signing_kid: String,
+    keys: HashMap<String, SigningKey>,
+}
+
+fn load_key(kid: &str, algorithm: Algorithm, path: &str) -> Result<SigningKey, String> {
+    let contents = fs::read(path).map_err(|e| format!("cannot read key {} from {}: {}", kid, path, e))?;
+    let invalid = |e: &dyn std::fmt::Display| format!("invalid {:?} key {} in {}: {}", algorithm, kid, path, e);
+    match algorithm {
+        Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512 => {
+            let secret = String::from_utf8_lossy(&contents).trim().to_string();
+            if secret.is_empty() {
+                return Err(invalid(&"secret is empty"));
+            }
+            Ok(hmac_key(algorithm, &secret))
+        }
+        Algorithm::RS256 | Algorithm::RS384 | Algorithm::RS512 => {
+            let pem = pem::parse(&contents).map_err(|e| invalid(&e))?;
+            let key_pair = match pem.tag() {
+                "RSA PRIVATE KEY" => RsaKeyPair::from_der(pem.contents()),
+                _ => RsaKeyPair::from_pkcs8(pem.contents()),
+            }
+            .map_err(|e| invalid(&e))?;
+            let public = PublicKeyComponents::<Vec<u8>>::from(key_pair.public());
+            Ok(SigningKey {
+                algorithm,
+                encoding: EncodingKey::from_rsa_pem(&contents).map_err(|e| invalid(&e))?,
+                decoding: DecodingKey::from_rsa_raw_components(&public.n, &public.e),
+                jwk: Some(json!({
+                    "kty": "RSA",
+                    "use": "sig",
+                    "alg": format!("{:?}", algorithm),
+                    "kid": kid,
+                    "n": URL_SAFE_NO_PAD.encode(&public.n),
+                    "e": URL_SAFE_NO_PAD.encode(&public.e),
+                })),
+            })
+        }
+        Algorithm::EdDSA => {
+            let pem = pem::parse(&contents).map_err(|e| invalid(&e))?;
+            let key_pair = Ed25519KeyPair::from_pkcs8_maybe_unchecked(pem.contents()).map_err(|e| invalid(&e))?;
+            let x = URL_SAFE_NO_PAD.encode(key_pair.public_key().as_ref());
+            Ok(SigningKey {
+                algorithm,
+                encoding: EncodingKey::from_ed_pem(&contents).map_err(|e| invalid(&e))?,
+                decoding: DecodingKey::from_ed_components(&x).map_err(|e| invalid(&e))?,
+                jwk: Some(json!({
+                    "kty": "OKP",
+                    "crv": "Ed25519",
+                    "use": "sig",
+                    "alg": "EdDSA",
+                    "kid": kid,
+                    "x": x,
+                })),
+            })
+        }
+        other => Err(format!("key {}: algorithm {:?} is not supported", kid, other)),
+    }
+}
+
+fn hmac_key(algorithm: Algorithm, secret: &str) -> SigningKey {
+    SigningKey {
+        algorithm,
+        encoding: EncodingKey::from_secret(secret.as_bytes()),
+        decoding: DecodingKey::from_secret(secret.as_bytes()),
+        jwk: None,
+    }
+}
+
+impl KeySet {
+    /// Loads the configured keys, failing if none is usable for signing.
+    pub fn from_env() -> Result<KeySet, String> {
+        let mut keys = HashMap::new();
+        let mut first_kid = None;
+        for entry in env::var("JWT_KEYS").unwrap_or_default().split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let mut parts = entry.splitn(3, ':');
+            let (kid, algorithm, path) = match (parts.next(), parts.next(), parts.next()) {
+                (Some(kid), Some(algorithm), Some(path)) if !kid.is_empty() => (kid, algorithm, path),
+                _ => return Err(format!("JWT_KEYS entry '{}' must look like kid:ALG:/path/to/key", entry)),
+            };
+            let algorithm: Algorithm = algorithm
+                .parse()
+                .map_err(|_| format!("JWT_KEYS entry '{}' has an unknown algorithm", entry))?;
+            if keys.insert(kid.to_string(), load_key(kid, algorithm, path)?).is_some() {
+                return Err(format!("JWT_KEYS lists kid '{}' twice", kid));
+            }
+            first_kid.get_or_insert_with(|| kid.to_string());
+        }
+        let secret = env::var("JWT_SECRET").ok().filter(|secret| !secret.is_empty());
+        let accept_secret = env::var("JWT_ACCEPT_LEGACY_SECRET").map(|v| v == "true" || v == "1").unwrap_or(false);
+        match secret {
+            Some(secret) if keys.is_empty() || accept_secret => {
+                if !keys.is_empty() {
+                    log::warn!("JWT_SECRET is still trusted next to JWT_KEYS; unset JWT_ACCEPT_LEGACY_SECRET once its tokens have expired");
+                }
+                keys.entry(LEGACY_KID.to_string()).or_insert_with(|| hmac_key(Algorithm::HS256, &secret));
+                first_kid.get_or_insert_with(|| LEGACY_KID.to_string());
+            }
+            Some(_) => log::warn!("ignoring JWT_SECRET since JWT_KEYS is set"),
+            None => {}
+        }
+        let signing_kid = env::var("JWT_SIGNING_KEY")
+            .ok()
+            .filter(|kid| !kid.is_empty())
+            .or(first_kid)
+            .ok_or("no JWT signing key configured; set JWT_SECRET or JWT_KEYS")?;
+        if !keys.contains_key(&signing_kid) {
+            return Err(format!("JWT_SIGNING_KEY '{}' is not among the configured keys", signing_kid));
+        }
+        Ok(KeySet { signing_kid, keys })
+    }
+
+    /// A key set with just the legacy HMAC secret.
+    #[cfg(test)]
+    pub fn from_secret(secret: &str) -> KeySet {
+        let keys = HashMap::from([(LEGACY_KID.to_string(), hmac_key(Algorithm::HS256, secret))]);
+        KeySet { signing_kid: LEGACY_KID.to_string(), keys }
+    }
+
+    /// Header and key to sign new tokens with.
+    pub fn signing(&self) -> (jsonwebtoken::Header, &EncodingKey) {
+        let key = &self.keys[&self.signing_kid];
+        let mut header = jsonwebtoken::Header::new(key.algorithm);
+        header.kid = Some(self.signing_kid.clone());
+        (header, &key.encoding)
+    }
+
+    /// Key and algorithm for a token's `kid`, if it is one we trust.
+    pub fn verifying(&self, kid: Option<&str>) -> Option<(Algorithm, &DecodingKey)> {
+        let key = self.keys.get(kid.unwrap_or(LEGACY_KID))?;
+        Some((key.algorithm, &key.decoding))
+    }
+
+    pub fn jwks(&self) -> serde_json::Value {
+        let mut keys: Vec<(&String, &serde_json::Value)> = self
+            .keys
+            .iter()
+            .filter_map(|(kid, key)| key.jwk.as_ref().map(|jwk| (kid, jwk)))
+            .collect();
+        keys.sort_by_key(|(kid, _)| *kid);
+        json!({ "keys": keys.into_iter().map(|(_, jwk)| jwk).collect::<Vec<_>>() })
+    }
+}
+
+pub async fn jwks(keys: web::Data<KeySet>) -> impl Responder {
+    web::Json(keys.jwks())
+}
+
+#[cfg(test)]
+mod tests {
+    use jsonwebtoken::{decode, encode, jwk::Jwk, Validation};
+    use super::*;
+
+    fn testdata(file: &str) -> String {
+        format!("{}/testdata/{}", env!("CARGO_MANIFEST_DIR"), file)
+    }
+
+    fn sign(keys: &KeySet) -> String {
+        let (header, key) = keys.signing();
+        encode(&header, &json!({"sub": "alice", "exp": crate::db::now() + 60}), key).unwrap()
+    }
+
+    fn verify(keys: &KeySet, token: &str) -> bool {
+        let kid = jsonwebtoken::decode_header(token).unwrap().kid;
+        match keys.verifying(kid.as_deref()) {
+            Some((algorithm, key)) => decode::<serde_json::Value>(token, key, &Validation::new(algorithm)).is_ok(),
+            None => false,
+        }
+    }
+
+    #[test]
+    fn asymmetric_keys_sign_and_publish_their_public_half() {
+        for (algorithm, file) in [(Algorithm::RS256, "jwt-rs256.pem"), (Algorithm::EdDSA, "jwt-ed25519.pem")] {
+            let key = load_key("k1", algorithm, &testdata(file)).unwrap();
+            let keys = KeySet { signing_kid: "k1".to_string(), keys: HashMap::from([("k1".to_string(), key)]) };
+            let token = sign(&keys);
+            assert_eq!(jsonwebtoken::decode_header(&token).unwrap().alg, algorithm);
+            assert!(verify(&keys, &token));
+            // Anyone holding the JWKS can check the token.
+            let jwks = keys.jwks();
+            assert_eq!(jwks["keys"].as_array().unwrap().len(), 1);
+            assert!(jwks["keys"][0].get("d").is_none());
+            let jwk: Jwk = serde_json::from_value(jwks["keys"][0].clone()).unwrap();
+            let public = DecodingKey::from_jwk(&jwk).unwrap();
+            assert!(decode::<serde_json::Value>(&token, &public, &Validation::new(algorithm)).is_ok());
+        }
+        assert!(load_key("k1", Algorithm::RS256, &testdata("jwt-ed25519.pem")).is_err());
+        assert!(load_key("k1", Algorithm::ES256, &testdata("jwt-ed25519.pem")).is_err());
+        assert!(load_key("k1", Algorithm::EdDSA, &testdata("missing.pem")).is_err());
+    }
+
+    /// Runs every `from_env` case in one test, since they share variables.
+    #[test]
+    fn keys_rotate_through_the_environment() {
+        let rsa = testdata("jwt-rs256.pem");
+        let ed = testdata("jwt-ed25519.pem");
+        env::remove_var("JWT_SECRET");
+        env::set_var("JWT_KEYS", format!("new:EdDSA:{},old:RS256:{}", ed, rsa));
+        env::set_var("JWT_SIGNING_KEY", "old");
+        let before = KeySet::from_env().unwrap();
+        let old_token = sign(&before);
+
+        // Switch signing to the new key; old tokens stay valid.
+        env::set_var("JWT_SIGNING_KEY", "new");
+        let during = KeySet::from_env().unwrap();
+        let new_token = sign(&during);
+        assert_eq!(jsonwebtoken::decode_header(&new_token).unwrap().kid.as_deref(), Some("new"));
+        assert!(verify(&during, &old_token));
+        assert!(verify(&during, &new_token));
+        let jwks = during.jwks();
+        let kids: Vec<&str> = jwks["keys"].as_array().unwrap().iter().map(|k| k["kid"].as_str().unwrap()).collect();
+        assert_eq!(kids, ["new", "old"]);
+
+        // Drop the old key; the first entry signs by default.
+        env::remove_var("JWT_SIGNING_KEY");
+        env::set_var("JWT_KEYS", format!("new:EdDSA:{}", ed));
+        env::set_var("JWT_SECRET", "legacy-secret");
+        let after = KeySet::from_env().unwrap();
+        assert!(!verify(&after, &old_token));
+        assert!(verify(&after, &new_token));
+        // The shared secret is dropped along with kid-less tokens...
+        assert!(after.verifying(None).is_none());
+        // ...unless explicitly kept for the move, and it is never published.
+        env::set_var("JWT_ACCEPT_LEGACY_SECRET", "true");
+        let moving = KeySet::from_env().unwrap();
+        assert_eq!(moving.verifying(None).unwrap().0, Algorithm::HS256);
+        assert_eq!(moving.signing().0.kid.as_deref(), Some("new"));
+        assert_eq!(moving.jwks()["keys"].as_array().unwrap().len(), 1);
+        env::remove_var("JWT_ACCEPT_LEGACY_SECRET");
+
+        for (keys, signing_kid) in [
+            (format!("new:EdDSA:{}", ed), Some("missing")),
+            (format!("new:EdDSA:{},new:RS256:{}", ed, rsa), None),
+            (format!("new:XX256:{}", ed), None),
+            ("new:EdDSA".to_string(), None),
+            (format!(":EdDSA:{}", ed), None),
+        ] {
+            env::set_var("JWT_KEYS", &keys);
+            match signing_kid {
+                Some(kid) => env::set_var("JWT_SIGNING_KEY", kid),
+                None => env::remove_var("JWT_SIGNING_KEY"),
+            }
+            assert!(KeySet::from_env().is_err(), "{}", keys);
+        }
+        env::remove_var("JWT_KEYS");
+        env::remove_var("JWT_SIGNING_KEY");
+        env::remove_var("JWT_SECRET");
+        assert!(KeySet::from_env().is_err());
+    }
+}
@@ -1,78 +1,114 @@
-use std::sync::Arc;
-use actix_web::{body::{BoxBody, MessageBody}, error::ResponseError, http::{header, StatusCode}, middleware::{from_fn, Logger, Next}, web, App, HttpServer, Responder};
+mod apikeys;
+mod audit;
+mod containers;
+mod cors;
+mod db;
+mod engine;
+mod health;
+mod hosts;
+mod keys;
+mod mfa;
+mod oidc;
+mod passwords;
+mod policies;
+mod proxy_auth;
+mod rbac;
+mod sessions;
+mod ssh;
+mod throttle;
+mod tls;
+mod tokens;
+mod users;
+#[cfg(test)]
+mod tests;
+
+use actix_web::{body::BoxBody, error::ResponseError, http::StatusCode, middleware::{from_fn, Logger, Next}, web, App, HttpMessage, HttpRequest, HttpResponse, HttpServer, Responder};
 use dotenv::dotenv;
 use std::env;
-use actix_cors::Cors;
-use bollard::{Docker, API_DEFAULT_VERSION, models::{SystemInfo}};
-use bollard::container::ListContainersOptions;
+use bollard::models::SystemInfo;
 use serde::{Serialize, Deserialize};
 use std::fmt;
 use std::error::Error as StdError;
-use lazy_static::lazy_static;
-use serde_json;
-use jsonwebtoken::{encode, decode, Header, Algorithm, Validation, EncodingKey, DecodingKey};
-use bcrypt::{hash, verify, DEFAULT_COST};
-use std::time::{SystemTime, UNIX_EPOCH};
+use jsonwebtoken::{encode, decode, decode_header, Validation};
+use bcrypt::verify;
 use env_logger::Env;
+use db::Store;
+use hosts::{Host, Hosts};
+use keys::KeySet;
+use proxy_auth::{ProxyAuth, TrustedProxies};
+use rbac::Role;
+use throttle::LoginThrottle;
 
 #[derive(Debug, Serialize, Deserialize)]
 struct User {
     username: String,
     password: String,
+    /// Ask for session cookies instead of tokens in the response body.
+    #[serde(default)]
+    cookie: bool,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 struct Claims {
     sub: String,
+    role: Role,
+    jti: String,
+    iat: usize,
     exp: usize,
+    /// Session the token belongs to; see `sessions`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sid: Option<String>,
+    /// Hash of the CSRF token bound to a session-mode token.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    csrf: Option<String>,
 }
 
-fn create_jwt(username: &str) -> Result<String, jsonwebtoken::errors::Error> {
-    let expiration = SystemTime::now()
-        .duration_since(UNIX_EPOCH)
-        .unwrap()
-        .as_secs() as usize + 24 * 3600; // Token有效期24小时
+/// The authenticated caller, stored in request extensions by `auth_middleware`.
+#[derive(Debug, Clone)]
+struct Identity {
+    username: String,
+    role: Role,
+    /// Set when authenticated by an access token that belongs to a session.
+    session_id: Option<String>,
+    /// Set when authenticated by an API key.
+    api_key_id: Option<i64>,
+}
 
+fn create_jwt(keys: &KeySet, username: &str, role: Role, sid: Option<&str>, csrf: Option<String>) -> Result<String, jsonwebtoken::errors::Error> {
+    let now = db::now();
     let claims = Claims {
         sub: username.to_owned(),
-        exp: expiration,
+        role,
+        jti: uuid::Uuid::new_v4().to_string(),
+        iat: now as usize,
+        exp: (now + tokens::access_token_ttl()) as usize,
+        sid: sid.map(str::to_string),
+        csrf,
     };
 
-    let secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-    encode(
-        &Header::default(),
-        &claims,
-        &EncodingKey::from_secret(secret.as_bytes())
-    )
+    let (header, key) = keys.signing();
+    encode(&header, &claims, key)
 }
 
-fn verify_jwt(token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
-    let secret = env::var("JWT_SECRET").expect("JWT_SECRET must be set");
-    let validation = Validation::new(Algorithm::HS256);
-    let token_data = decode::<Claims>(
-        token,
-        &DecodingKey::from_secret(secret.as_bytes()),
-        &validation
-    )?;
-    Ok(token_data.claims)
-}
-
-lazy_static! {
-    static ref DOCKER: Docker = {
-        let docker_host = std::env::var("DOCKER_HOST").unwrap_or("unix:///var/run/docker.sock".to_string());
-        match docker_host.starts_with("unix://") {
-            true => Docker::connect_with_socket(
-                &docker_host[7..],
-                120,
-                API_DEFAULT_VERSION,
-            ).expect("Failed to connect to Docker socket"),
-            false => Docker::connect_with_http(
-                &docker_host,
-                5,
-                API_DEFAULT_VERSION,
-            ).expect("Failed to connect to Docker HTTP")
-        }
+fn verify_jwt(store: &Store, keys: &KeySet, token: &str) -> Result<Claims, jsonwebtoken::errors::Error> {
+    let header = decode_header(token)?;
+    // The algorithm comes from our key, never from the token header.
+    let (algorithm, key) = keys
+        .verifying(header.kid.as_deref())
+        .ok_or(jsonwebtoken::errors::ErrorKind::InvalidToken)?;
+    let token_data = decode::<Claims>(token, key, &Validation::new(algorithm))?;
+    let claims = token_data.claims;
+    // Treat storage errors like a revoked token rather than letting it through.
+    let revoked = store.is_jti_revoked(&claims.jti).unwrap_or(true)
+        || claims.sid.as_ref().is_some_and(|sid| !store.is_session_active(sid).unwrap_or(false));
+    let user_valid = match store.find_user(&claims.sub) {
+        Ok(Some(user)) => !user.disabled && claims.iat as i64 >= user.tokens_valid_after,
+        _ => false,
     };
+    if revoked || !user_valid {
+        return Err(jsonwebtoken::errors::ErrorKind::InvalidToken.into());
+    }
+    Ok(claims)
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -87,19 +123,52 @@ struct MyError(bollard::errors::Error);
 
 impl fmt::Display for MyError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{}", hosts::describe(&self.0))
     }
 }
 impl StdError for MyError {}
 
+impl MyError {
+    /// The engine refusing a request for something missing or in the wrong
+    /// state, which is passed on rather than reported as our own failure.
+    fn refusal(&self) -> Option<&'static str> {
+        match self.0 {
+            bollard::errors::Error::DockerResponseServerError { status_code: 404, .. } => Some("not_found"),
+            bollard::errors::Error::DockerResponseServerError { status_code: 409, .. } => Some("conflict"),
+            _ => None,
+        }
+    }
+}
+
 impl ResponseError for MyError {
     fn error_response(&self) -> actix_web::HttpResponse {
+        if hosts::is_unreachable(&self.0) {
+            return actix_web::HttpResponse::build(self.status_code()).json(serde_json::json!({
+                "error": "docker_unavailable",
+                "message": format!("Docker is unavailable: {}", self),
+            }));
+        }
+        if let Some(error) = self.refusal() {
+            return actix_web::HttpResponse::build(self.status_code()).json(serde_json::json!({
+                "error": error,
+                "message": self.to_string(),
+            }));
+        }
          actix_web::HttpResponse::build(self.status_code())
             .insert_header(actix_web::http::header::ContentType::json())
             .body(self.to_string())
     }
      fn status_code(&self) -> StatusCode {
-        StatusCode::INTERNAL_SERVER_ERROR
+        if hosts::is_unreachable(&self.0) {
+            StatusCode::SERVICE_UNAVAILABLE
+        } else if let Some(error) = self.refusal() {
+            match error {
+                "not_found" => StatusCode::NOT_FOUND,
+                _ => StatusCode::CONFLICT,
+            }
+        } else {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
     }
 }
 
@@ -111,9 +180,18 @@ async fn hello() -> impl Responder {
     })
 }
 
-async fn start_container(id: web::Path<String>) ->  impl Responder{
-    println!("starting: {}",id);
-    DOCKER.start_container::<String>(&id, None).await.map_err(MyError)?;
+/// Path of the per-container routes, with or without a `{host}` prefix.
+#[derive(Debug, Deserialize)]
+struct ContainerPath {
+    id: String,
+}
+
+async fn start_container(req: HttpRequest, store: web::Data<Store>, identity: web::ReqData<Identity>, host: Host, path: web::Path<ContainerPath>) ->  impl Responder{
+    let id = &path.id;
+    policies::authorize_container(&req, &store, &identity, &host, "start", id, Role::Operator).await?;
+    let result = host.engine()?.start_container(id).await;
+    audit::record_container_action(&store, &req, &identity, &host, "start", id, &result).await;
+    result.map_err(MyError)?;
     Ok::<web::Json<ApiResponse>, actix_web::Error>(web::Json(ApiResponse {
         message: format!("Container {} started", id),
         docker_info: None,
@@ -121,9 +199,30 @@ async fn start_container(id: web::Path<String>) ->  impl Responder{
     }))
 }
 
-async fn stop_container(id: web::Path<String>) ->  impl Responder{
-    println!("stoping:{}",id);
-    DOCKER.stop_container(&id, None).await.map_err(MyError)?;
+/// Timeout for stop and restart, named as in the Docker API.
+#[derive(Debug, Deserialize)]
+struct StopQuery {
+    /// Seconds to wait for the container to exit before killing it; the
+    /// container's own stop timeout if not given.
+    t: Option<i64>,
+}
+
+impl StopQuery {
+    fn timeout(&self) -> Result<Option<i64>, actix_web::Error> {
+        match self.t {
+            Some(t) if t < 0 => Err(actix_web::error::ErrorBadRequest("t must be a number of seconds, 0 or more")),
+            t => Ok(t),
+        }
+    }
+}
+
+async fn stop_container(req: HttpRequest, store: web::Data<Store>, identity: web::ReqData<Identity>, host: Host, path: web::Path<ContainerPath>, query: web::Query<StopQuery>) ->  impl Responder{
+    let id = &path.id;
+    let timeout = query.timeout()?;
+    policies::authorize_container(&req, &store, &identity, &host, "stop", id, Role::Operator).await?;
+    let result = host.engine()?.stop_container(id, timeout).await;
+    audit::record_container_action(&store, &req, &identity, &host, "stop", id, &result).await;
+    result.map_err(MyError)?;
     Ok::<web::Json<ApiResponse>, actix_web::Error>(web::Json(ApiResponse {
         message: format!("Container {} stopped", id),
         docker_info: None,
@@ -131,8 +230,13 @@ async fn stop_container(id: web::Path<String>) ->  impl Responder{
     }))
 }
 
-async fn restart_container(id: web::Path<String>) -> Result<impl Responder, actix_web::Error> {
-    DOCKER.restart_container(&id, None).await.map_err(MyError)?;
+async fn restart_container(req: HttpRequest, store: web::Data<Store>, identity: web::ReqData<Identity>, host: Host, path: web::Path<ContainerPath>, query: web::Query<StopQuery>) -> Result<impl Responder, actix_web::Error> {
+    let id = &path.id;
+    let timeout = query.timeout()?;
+    policies::authorize_container(&req, &store, &identity, &host, "restart", id, Role::Operator).await?;
+    let result = host.engine()?.restart_container(id, timeout).await;
+    audit::record_container_action(&store, &req, &identity, &host, "restart", id, &result).await;
+    result.map_err(MyError)?;
     Ok(web::Json(ApiResponse {
         message: format!("Container {} restarted", id),
         docker_info: None,
@@ -140,22 +244,139 @@ async fn restart_container(id: web::Path<String>) -> Result<impl Responder, acti
     }))
 }
 
-async fn docker_info() -> impl Responder {
-    let info = DOCKER.info().await.map_err(MyError)?;
-    Ok::<web::Json<ApiResponse>, actix_web::Error>(web::Json(ApiResponse{
-        message: "Docker Info".to_string(),
-        docker_info: Some(info),
+async fn pause_container(req: HttpRequest, store: web::Data<Store>, identity: web::ReqData<Identity>, host: Host, path: web::Path<ContainerPath>) -> Result<impl Responder, actix_web::Error> {
+    let id = &path.id;
+    policies::authorize_container(&req, &store, &identity, &host, "pause", id, Role::Operator).await?;
+    let result = host.engine()?.pause_container(id).await;
+    audit::record_container_action(&store, &req, &identity, &host, "pause", id, &result).await;
+    result.map_err(MyError)?;
+    Ok(web::Json(ApiResponse {
+        message: format!("Container {} paused", id),
+        docker_info: None,
+        containers: None,
+    }))
+}
+
+async fn unpause_container(req: HttpRequest, store: web::Data<Store>, identity: web::ReqData<Identity>, host: Host, path: web::Path<ContainerPath>) -> Result<impl Responder, actix_web::Error> {
+    let id = &path.id;
+    policies::authorize_container(&req, &store, &identity, &host, "unpause", id, Role::Operator).await?;
+    let result = host.engine()?.unpause_container(id).await;
+    audit::record_container_action(&store, &req, &identity, &host, "unpause", id, &result).await;
+    result.map_err(MyError)?;
+    Ok(web::Json(ApiResponse {
+        message: format!("Container {} unpaused", id),
+        docker_info: None,
+        containers: None,
+    }))
+}
+
+/// Signals `POST /container/{id}/kill` accepts, by name without `SIG`.
+const SIGNALS: &[&str] = &[
+    "HUP", "INT", "QUIT", "ILL", "TRAP", "ABRT", "BUS", "FPE", "KILL", "USR1", "SEGV", "USR2", "PIPE", "ALRM", "TERM",
+    "STKFLT", "CHLD", "CONT", "STOP", "TSTP", "TTIN", "TTOU", "URG", "XCPU", "XFSZ", "VTALRM", "PROF", "WINCH", "IO",
+    "PWR", "SYS",
+];
+
+#[derive(Debug, Deserialize)]
+struct KillQuery {
+    /// A signal name such as `SIGHUP` or `hup`, or its number; `SIGKILL`
+    /// if not given.
+    signal: Option<String>,
+}
+
+impl KillQuery {
+    /// The signal as the engine and audit log get it: a name in the
+    /// `SIGHUP` form, or a number.
+    fn signal(&self) -> Result<String, actix_web::Error> {
+        let Some(signal) = self.signal.as_deref().map(str::trim) else {
+            return Ok("SIGKILL".to_string());
+        };
+        if let Ok(number) = signal.parse::<u8>() {
+            return match number {
+                1..=64 => Ok(number.to_string()),
+                _ => Err(actix_web::error::ErrorBadRequest(format!("Unknown signal {}", signal))),
+            };
+        }
+        let upper = signal.to_ascii_uppercase();
+        let name = upper.strip_prefix("SIG").unwrap_or(&upper);
+        if SIGNALS.contains(&name) {
+            Ok(format!("SIG{}", name))
+        } else {
+            Err(actix_web::error::ErrorBadRequest(format!("Unknown signal {}", signal)))
+        }
+    }
+}
+
+async fn kill_container(req: HttpRequest, store: web::Data<Store>, identity: web::ReqData<Identity>, host: Host, path: web::Path<ContainerPath>, query: web::Query<KillQuery>) -> Result<impl Responder, actix_web::Error> {
+    let id = &path.id;
+    let signal = query.signal()?;
+    // The signal is part of the action, SIGHUP to reload is not SIGKILL.
+    let action = format!("kill {}", signal);
+    policies::authorize_container(&req, &store, &identity, &host, &action, id, Role::Operator).await?;
+    let result = host.engine()?.kill_container(id, &signal).await;
+    audit::record_container_action(&store, &req, &identity, &host, &action, id, &result).await;
+    result.map_err(MyError)?;
+    Ok(web::Json(ApiResponse {
+        message: format!("Sent {} to container {}", signal, id),
+        docker_info: None,
+        containers: None,
+    }))
+}
+
+/// Flags for `DELETE /container/{id}`, named as in the Docker API.
+#[derive(Debug, Deserialize)]
+struct RemoveQuery {
+    /// Kill the container first if it is running.
+    #[serde(default)]
+    force: bool,
+    /// Also remove the container's anonymous volumes.
+    #[serde(default)]
+    v: bool,
+}
+
+async fn remove_container(req: HttpRequest, store: web::Data<Store>, identity: web::ReqData<Identity>, host: Host, path: web::Path<ContainerPath>, query: web::Query<RemoveQuery>) -> Result<impl Responder, actix_web::Error> {
+    let id = &path.id;
+    policies::authorize_container(&req, &store, &identity, &host, "remove", id, Role::Operator).await?;
+    // Resolve the container first, it cannot be looked up once removed.
+    let target = audit::ContainerTarget::resolve(&host, id).await;
+    let result = host.engine()?.remove_container(id, query.force, query.v).await;
+    audit::record_container_target(&store, &req, &identity, &host, "remove", target, &result);
+    result.map_err(MyError)?;
+    Ok(web::Json(ApiResponse {
+        message: format!("Container {} removed", id),
+        docker_info: None,
         containers: None,
     }))
 }
 
-async fn get_containers() -> impl Responder {
-    let options = ListContainersOptions::<String> {
-        all: true,
-        ..Default::default()
+#[derive(Serialize, Debug)]
+struct DockerInfoResponse {
+    message: String,
+    docker_info: Option<SystemInfo>,
+    engine: Option<hosts::EngineInfo>,
+}
+
+async fn docker_info(host: Host) -> impl Responder {
+    let (message, info) = match host.engine()?.info().await {
+        Ok(info) => ("Docker Info".to_string(), Some(info)),
+        // Older engines and Podman may answer with fields that do not fit
+        // the current schema; still report what is known about the engine.
+        Err(e @ (bollard::errors::Error::JsonDataError { .. } | bollard::errors::Error::JsonSerdeError { .. })) => {
+            (format!("Docker Info (details unavailable: {})", e), None)
+        }
+        Err(e) => return Err(MyError(e).into()),
     };
-    
-    let containers = DOCKER.list_containers(Some(options)).await.map_err(MyError)?;
+    Ok::<web::Json<DockerInfoResponse>, actix_web::Error>(web::Json(DockerInfoResponse {
+        message,
+        docker_info: info,
+        engine: host.engine_info(),
+    }))
+}
+
+async fn get_containers(store: web::Data<Store>, identity: web::ReqData<Identity>, host: Host) -> impl Responder {
+    let access = policies::ContainerAccess::for_identity(&store, &identity)?;
+    let mut containers = host.engine()?.list_containers().await.map_err(MyError)?;
+    containers.retain(|container| access.role_for(container.labels.as_ref()).is_some());
     
     // 处理容器数据，添加service字段
     let mut container_data = Vec::new();
@@ -182,53 +403,199 @@ async fn get_containers() -> impl Responder {
     }))
 }
 
-#[derive(Debug, Serialize)]
-struct LoginResponse {
-    token: String,
-    message: String,
-}
-
-// async fn register(user: web::Json<User>) -> impl Responder {
-//     let hashed = hash(user.password.as_bytes(), DEFAULT_COST).unwrap();
-//     // TODO: 在实际应用中，这里应该将用户信息存储到数据库
-//     Ok::<web::Json<ApiResponse>, actix_web::Error>(web::Json(ApiResponse {
-//         message: "User registered successfully".to_string(),
-//         docker_info: None,
-//         containers: None,
-//     }))
-// }
-
-async fn login(user: web::Json<User>) -> impl Responder {
-    // TODO: 在实际应用中，这里应该从数据库验证用户信息
-    // 这里仅作演示，使用固定的用户名和密码
-    if user.username == "admin" && user.password == "password" {
-        let token = create_jwt(&user.username).unwrap();
-        Ok::<web::Json<LoginResponse>, actix_web::Error>(web::Json(LoginResponse {
-            token,
-            message: "Login successful".to_string(),
-        }))
-    } else {
-        Err(actix_web::error::ErrorUnauthorized("Invalid credentials"))
+async fn login(req: HttpRequest, store: web::Data<Store>, keys: web::Data<KeySet>, challenges: web::Data<mfa::MfaChallenges>, throttle: web::Data<LoginThrottle>, user: web::Json<User>) -> impl Responder {
+    let User { username, password, cookie } = user.into_inner();
+    let ip = audit::client_ip(&req);
+    if let Some(retry_after) = throttle.retry_after(&username, ip.as_deref()) {
+        return Ok(throttle::too_many_requests(retry_after));
     }
+    let record = store.find_user(&username).map_err(actix_web::error::ErrorInternalServerError)?;
+    // Unknown and disabled users get the same answer as a wrong password.
+    let record = match record {
+        Some(record) if !record.disabled => {
+            let password_hash = record.password_hash.clone();
+            let valid = web::block(move || verify(password, &password_hash))
+                .await?
+                .unwrap_or(false);
+            valid.then_some(record)
+        }
+        _ => None,
+    };
+    let Some(record) = record else {
+        if let Some(response) = throttle.record_failed_login(&store, &username, ip) {
+            return Ok(response);
+        }
+        return Err(actix_web::error::ErrorUnauthorized("Invalid credentials"));
+    };
+    // Failures are only forgotten once the second factor is passed too.
+    if record.totp_enabled {
+        return Ok(HttpResponse::Ok().json(challenges.start(&username)));
+    }
+    let session_id = sessions::start(&store, &req, &record.username)?;
+    let response = tokens::login_response(&store, &keys, &record, &session_id, "Login successful", cookie)?;
+    throttle.record_success(&username);
+    Ok(response)
 }
 
-async fn auth_middleware(req: actix_web::dev::ServiceRequest, next: Next<BoxBody>) -> Result<actix_web::dev::ServiceResponse, actix_web::Error> {
+fn bearer_identity(store: &Store, keys: &KeySet, req: &actix_web::dev::ServiceRequest) -> Result<Identity, actix_web::Error> {
     let auth_header = req.headers().get("Authorization");
     match auth_header {
         Some(auth_str) => {
-            let auth_str = auth_str.to_str().unwrap();
-            if auth_str.starts_with("Bearer ") {
-                let token = &auth_str[7..];
-                match verify_jwt(token) {
-                    Ok(_) => next.call(req).await,
-                    Err(_) => Err(actix_web::error::ErrorUnauthorized("Invalid token"))
-                }
+            let auth_str = auth_str.to_str().unwrap_or_default();
+            if let Some(token) = auth_str.strip_prefix("Bearer ") {
+                let identity = if token.starts_with(apikeys::API_KEY_PREFIX) {
+                    apikeys::verify_api_key(store, token)
+                } else {
+                    verify_jwt(store, keys, token).ok().map(|claims| Identity {
+                        username: claims.sub,
+                        role: claims.role,
+                        session_id: claims.sid,
+                        api_key_id: None,
+                    })
+                };
+                identity.ok_or_else(|| actix_web::error::ErrorUnauthorized("Invalid token"))
             } else {
                 Err(actix_web::error::ErrorUnauthorized("Invalid authorization header"))
             }
         }
-        None => Err(actix_web::error::ErrorUnauthorized("No authorization header"))
+        None => match req.cookie(tokens::SESSION_COOKIE) {
+            Some(cookie) => session_identity(store, keys, req, cookie.value()),
+            None => Err(actix_web::error::ErrorUnauthorized("No authorization header")),
+        },
+    }
+}
+
+/// Authenticates a session-mode cookie. Cookies are sent by the browser on
+/// its own, so state-changing requests must also echo the CSRF token.
+fn session_identity(store: &Store, keys: &KeySet, req: &actix_web::dev::ServiceRequest, token: &str) -> Result<Identity, actix_web::Error> {
+    let claims = verify_jwt(store, keys, token).map_err(|_| actix_web::error::ErrorUnauthorized("Invalid session"))?;
+    if !req.method().is_safe() {
+        let sent = req
+            .headers()
+            .get(tokens::CSRF_HEADER)
+            .and_then(|h| h.to_str().ok())
+            .map(tokens::hash_token);
+        if sent.is_none() || sent != claims.csrf {
+            return Err(actix_web::error::ErrorForbidden("Missing or invalid CSRF token"));
+        }
+    }
+    Ok(Identity {
+        username: claims.sub,
+        role: claims.role,
+        session_id: claims.sid,
+        api_key_id: None,
+    })
+}
+
+async fn auth_middleware(req: actix_web::dev::ServiceRequest, next: Next<BoxBody>) -> Result<actix_web::dev::ServiceResponse, actix_web::Error> {
+    let store = req.app_data::<web::Data<Store>>().expect("Store is not configured").clone();
+    let keys = req.app_data::<web::Data<KeySet>>().expect("KeySet is not configured").clone();
+    let proxy_auth = req.app_data::<web::Data<Option<ProxyAuth>>>().cloned();
+    let proxied = proxy_auth
+        .as_ref()
+        .and_then(|proxy_auth| proxy_auth.as_ref().as_ref())
+        .and_then(|proxy_auth| proxy_auth.authenticate(&store, &req))
+        // A token or session cookie wins over the connection's client
+        // certificate, so a session gets the identity (and CSRF checks) it
+        // was issued for.
+        .or_else(|| {
+            let signed_in = req.headers().contains_key("Authorization") || req.cookie(tokens::SESSION_COOKIE).is_some();
+            (!signed_in).then(|| tls::client_cert_identity(&store, &req)).flatten()
+        });
+    let identity = match proxied {
+        Some(Ok(identity)) => identity,
+        Some(Err(message)) => return Err(actix_web::error::ErrorUnauthorized(message)),
+        None => bearer_identity(&store, &keys, &req)?,
+    };
+    let required = rbac::required_role(req.method(), req.path());
+    if identity.role < required {
+        let response = rbac::forbidden(identity.role, required, req.path());
+        return Ok(req.into_response(response));
+    }
+    if let Some(session_id) = &identity.session_id {
+        let user_agent = req.headers().get("User-Agent").and_then(|v| v.to_str().ok());
+        let ip = audit::client_ip(req.request());
+        if let Err(e) = store.touch_session(session_id, user_agent, ip.as_deref()) {
+            log::warn!("failed to update session {}: {}", session_id, e);
+        }
     }
+    if let Some(response) = passwords::change_required(&store, &identity, &req) {
+        return Ok(req.into_response(response));
+    }
+    req.extensions_mut().insert(identity);
+    next.call(req).await
+}
+
+/// Every route the backend serves; `main` adds the middleware and state.
+fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.service(
+        web::scope("/auth")
+            .route("/login", web::post().to(login))
+            .route("/refresh", web::post().to(tokens::refresh))
+            .route("/logout", web::post().to(tokens::logout))
+            .route("/config", web::get().to(oidc::auth_config))
+            .route("/oidc/login", web::get().to(oidc::oidc_login))
+            .route("/oidc/callback", web::get().to(oidc::oidc_callback))
+            .route("/oidc/exchange", web::post().to(oidc::oidc_exchange))
+            .route("/2fa/verify", web::post().to(mfa::verify))
+            .service(
+                web::resource("/password")
+                    .wrap(from_fn(auth_middleware))
+                    .route(web::post().to(passwords::change_password))
+            )
+    )
+    .route("/.well-known/jwks.json", web::get().to(keys::jwks))
+    .route("/healthz", web::get().to(health::healthz))
+    .route("/readyz", web::get().to(health::readyz))
+    .service(
+        web::scope("")
+            .wrap(from_fn(auth_middleware))
+            .route("/", web::get().to(hello))
+            .route("/docker_info", web::get().to(docker_info))
+            .route("/containers", web::get().to(get_containers))
+            .route("/containers", web::post().to(containers::create_container))
+            .route("/container/{id}/start", web::post().to(start_container))
+            .route("/container/{id}/stop", web::post().to(stop_container)) 
+            .route("/container/{id}/restart", web::post().to(restart_container))
+            .route("/container/{id}/pause", web::post().to(pause_container))
+            .route("/container/{id}/unpause", web::post().to(unpause_container))
+            .route("/container/{id}/kill", web::post().to(kill_container))
+            .route("/container/{id}", web::delete().to(remove_container))
+            .route("/hosts", web::get().to(hosts::list_hosts))
+            .route("/hosts", web::post().to(hosts::create_host))
+            .route("/hosts/{host}", web::delete().to(hosts::delete_host))
+            .route("/hosts/{host}/docker_info", web::get().to(docker_info))
+            .route("/hosts/{host}/containers", web::get().to(get_containers))
+            .route("/hosts/{host}/containers", web::post().to(containers::create_container))
+            .route("/hosts/{host}/container/{id}/start", web::post().to(start_container))
+            .route("/hosts/{host}/container/{id}/stop", web::post().to(stop_container))
+            .route("/hosts/{host}/container/{id}/restart", web::post().to(restart_container))
+            .route("/hosts/{host}/container/{id}/pause", web::post().to(pause_container))
+            .route("/hosts/{host}/container/{id}/unpause", web::post().to(unpause_container))
+            .route("/hosts/{host}/container/{id}/kill", web::post().to(kill_container))
+            .route("/hosts/{host}/container/{id}", web::delete().to(remove_container))
+            .route("/users", web::get().to(users::list_users))
+            .route("/users", web::post().to(users::create_user))
+            .route("/users/{username}", web::delete().to(users::delete_user))
+            .route("/users/{username}/disable", web::post().to(users::disable_user))
+            .route("/users/{username}/enable", web::post().to(users::enable_user))
+            .route("/users/{username}/role", web::post().to(users::set_user_role))
+            .route("/users/{username}/revoke_tokens", web::post().to(tokens::revoke_user_tokens))
+            .route("/users/{username}/2fa/reset", web::post().to(mfa::reset))
+            .route("/users/{username}/password", web::post().to(passwords::reset_password))
+            .route("/2fa/setup", web::post().to(mfa::setup))
+            .route("/2fa/enable", web::post().to(mfa::enable))
+            .route("/2fa/disable", web::post().to(mfa::disable))
+            .route("/api_keys", web::get().to(apikeys::list_api_keys))
+            .route("/api_keys", web::post().to(apikeys::create_api_key))
+            .route("/api_keys/{id}", web::delete().to(apikeys::revoke_api_key))
+            .route("/audit", web::get().to(audit::get_audit))
+            .route("/sessions", web::get().to(sessions::list_sessions))
+            .route("/sessions/{id}", web::delete().to(sessions::revoke_session))
+            .route("/policies", web::get().to(policies::list_policies))
+            .route("/policies", web::post().to(policies::create_policy))
+            .route("/policies/{id}", web::delete().to(policies::delete_policy))
+    );
 }
 
 #[actix_web::main]
@@ -241,37 +608,70 @@ async fn main() -> std::io::Result<()> {
 
     env_logger::init_from_env(Env::default().default_filter_or("info"));
 
-        
-    HttpServer::new(|| {
-        let cors = Cors::default()
-            .allow_any_origin()
-            .allow_any_method()
-            .allow_any_header();
-            // .expose_headers(&["Authorization"]);
+    let cors_config = match cors::CorsConfig::from_env() {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("CORS configuration is invalid: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let tls_config = match tls::TlsConfig::from_env().and_then(|tls| tls.map(|tls| tls.server_config()).transpose()) {
+        Ok(config) => config,
+        Err(e) => {
+            log::error!("TLS configuration is invalid: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let keys = match KeySet::from_env() {
+        Ok(keys) => web::Data::new(keys),
+        Err(e) => {
+            log::error!("JWT key configuration is invalid: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let database_path = env::var("DATABASE_PATH").unwrap_or_else(|_| "dashboard.db".to_string());
+    let store = Store::open(&database_path).expect("Failed to open database");
+    users::bootstrap_admin(&store).expect("Failed to bootstrap admin account");
+    let docker_hosts = match Hosts::load(&store) {
+        Ok(hosts) => web::Data::new(hosts),
+        Err(e) => {
+            log::error!("Docker host configuration is invalid: {}", e);
+            std::process::exit(1);
+        }
+    };
+    let store = web::Data::new(store);
+    let oidc = web::Data::new(oidc::Oidc::from_env());
+    let proxy_auth = web::Data::new(ProxyAuth::from_env());
+    let trusted_proxies = web::Data::new(TrustedProxies::from_env());
+    let mfa_challenges = web::Data::new(mfa::MfaChallenges::default());
+    let login_throttle = web::Data::new(LoginThrottle::from_env());
+
+    let server = HttpServer::new(move || {
+        let cors = cors_config.build();
 
         // let auth = actix_web::middleware::Wrap::new(auth_middleware);
 
         App::new()
+            .app_data(store.clone())
+            .app_data(docker_hosts.clone())
+            .app_data(keys.clone())
+            .app_data(oidc.clone())
+            .app_data(proxy_auth.clone())
+            .app_data(trusted_proxies.clone())
+            .app_data(mfa_challenges.clone())
+            .app_data(login_throttle.clone())
             .wrap(Logger::default())
             .wrap(Logger::new("%a %{User-Agent}i"))
             .wrap(cors)
-            .service(
-                web::scope("/auth")
-                    // .route("/register", web::post().to(register))
-                    .route("/login", web::post().to(login))
-            )
-            .service(
-                web::scope("")
-                    .wrap(from_fn(auth_middleware))
-                    .route("/", web::get().to(hello))
-                    .route("/docker_info", web::get().to(docker_info))
-                    .route("/containers", web::get().to(get_containers))
-                    .route("/container/{id}/start", web::post().to(start_container))
-                    .route("/container/{id}/stop", web::post().to(stop_container)) 
-                    .route("/container/{id}/restart", web::post().to(restart_container))
-            )
+            .wrap(from_fn(cors::log_rejected))
+            .configure(routes)
     })
-    .bind(("0.0.0.0", port))?
+    .on_connect(tls::on_connect);
+    match tls_config {
+        Some(config) => server.bind_rustls_0_23(("0.0.0.0", port), config)?,
+        None => server.bind(("0.0.0.0", port))?,
+    }
     .run()
     .await
 }
@@ -0,0 +1,315 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use actix_web::{error, web, HttpRequest, HttpResponse, Responder};
+use rand::RngCore;
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use totp_rs::{Algorithm, Secret, TOTP};
+use crate::audit;
+use crate::db::{self, Store};
+use crate::keys::KeySet;
+use crate::sessions;
+use crate::throttle::{self, LoginThrottle};
+use crate::tokens::{self, hash_token, random_token};
+use crate::users::UserRecord;
+use crate::Identity;
+
+const ISSUER: &str = "docker-dashboard";
+const TOTP_STEP: u64 = 30;
+const RECOVERY_CODE_COUNT: usize = 10;
+/// A login challenge must be answered within this many seconds.
+const CHALLENGE_TTL: i64 = 5 * 60;
+/// Wrong codes allowed per challenge before the password must be re-entered.
+const CHALLENGE_ATTEMPTS: u32 = 5;
+
+impl Store {
+    /// Stores a not-yet-confirmed secret, replacing any previous enrollment.
+    pub fn set_totp_secret(&self, username: &str, secret: &str) -> rusqlite::Result<()> {
+        self.conn().execute(
+            "UPDATE users SET totp_secret = ?2, totp_enabled = 0, totp_last_step = 0 WHERE username = ?1",
+            params![username, secret],
+        )?;
+        Ok(())
+    }
+
+    /// Turns on 2FA and replaces the recovery codes in one transaction.
+    pub fn enable_totp(&self, username: &str, recovery_code_hashes: &[String]) -> rusqlite::Result<()> {
+        let mut conn = self.conn();
+        let tx = conn.transaction()?;
+        tx.execute("UPDATE users SET totp_enabled = 1 WHERE username = ?1", params![username])?;
+        tx.execute("DELETE FROM recovery_codes WHERE username = ?1", params![username])?;
+        for code_hash in recovery_code_hashes {
+            tx.execute(
+                "INSERT INTO recovery_codes (username, code_hash) VALUES (?1, ?2)",
+                params![username, code_hash],
+            )?;
+        }
+        tx.commit()
+    }
+
+    /// Returns false if no such user exists.
+    pub fn reset_totp(&self, username: &str) -> rusqlite::Result<bool> {
+        let conn = self.conn();
+        conn.execute("DELETE FROM recovery_codes WHERE username = ?1", params![username])?;
+        let n = conn.execute(
+            "UPDATE users SET totp_secret = NULL, totp_enabled = 0, totp_last_step = 0 WHERE username = ?1",
+            params![username],
+        )?;
+        Ok(n > 0)
+    }
+
+    /// Records the last accepted time step; returns false if `step` is not
+    /// newer, i.e. the code was already used.
+    pub fn advance_totp_step(&self, username: &str, step: i64) -> rusqlite::Result<bool> {
+        let n = self.conn().execute(
+            "UPDATE users SET totp_last_step = ?2 WHERE username = ?1 AND totp_last_step < ?2",
+            params![username, step],
+        )?;
+        Ok(n > 0)
+    }
+
+    /// Burns a recovery code; returns false if it is unknown or already used.
+    pub fn use_recovery_code(&self, username: &str, code_hash: &str) -> rusqlite::Result<bool> {
+        let n = self.conn().execute(
+            "UPDATE recovery_codes SET used_at = ?3 WHERE username = ?1 AND code_hash = ?2 AND used_at IS NULL",
+            params![username, code_hash, db::now()],
+        )?;
+        Ok(n > 0)
+    }
+}
+
+fn totp_for(username: &str, secret: &str) -> Result<TOTP, actix_web::Error> {
+    let secret = Secret::Encoded(secret.to_string())
+        .to_bytes()
+        .map_err(|e| error::ErrorInternalServerError(format!("Invalid TOTP secret: {:?}", e)))?;
+    // otpauth account names may not contain ':'.
+    TOTP::new(Algorithm::SHA1, 6, 1, TOTP_STEP, secret, Some(ISSUER.to_string()), username.replace(':', "_"))
+        .map_err(|e| error::ErrorInternalServerError(format!("Invalid TOTP parameters: {:?}", e)))
+}
+
+/// Returns the time step `code` is valid for, allowing one step of clock skew.
+fn matching_step(totp: &TOTP, code: &str) -> Option<i64> {
+    let current = db::now() as u64 / TOTP_STEP;
+    (current.saturating_sub(1)..=current + 1)
+        .find(|step| totp.generate(step * TOTP_STEP) == code)
+        .map(|step| step as i64)
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+    code.trim().to_lowercase().replace('-', "")
+}
+
+fn generate_recovery_codes() -> Vec<String> {
+    (0..RECOVERY_CODE_COUNT)
+        .map(|_| {
+            let mut bytes = [0u8; 5];
+            rand::thread_rng().fill_bytes(&mut bytes);
+            let code = hex::encode(bytes);
+            format!("{}-{}", &code[..5], &code[5..])
+        })
+        .collect()
+}
+
+/// Checks a TOTP code, or failing that a recovery code, for `user`.
+fn verify_second_factor(store: &Store, user: &UserRecord, code: &str) -> Result<bool, actix_web::Error> {
+    let secret = match &user.totp_secret {
+        Some(secret) => secret,
+        None => return Ok(false),
+    };
+    let code = code.trim();
+    if let Some(step) = matching_step(&totp_for(&user.username, secret)?, code) {
+        return store.advance_totp_step(&user.username, step).map_err(error::ErrorInternalServerError);
+    }
+    store
+        .use_recovery_code(&user.username, &hash_token(&normalize_recovery_code(code)))
+        .map_err(error::ErrorInternalServerError)
+}
+
+struct Challenge {
+    username: String,
+    created_at: i64,
+    attempts: u32,
+}
+
+/// Logins that passed the password check and are waiting for a second factor.
+#[derive(Default)]
+pub struct MfaChallenges {
+    pending: Mutex<HashMap<String, Challenge>>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct MfaChallengeResponse {
+    mfa_required: bool,
+    mfa_token: String,
+    message: String,
+}
+
+impl MfaChallenges {
+    pub fn start(&self, username: &str) -> MfaChallengeResponse {
+        let mfa_token = random_token();
+        let mut pending = self.pending.lock().unwrap_or_else(|e| e.into_inner());
+        let now = db::now();
+        pending.retain(|_, challenge| now - challenge.created_at < CHALLENGE_TTL);
+        pending.insert(mfa_token.clone(), Challenge {
+            username: username.to_string(),
+            created_at: now,
+            attempts: 0,
+        });
+        MfaChallengeResponse {
+            mfa_required: true,
+            mfa_token,
+            message: "Two-factor code required".to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CodeRequest {
+    code: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct VerifyRequest {
+    mfa_token: String,
+    code: String,
+    #[serde(default)]
+    cookie: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct SetupResponse {
+    message: String,
+    secret: String,
+    otpauth_uri: String,
+    /// PNG QR code of `otpauth_uri`, base64 encoded.
+    qr_code: String,
+}
+
+#[derive(Debug, Serialize)]
+struct RecoveryCodesResponse {
+    message: String,
+    recovery_codes: Vec<String>,
+}
+
+fn current_user(store: &Store, identity: &Identity) -> Result<UserRecord, actix_web::Error> {
+    store
+        .find_user(&identity.username)
+        .map_err(error::ErrorInternalServerError)?
+        .ok_or_else(|| error::ErrorNotFound("Only local accounts can use two-factor authentication"))
+}
+
+pub async fn setup(store: web::Data<Store>, identity: web::ReqData<Identity>) -> Result<impl Responder, actix_web::Error> {
+    let user = current_user(&store, &identity)?;
+    if user.totp_enabled {
+        return Err(error::ErrorConflict("Two-factor authentication is already enabled"));
+    }
+    let secret = Secret::generate_secret().to_encoded().to_string();
+    let totp = totp_for(&user.username, &secret)?;
+    let qr_code = totp.get_qr_base64().map_err(error::ErrorInternalServerError)?;
+    store.set_totp_secret(&user.username, &secret).map_err(error::ErrorInternalServerError)?;
+    Ok(web::Json(SetupResponse {
+        message: "Scan the QR code, then confirm with a code from your authenticator".to_string(),
+        secret,
+        otpauth_uri: totp.get_url(),
+        qr_code,
+    }))
+}
+
+pub async fn enable(store: web::Data<Store>, identity: web::ReqData<Identity>, body: web::Json<CodeRequest>) -> Result<impl Responder, actix_web::Error> {
+    let user = current_user(&store, &identity)?;
+    let secret = match (&user.totp_secret, user.totp_enabled) {
+        (_, true) => return Err(error::ErrorConflict("Two-factor authentication is already enabled")),
+        (None, _) => return Err(error::ErrorBadRequest("Call /2fa/setup first")),
+        (Some(secret), false) => secret,
+    };
+    let step = matching_step(&totp_for(&user.username, secret)?, body.code.trim())
+        .ok_or_else(|| error::ErrorBadRequest("Invalid code"))?;
+    let recovery_codes = generate_recovery_codes();
+    let hashes: Vec<String> = recovery_codes
+        .iter()
+        .map(|code| hash_token(&normalize_recovery_code(code)))
+        .collect();
+    store.enable_totp(&user.username, &hashes).map_err(error::ErrorInternalServerError)?;
+    store.advance_totp_step(&user.username, step).map_err(error::ErrorInternalServerError)?;
+    Ok(web::Json(RecoveryCodesResponse {
+        message: "Two-factor authentication enabled. Store these recovery codes somewhere safe".to_string(),
+        recovery_codes,
+    }))
+}
+
+/// Turns 2FA off given a current code. Wrong codes count towards the login
+/// lockout, so a stolen access token cannot be used to guess one.
+pub async fn disable(req: HttpRequest, store: web::Data<Store>, throttle: web::Data<LoginThrottle>, identity: web::ReqData<Identity>, body: web::Json<CodeRequest>) -> Result<HttpResponse, actix_web::Error> {
+    let user = current_user(&store, &identity)?;
+    if !user.totp_enabled {
+        return Err(error::ErrorBadRequest("Two-factor authentication is not enabled"));
+    }
+    let ip = audit::client_ip(&req);
+    if let Some(retry_after) = throttle.retry_after(&user.username, ip.as_deref()) {
+        return Ok(throttle::too_many_requests(retry_after));
+    }
+    if !verify_second_factor(&store, &user, &body.code)? {
+        if let Some(response) = throttle.record_failed_login(&store, &user.username, ip) {
+            return Ok(response);
+        }
+        return Err(error::ErrorBadRequest("Invalid code"));
+    }
+    store.reset_totp(&user.username).map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(crate::ApiResponse {
+        message: "Two-factor authentication disabled".to_string(),
+        docker_info: None,
+        containers: None,
+    }))
+}
+
+/// Second login step: exchanges the challenge token and a valid code for
+/// dashboard tokens. Wrong codes count towards the login lockout, so new
+/// challenges cannot be used to keep guessing.
+pub async fn verify(req: HttpRequest, store: web::Data<Store>, keys: web::Data<KeySet>, challenges: web::Data<MfaChallenges>, throttle: web::Data<LoginThrottle>, body: web::Json<VerifyRequest>) -> Result<HttpResponse, actix_web::Error> {
+    let username = {
+        let pending = challenges.pending.lock().unwrap_or_else(|e| e.into_inner());
+        match pending.get(&body.mfa_token) {
+            Some(challenge) if db::now() - challenge.created_at < CHALLENGE_TTL => challenge.username.clone(),
+            _ => return Err(error::ErrorUnauthorized("Login challenge expired, please sign in again")),
+        }
+    };
+    let user = match store.find_user(&username).map_err(error::ErrorInternalServerError)? {
+        Some(user) if !user.disabled && user.totp_enabled => user,
+        _ => return Err(error::ErrorUnauthorized("Login challenge expired, please sign in again")),
+    };
+    let ip = audit::client_ip(&req);
+    if let Some(retry_after) = throttle.retry_after(&username, ip.as_deref()) {
+        return Ok(throttle::too_many_requests(retry_after));
+    }
+    if !verify_second_factor(&store, &user, &body.code)? {
+        {
+            let mut pending = challenges.pending.lock().unwrap_or_else(|e| e.into_inner());
+            if let Some(challenge) = pending.get_mut(&body.mfa_token) {
+                challenge.attempts += 1;
+                if challenge.attempts >= CHALLENGE_ATTEMPTS {
+                    pending.remove(&body.mfa_token);
+                }
+            }
+        }
+        if let Some(response) = throttle.record_failed_login(&store, &username, ip) {
+            return Ok(response);
+        }
+        return Err(error::ErrorUnauthorized("Invalid code"));
+    }
+    challenges.pending.lock().unwrap_or_else(|e| e.into_inner()).remove(&body.mfa_token);
+    let session_id = sessions::start(&store, &req, &user.username)?;
+    let response = tokens::login_response(&store, &keys, &user, &session_id, "Login successful", body.cookie)?;
+    throttle.record_success(&username);
+    Ok(response)
+}
+
+pub async fn reset(store: web::Data<Store>, username: web::Path<String>) -> Result<impl Responder, actix_web::Error> {
+    if !store.reset_totp(&username).map_err(error::ErrorInternalServerError)? {
+        return Err(error::ErrorNotFound(format!("User {} not found", username)));
+    }
+    Ok(web::Json(crate::ApiResponse {
+        message: format!("Two-factor authentication reset for {}", username),
+        docker_info: None,
+        containers: None,
+    }))
+}
@@ -0,0 +1,395 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::Mutex;
+use actix_web::cookie::{time::Duration, Cookie, SameSite};
+use actix_web::{error, http::header, web, HttpRequest, HttpResponse, Responder};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use jsonwebtoken::{decode, decode_header, jwk::JwkSet, Algorithm, DecodingKey, Validation};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::db::{self, Store};
+use crate::keys::KeySet;
+use crate::sessions;
+use crate::rbac::{GroupRoles, Role};
+use crate::tokens::{self, random_token};
+
+/// Pending logins and exchange codes are dropped after this many seconds.
+const PENDING_TTL: i64 = 10 * 60;
+
+/// Holds the `state` of the login this browser started. The callback must
+/// present it, so a callback URL from someone else's login cannot sign the
+/// browser in to their account.
+const STATE_COOKIE: &str = "dd_oidc_state";
+
+fn state_cookie(state: String, max_age: i64) -> Cookie<'static> {
+    Cookie::build(STATE_COOKIE, state)
+        .path("/auth/oidc")
+        .http_only(true)
+        .secure(tokens::secure_cookies())
+        // The callback arrives as a navigation from the IdP's site.
+        .same_site(SameSite::Lax)
+        .max_age(Duration::seconds(max_age))
+        .finish()
+}
+
+pub struct OidcConfig {
+    issuer_url: String,
+    client_id: String,
+    client_secret: String,
+    redirect_url: String,
+    /// Where the browser is sent after a successful callback; the frontend
+    /// picks up `?sso_code=` from there.
+    frontend_url: String,
+    scopes: String,
+    username_claim: String,
+    groups_claim: String,
+    group_roles: GroupRoles,
+}
+
+impl OidcConfig {
+    /// Reads the `OIDC_*` variables. SSO stays disabled unless the issuer,
+    /// client id, redirect and frontend URLs are all set.
+    pub fn from_env() -> Option<OidcConfig> {
+        let var = |name: &str| env::var(name).ok().filter(|v| !v.is_empty());
+        Some(OidcConfig {
+            issuer_url: var("OIDC_ISSUER_URL")?.trim_end_matches('/').to_string(),
+            client_id: var("OIDC_CLIENT_ID")?,
+            client_secret: var("OIDC_CLIENT_SECRET").unwrap_or_default(),
+            redirect_url: var("OIDC_REDIRECT_URL")?,
+            frontend_url: var("OIDC_FRONTEND_URL")?,
+            scopes: var("OIDC_SCOPES").unwrap_or_else(|| "openid profile email groups".to_string()),
+            username_claim: var("OIDC_USERNAME_CLAIM").unwrap_or_else(|| "preferred_username".to_string()),
+            groups_claim: var("OIDC_GROUPS_CLAIM").unwrap_or_else(|| "groups".to_string()),
+            group_roles: GroupRoles::from_env("OIDC"),
+        })
+    }
+}
+
+struct PendingLogin {
+    nonce: String,
+    pkce_verifier: String,
+    created_at: i64,
+}
+
+struct PendingExchange {
+    username: String,
+    created_at: i64,
+}
+
+/// SSO settings plus the in-flight state of the authorization-code flow.
+pub struct Oidc {
+    config: Option<OidcConfig>,
+    http: reqwest::Client,
+    logins: Mutex<HashMap<String, PendingLogin>>,
+    exchanges: Mutex<HashMap<String, PendingExchange>>,
+}
+
+impl Oidc {
+    pub fn from_env() -> Oidc {
+        let config = OidcConfig::from_env();
+        if let Some(config) = &config {
+            log::info!("OIDC login enabled for issuer {}", config.issuer_url);
+        }
+        Oidc {
+            config,
+            http: reqwest::Client::new(),
+            logins: Mutex::new(HashMap::new()),
+            exchanges: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// SSO against `issuer_url` as client `dashboard`, with the defaults
+    /// `from_env` uses for everything else.
+    #[cfg(test)]
+    pub fn for_issuer(issuer_url: &str, group_roles: GroupRoles) -> Oidc {
+        Oidc {
+            config: Some(OidcConfig {
+                issuer_url: issuer_url.to_string(),
+                client_id: "dashboard".to_string(),
+                client_secret: "dashboard-secret".to_string(),
+                redirect_url: "http://dashboard.test/auth/oidc/callback".to_string(),
+                frontend_url: "http://dashboard.test/".to_string(),
+                scopes: "openid profile email groups".to_string(),
+                username_claim: "preferred_username".to_string(),
+                groups_claim: "groups".to_string(),
+                group_roles,
+            }),
+            http: reqwest::Client::new(),
+            logins: Mutex::new(HashMap::new()),
+            exchanges: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn config(&self) -> Result<&OidcConfig, actix_web::Error> {
+        self.config.as_ref().ok_or_else(|| error::ErrorNotFound("SSO is not configured"))
+    }
+
+    async fn discover(&self, config: &OidcConfig) -> Result<ProviderMetadata, actix_web::Error> {
+        let url = format!("{}/.well-known/openid-configuration", config.issuer_url);
+        let metadata = self.http.get(&url).send().await.and_then(|r| r.error_for_status());
+        let metadata = metadata
+            .map_err(|e| error::ErrorBadGateway(format!("OIDC discovery failed: {}", e)))?
+            .json::<ProviderMetadata>()
+            .await
+            .map_err(|e| error::ErrorBadGateway(format!("Invalid OIDC discovery document: {}", e)))?;
+        // ID tokens are checked against this issuer, so it must be the one
+        // that was configured rather than whatever the document claims.
+        if metadata.issuer.trim_end_matches('/') != config.issuer_url {
+            return Err(error::ErrorBadGateway(format!(
+                "OIDC discovery document is for issuer {}, not {}",
+                metadata.issuer, config.issuer_url
+            )));
+        }
+        Ok(metadata)
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ProviderMetadata {
+    issuer: String,
+    authorization_endpoint: String,
+    token_endpoint: String,
+    jwks_uri: String,
+    userinfo_endpoint: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct TokenResponse {
+    id_token: String,
+    access_token: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CallbackQuery {
+    code: Option<String>,
+    state: Option<String>,
+    error: Option<String>,
+    error_description: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ExchangeRequest {
+    code: String,
+    #[serde(default)]
+    cookie: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct AuthConfigResponse {
+    sso_enabled: bool,
+    sso_login_path: Option<&'static str>,
+}
+
+fn pkce_challenge(verifier: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()))
+}
+
+fn groups_from(claims: &serde_json::Value, claim: &str) -> Option<Vec<String>> {
+    match claims.get(claim)? {
+        serde_json::Value::Array(groups) => Some(
+            groups.iter().filter_map(|g| g.as_str().map(str::to_string)).collect(),
+        ),
+        serde_json::Value::String(group) => Some(vec![group.clone()]),
+        _ => None,
+    }
+}
+
+/// Lets the login page know whether to offer SSO.
+pub async fn auth_config(oidc: web::Data<Oidc>) -> impl Responder {
+    let sso_enabled = oidc.config.is_some();
+    web::Json(AuthConfigResponse {
+        sso_enabled,
+        sso_login_path: sso_enabled.then_some("/auth/oidc/login"),
+    })
+}
+
+pub async fn oidc_login(oidc: web::Data<Oidc>) -> Result<impl Responder, actix_web::Error> {
+    let config = oidc.config()?;
+    let metadata = oidc.discover(config).await?;
+    let state = random_token();
+    let nonce = random_token();
+    let pkce_verifier = random_token();
+    let mut url = reqwest::Url::parse(&metadata.authorization_endpoint)
+        .map_err(|e| error::ErrorBadGateway(format!("Invalid authorization endpoint: {}", e)))?;
+    url.query_pairs_mut()
+        .append_pair("response_type", "code")
+        .append_pair("client_id", &config.client_id)
+        .append_pair("redirect_uri", &config.redirect_url)
+        .append_pair("scope", &config.scopes)
+        .append_pair("state", &state)
+        .append_pair("nonce", &nonce)
+        .append_pair("code_challenge", &pkce_challenge(&pkce_verifier))
+        .append_pair("code_challenge_method", "S256");
+
+    let mut logins = oidc.logins.lock().unwrap_or_else(|e| e.into_inner());
+    let now = db::now();
+    logins.retain(|_, pending| now - pending.created_at < PENDING_TTL);
+    logins.insert(state.clone(), PendingLogin { nonce, pkce_verifier, created_at: now });
+    Ok(HttpResponse::Found()
+        .cookie(state_cookie(state, PENDING_TTL))
+        .insert_header((header::LOCATION, url.to_string()))
+        .finish())
+}
+
+pub async fn oidc_callback(req: HttpRequest, oidc: web::Data<Oidc>, store: web::Data<Store>, query: web::Query<CallbackQuery>) -> Result<impl Responder, actix_web::Error> {
+    let config = oidc.config()?;
+    if let Some(err) = &query.error {
+        let description = query.error_description.as_deref().unwrap_or_default();
+        return Err(error::ErrorUnauthorized(format!("SSO login failed: {} {}", err, description)));
+    }
+    let (code, state) = match (&query.code, &query.state) {
+        (Some(code), Some(state)) => (code, state),
+        _ => return Err(error::ErrorBadRequest("Missing code or state")),
+    };
+    if req.cookie(STATE_COOKIE).as_ref().map(Cookie::value) != Some(state.as_str()) {
+        return Err(error::ErrorBadRequest("SSO login was not started by this browser"));
+    }
+    let pending = oidc
+        .logins
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(state)
+        .filter(|pending| db::now() - pending.created_at < PENDING_TTL)
+        .ok_or_else(|| error::ErrorBadRequest("Unknown or expired SSO state"))?;
+
+    let metadata = oidc.discover(config).await?;
+    let tokens = oidc
+        .http
+        .post(&metadata.token_endpoint)
+        .basic_auth(&config.client_id, Some(&config.client_secret))
+        .form(&[
+            ("grant_type", "authorization_code"),
+            ("code", code),
+            ("redirect_uri", &config.redirect_url),
+            ("client_id", &config.client_id),
+            ("code_verifier", &pending.pkce_verifier),
+        ])
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| error::ErrorBadGateway(format!("OIDC token exchange failed: {}", e)))?
+        .json::<TokenResponse>()
+        .await
+        .map_err(|e| error::ErrorBadGateway(format!("Invalid OIDC token response: {}", e)))?;
+
+    let claims = verify_id_token(&oidc, config, &metadata, &tokens.id_token).await?;
+    if claims.get("nonce").and_then(|n| n.as_str()) != Some(pending.nonce.as_str()) {
+        return Err(error::ErrorUnauthorized("ID token nonce mismatch"));
+    }
+    let username = claims
+        .get(&config.username_claim)
+        .or_else(|| claims.get("sub"))
+        .and_then(|v| v.as_str())
+        .ok_or_else(|| error::ErrorUnauthorized("ID token has no usable username"))?
+        .to_string();
+    let groups = match groups_from(&claims, &config.groups_claim) {
+        Some(groups) => groups,
+        None => fetch_userinfo_groups(&oidc, config, &metadata, &tokens.access_token).await,
+    };
+    let role = config
+        .group_roles
+        .role_for(&groups)
+        .ok_or_else(|| error::ErrorForbidden(format!("No dashboard role is mapped for {}", username)))?;
+    provision_user(&store, &username, role)?;
+
+    let exchange_code = random_token();
+    let mut exchanges = oidc.exchanges.lock().unwrap_or_else(|e| e.into_inner());
+    let now = db::now();
+    exchanges.retain(|_, pending| now - pending.created_at < PENDING_TTL);
+    exchanges.insert(exchange_code.clone(), PendingExchange { username, created_at: now });
+
+    let mut redirect = reqwest::Url::parse(&config.frontend_url).map_err(error::ErrorInternalServerError)?;
+    redirect.query_pairs_mut().append_pair("sso_code", &exchange_code);
+    Ok(HttpResponse::Found()
+        .cookie(state_cookie(String::new(), 0))
+        .insert_header((header::LOCATION, redirect.to_string()))
+        .finish())
+}
+
+/// Trades the one-time code from the callback redirect for dashboard tokens,
+/// so tokens never appear in a URL.
+pub async fn oidc_exchange(req: HttpRequest, oidc: web::Data<Oidc>, store: web::Data<Store>, keys: web::Data<KeySet>, body: web::Json<ExchangeRequest>) -> Result<impl Responder, actix_web::Error> {
+    let pending = oidc
+        .exchanges
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .remove(&body.code)
+        .filter(|pending| db::now() - pending.created_at < PENDING_TTL)
+        .ok_or_else(|| error::ErrorUnauthorized("Invalid SSO code"))?;
+    let user = match store.find_user(&pending.username).map_err(error::ErrorInternalServerError)? {
+        Some(user) if !user.disabled => user,
+        _ => return Err(error::ErrorUnauthorized("Invalid SSO code")),
+    };
+    let session_id = sessions::start(&store, &req, &user.username)?;
+    tokens::login_response(&store, &keys, &user, &session_id, "Login successful", body.cookie)
+}
+
+async fn verify_id_token(oidc: &Oidc, config: &OidcConfig, metadata: &ProviderMetadata, id_token: &str) -> Result<serde_json::Value, actix_web::Error> {
+    let invalid = |e: jsonwebtoken::errors::Error| error::ErrorUnauthorized(format!("Invalid ID token: {}", e));
+    let header = decode_header(id_token).map_err(invalid)?;
+    if matches!(header.alg, Algorithm::HS256 | Algorithm::HS384 | Algorithm::HS512) {
+        return Err(error::ErrorUnauthorized("ID tokens must be signed with an asymmetric key"));
+    }
+    let jwks = oidc
+        .http
+        .get(&metadata.jwks_uri)
+        .send()
+        .await
+        .and_then(|r| r.error_for_status())
+        .map_err(|e| error::ErrorBadGateway(format!("Failed to fetch JWKS: {}", e)))?
+        .json::<JwkSet>()
+        .await
+        .map_err(|e| error::ErrorBadGateway(format!("Invalid JWKS: {}", e)))?;
+    let jwk = match &header.kid {
+        Some(kid) => jwks.find(kid),
+        None => jwks.keys.first(),
+    }
+    .ok_or_else(|| error::ErrorUnauthorized("ID token signed with an unknown key"))?;
+    let key = DecodingKey::from_jwk(jwk).map_err(invalid)?;
+    let mut validation = Validation::new(header.alg);
+    validation.set_audience(&[&config.client_id]);
+    validation.set_issuer(&[&metadata.issuer]);
+    Ok(decode::<serde_json::Value>(id_token, &key, &validation).map_err(invalid)?.claims)
+}
+
+async fn fetch_userinfo_groups(oidc: &Oidc, config: &OidcConfig, metadata: &ProviderMetadata, access_token: &str) -> Vec<String> {
+    let Some(endpoint) = &metadata.userinfo_endpoint else {
+        return Vec::new();
+    };
+    let userinfo = oidc.http.get(endpoint).bearer_auth(access_token).send().await;
+    match userinfo.and_then(|r| r.error_for_status()) {
+        Ok(response) => match response.json::<serde_json::Value>().await {
+            Ok(claims) => groups_from(&claims, &config.groups_claim).unwrap_or_default(),
+            Err(_) => Vec::new(),
+        },
+        Err(e) => {
+            log::warn!("OIDC userinfo request failed: {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Creates or updates the local account backing an SSO identity. Local
+/// password accounts are never taken over by an IdP user of the same name.
+fn provision_user(store: &Store, username: &str, role: Role) -> Result<(), actix_web::Error> {
+    match store.find_user(username).map_err(error::ErrorInternalServerError)? {
+        Some(user) if user.auth_source != "oidc" => Err(error::ErrorConflict(format!(
+            "A local account named {} already exists",
+            username
+        ))),
+        Some(user) if user.disabled => Err(error::ErrorUnauthorized("Account is disabled")),
+        Some(user) => {
+            if user.role != role {
+                store.set_user_role(username, role).map_err(error::ErrorInternalServerError)?;
+            }
+            Ok(())
+        }
+        None => {
+            // "!" is not a valid bcrypt hash, so password login always fails.
+            store
+                .create_user_with_source(username, "!", role, "oidc")
+                .map_err(error::ErrorInternalServerError)?;
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,175 @@
+use std::env;
+use actix_web::dev::ServiceRequest;
+use actix_web::{error, web, HttpRequest, HttpResponse, Responder};
+use bcrypt::verify;
+use rand::distributions::{Alphanumeric, DistString};
+use rusqlite::params;
+use serde::{Deserialize, Serialize};
+use crate::db::Store;
+use crate::keys::KeySet;
+use crate::users::{hash_password, UserRecord};
+use crate::{sessions, tokens, Identity};
+
+/// bcrypt ignores everything past this many bytes.
+const MAX_PASSWORD_BYTES: usize = 72;
+const CHANGE_PASSWORD_PATH: &str = "/auth/password";
+
+fn env_or(name: &str, default: usize) -> usize {
+    env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+/// Checks `password` against `PASSWORD_MIN_LENGTH` (default 8) and
+/// `PASSWORD_MIN_CLASSES` (default 2; lower case, upper case, digits and
+/// symbols each count as one class).
+pub fn check_policy(username: &str, password: &str) -> Result<(), actix_web::Error> {
+    let min_length = env_or("PASSWORD_MIN_LENGTH", 8);
+    let min_classes = env_or("PASSWORD_MIN_CLASSES", 2);
+    let classes = [
+        password.chars().any(|c| c.is_lowercase()),
+        password.chars().any(|c| c.is_uppercase()),
+        password.chars().any(|c| c.is_ascii_digit()),
+        password.chars().any(|c| !c.is_alphanumeric()),
+    ];
+    let mut problems = Vec::new();
+    if password.chars().count() < min_length {
+        problems.push(format!("must be at least {} characters", min_length));
+    }
+    if password.len() > MAX_PASSWORD_BYTES {
+        problems.push(format!("must be at most {} bytes", MAX_PASSWORD_BYTES));
+    }
+    if classes.iter().filter(|&&present| present).count() < min_classes {
+        problems.push(format!(
+            "must mix at least {} of lower case, upper case, digits and symbols",
+            min_classes
+        ));
+    }
+    if password.eq_ignore_ascii_case(username) {
+        problems.push("must not be the username".to_string());
+    }
+    if problems.is_empty() {
+        Ok(())
+    } else {
+        Err(error::ErrorBadRequest(format!("Password {}", problems.join(", "))))
+    }
+}
+
+impl Store {
+    /// Replaces the password hash and invalidates every token issued so far.
+    /// Returns false if no such user exists.
+    pub fn set_password(&self, username: &str, password_hash: &str, must_change: bool) -> rusqlite::Result<bool> {
+        let n = self.conn().execute(
+            "UPDATE users SET password_hash = ?2, must_change_password = ?3 WHERE username = ?1",
+            params![username, password_hash, must_change],
+        )?;
+        if n > 0 {
+            self.revoke_user_tokens(username)?;
+        }
+        Ok(n > 0)
+    }
+}
+
+/// Rejects every request but the password change itself while an admin
+/// reset is pending for the caller.
+pub fn change_required(store: &Store, identity: &Identity, req: &ServiceRequest) -> Option<HttpResponse> {
+    if req.path() == CHANGE_PASSWORD_PATH {
+        return None;
+    }
+    let user = store.find_user(&identity.username).ok()??;
+    user.must_change_password.then(|| {
+        HttpResponse::Forbidden().json(PasswordChangeRequired {
+            error: "password_change_required",
+            message: format!("Change your password at {} first", CHANGE_PASSWORD_PATH),
+        })
+    })
+}
+
+#[derive(Debug, Serialize)]
+struct PasswordChangeRequired {
+    error: &'static str,
+    message: String,
+}
+
+fn local_user(store: &Store, username: &str) -> Result<UserRecord, actix_web::Error> {
+    match store.find_user(username).map_err(error::ErrorInternalServerError)? {
+        Some(user) if user.auth_source == "local" => Ok(user),
+        Some(_) => Err(error::ErrorBadRequest(format!("{} signs in through single sign-on and has no password", username))),
+        None => Err(error::ErrorNotFound(format!("User {} not found", username))),
+    }
+}
+
+async fn password_matches(password: String, password_hash: String) -> Result<bool, actix_web::Error> {
+    Ok(web::block(move || verify(password, &password_hash)).await?.unwrap_or(false))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordChange {
+    current_password: String,
+    new_password: String,
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PasswordReset {
+    /// Temporary password; a random one is generated when omitted.
+    password: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct PasswordResetResponse {
+    message: String,
+    /// Only present when the server generated the temporary password.
+    temporary_password: Option<String>,
+}
+
+/// Changes the caller's own password. Every other session of the user is
+/// signed out; the caller gets fresh tokens in the same form it used.
+pub async fn change_password(
+    req: HttpRequest,
+    store: web::Data<Store>,
+    keys: web::Data<KeySet>,
+    identity: web::ReqData<Identity>,
+    body: web::Json<PasswordChange>,
+) -> Result<impl Responder, actix_web::Error> {
+    let PasswordChange { current_password, new_password } = body.into_inner();
+    let user = local_user(&store, &identity.username)?;
+    if !password_matches(current_password, user.password_hash.clone()).await? {
+        return Err(error::ErrorForbidden("Current password is incorrect"));
+    }
+    check_policy(&user.username, &new_password)?;
+    if password_matches(new_password.clone(), user.password_hash.clone()).await? {
+        return Err(error::ErrorBadRequest("New password must differ from the current one"));
+    }
+    let password_hash = hash_password(new_password).await?;
+    store.set_password(&user.username, &password_hash, false).map_err(error::ErrorInternalServerError)?;
+    let user = local_user(&store, &user.username)?;
+    let cookie = !req.headers().contains_key("Authorization") && req.cookie(tokens::SESSION_COOKIE).is_some();
+    let session_id = sessions::start(&store, &req, &user.username)?;
+    tokens::login_response(&store, &keys, &user, &session_id, "Password changed", cookie)
+}
+
+/// Admin reset: sets a temporary password that must be changed at the next
+/// login, and signs the user out everywhere, API keys included.
+pub async fn reset_password(
+    store: web::Data<Store>,
+    username: web::Path<String>,
+    body: Option<web::Json<PasswordReset>>,
+) -> Result<impl Responder, actix_web::Error> {
+    let user = local_user(&store, &username)?;
+    let requested = body.map(|b| b.into_inner()).unwrap_or_default().password;
+    let (temporary_password, generated) = match requested {
+        Some(password) => {
+            check_policy(&user.username, &password)?;
+            (password, None)
+        }
+        None => {
+            let password = Alphanumeric.sample_string(&mut rand::thread_rng(), 16);
+            (password.clone(), Some(password))
+        }
+    };
+    let password_hash = hash_password(temporary_password).await?;
+    store.set_password(&user.username, &password_hash, true).map_err(error::ErrorInternalServerError)?;
+    store.revoke_user_api_keys(&user.username).map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Ok().json(PasswordResetResponse {
+        message: format!("Password reset for {}; it must be changed at the next login", user.username),
+        temporary_password: generated,
+    }))
+}
@@ -0,0 +1,254 @@
+use std::collections::HashMap;
+use actix_web::{error, web, HttpRequest, HttpResponse, Responder};
+use rusqlite::{params, Row};
+use serde::{Deserialize, Serialize};
+use crate::db::{self, Store};
+use crate::hosts::DockerHost;
+use crate::rbac::{self, Role};
+use crate::{audit, Identity};
+
+const COMPOSE_PROJECT_LABEL: &str = "com.docker.compose.project";
+
+/// Grants a user a role on the containers of one compose project and/or the
+/// containers matching a label selector.
+///
+/// Policies only narrow access: a user with no policies keeps their global
+/// role on every container, while a user with at least one policy can only
+/// see and act on matching containers, with the lower of their global role
+/// and the best matching policy role. Admins are never restricted.
+#[derive(Debug, Clone, Serialize)]
+pub struct ContainerPolicy {
+    pub id: i64,
+    pub username: String,
+    pub project: Option<String>,
+    /// Comma-separated `key=value` or bare `key` (label present) terms, all
+    /// of which must match.
+    pub labels: Option<String>,
+    pub role: Role,
+    pub created_at: i64,
+}
+
+impl ContainerPolicy {
+    fn from_row(row: &Row) -> rusqlite::Result<ContainerPolicy> {
+        Ok(ContainerPolicy {
+            id: row.get("id")?,
+            username: row.get("username")?,
+            project: row.get("project")?,
+            labels: row.get("labels")?,
+            role: row.get("role")?,
+            created_at: row.get("created_at")?,
+        })
+    }
+
+    fn matches(&self, labels: &HashMap<String, String>) -> bool {
+        let project_matches = self
+            .project
+            .as_ref()
+            .is_none_or(|project| labels.get(COMPOSE_PROJECT_LABEL) == Some(project));
+        let labels_match = self.labels.as_deref().is_none_or(|selector| {
+            parse_selector(selector)
+                .map(|terms| {
+                    terms.iter().all(|(key, value)| match value {
+                        Some(value) => labels.get(key) == Some(value),
+                        None => labels.contains_key(key),
+                    })
+                })
+                .unwrap_or(false)
+        });
+        project_matches && labels_match
+    }
+}
+
+fn parse_selector(selector: &str) -> Result<Vec<(String, Option<String>)>, String> {
+    selector
+        .split(',')
+        .map(str::trim)
+        .filter(|term| !term.is_empty())
+        .map(|term| {
+            let (key, value) = match term.split_once('=') {
+                Some((key, value)) => (key.trim(), Some(value.trim().to_string())),
+                None => (term, None),
+            };
+            if key.is_empty() {
+                return Err(format!("Invalid label selector term '{}'", term));
+            }
+            Ok((key.to_string(), value))
+        })
+        .collect()
+}
+
+impl Store {
+    pub fn create_policy(&self, username: &str, project: Option<&str>, labels: Option<&str>, role: Role) -> rusqlite::Result<ContainerPolicy> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO container_policies (username, project, labels, role, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![username, project, labels, role, db::now()],
+        )?;
+        conn.query_row(
+            "SELECT * FROM container_policies WHERE id = ?1",
+            params![conn.last_insert_rowid()],
+            ContainerPolicy::from_row,
+        )
+    }
+
+    /// Lists the policies of `username`, or every policy when `None`.
+    pub fn list_policies(&self, username: Option<&str>) -> rusqlite::Result<Vec<ContainerPolicy>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare("SELECT * FROM container_policies WHERE ?1 IS NULL OR username = ?1 ORDER BY id")?;
+        let policies = stmt.query_map(params![username], ContainerPolicy::from_row)?.collect();
+        policies
+    }
+
+    /// Returns false if no such policy exists.
+    pub fn delete_policy(&self, id: i64) -> rusqlite::Result<bool> {
+        let n = self.conn().execute("DELETE FROM container_policies WHERE id = ?1", params![id])?;
+        Ok(n > 0)
+    }
+}
+
+/// What a caller may do with individual containers.
+pub struct ContainerAccess {
+    role: Role,
+    /// `None` when the caller is not restricted by any policy.
+    policies: Option<Vec<ContainerPolicy>>,
+}
+
+impl ContainerAccess {
+    pub fn for_identity(store: &Store, identity: &Identity) -> Result<ContainerAccess, actix_web::Error> {
+        let policies = if identity.role == Role::Admin {
+            None
+        } else {
+            Some(store.list_policies(Some(&identity.username)).map_err(error::ErrorInternalServerError)?)
+                .filter(|policies| !policies.is_empty())
+        };
+        Ok(ContainerAccess {
+            role: identity.role,
+            policies,
+        })
+    }
+
+    /// The caller's role on a container with `labels`, or `None` if the
+    /// container is outside every policy.
+    pub fn role_for(&self, labels: Option<&HashMap<String, String>>) -> Option<Role> {
+        let Some(policies) = &self.policies else {
+            return Some(self.role);
+        };
+        let empty = HashMap::new();
+        let labels = labels.unwrap_or(&empty);
+        policies
+            .iter()
+            .filter(|policy| policy.matches(labels))
+            .map(|policy| policy.role)
+            .max()
+            .map(|role| role.min(self.role))
+    }
+}
+
+/// Checks that the caller holds `required` on container `id`, recording an
+/// audit entry for `action` when it does not. Containers outside the caller's
+/// policies are reported as missing so their existence is not leaked.
+pub async fn authorize_container(
+    req: &HttpRequest,
+    store: &Store,
+    identity: &Identity,
+    host: &DockerHost,
+    action: &str,
+    id: &str,
+    required: Role,
+) -> Result<(), actix_web::Error> {
+    let access = ContainerAccess::for_identity(store, identity)?;
+    if access.policies.is_none() {
+        return Ok(());
+    }
+    let labels = host
+        .engine()?
+        .inspect_container(id)
+        .await
+        .ok()
+        .and_then(|c| c.config)
+        .and_then(|config| config.labels);
+    let result = match labels.as_ref().and_then(|labels| access.role_for(Some(labels))) {
+        None => Err(error::ErrorNotFound(format!("No such container: {}", id))),
+        Some(role) if role < required => Err(error::InternalError::from_response(
+            format!("role {} on this container, {} required", role, required),
+            rbac::forbidden(role, required, req.path()),
+        )
+        .into()),
+        Some(_) => Ok(()),
+    };
+    if let Err(e) = &result {
+        let denied: Result<(), String> = Err(format!("Denied by container policy: {}", e));
+        audit::record_container_action(store, req, identity, host, action, id, &denied).await;
+    }
+    result
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PolicyQuery {
+    user: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewPolicy {
+    username: String,
+    project: Option<String>,
+    labels: Option<String>,
+    role: Role,
+}
+
+#[derive(Debug, Serialize)]
+struct PoliciesResponse {
+    message: String,
+    policies: Vec<ContainerPolicy>,
+}
+
+#[derive(Debug, Serialize)]
+struct PolicyResponse {
+    message: String,
+    policy: ContainerPolicy,
+}
+
+pub async fn list_policies(store: web::Data<Store>, query: web::Query<PolicyQuery>) -> Result<impl Responder, actix_web::Error> {
+    let policies = store.list_policies(query.user.as_deref()).map_err(error::ErrorInternalServerError)?;
+    Ok(web::Json(PoliciesResponse {
+        message: "Container Policies".to_string(),
+        policies,
+    }))
+}
+
+pub async fn create_policy(store: web::Data<Store>, body: web::Json<NewPolicy>) -> Result<impl Responder, actix_web::Error> {
+    let NewPolicy { username, project, labels, role } = body.into_inner();
+    let username = username.trim();
+    let project = project.map(|p| p.trim().to_string()).filter(|p| !p.is_empty());
+    let labels = labels.map(|l| l.trim().to_string()).filter(|l| !l.is_empty());
+    if username.is_empty() {
+        return Err(error::ErrorBadRequest("username is required"));
+    }
+    if project.is_none() && labels.is_none() {
+        return Err(error::ErrorBadRequest("A policy needs a project, a label selector or both"));
+    }
+    if let Some(labels) = &labels {
+        parse_selector(labels).map_err(error::ErrorBadRequest)?;
+    }
+    if store.find_user(username).map_err(error::ErrorInternalServerError)?.is_none() {
+        return Err(error::ErrorNotFound(format!("User {} not found", username)));
+    }
+    let policy = store
+        .create_policy(username, project.as_deref(), labels.as_deref(), role)
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(HttpResponse::Created().json(PolicyResponse {
+        message: format!("Policy {} created for {}", policy.id, policy.username),
+        policy,
+    }))
+}
+
+pub async fn delete_policy(store: web::Data<Store>, id: web::Path<i64>) -> Result<impl Responder, actix_web::Error> {
+    if !store.delete_policy(*id).map_err(error::ErrorInternalServerError)? {
+        return Err(error::ErrorNotFound(format!("Policy {} not found", id)));
+    }
+    Ok(web::Json(crate::ApiResponse {
+        message: format!("Policy {} deleted", id),
+        docker_info: None,
+        containers: None,
+    }))
+}
@@ -0,0 +1,143 @@
+use std::env;
+use std::net::IpAddr;
+use actix_web::dev::ServiceRequest;
+use ipnet::IpNet;
+use crate::db::Store;
+use crate::rbac::GroupRoles;
+use crate::Identity;
+
+/// Reads a comma-separated list of networks from `name`, skipping (and
+/// logging) entries that do not parse.
+fn cidrs(name: &str) -> Vec<IpNet> {
+    env::var(name)
+        .unwrap_or_default()
+        .split(',')
+        .map(str::trim)
+        .filter(|cidr| !cidr.is_empty())
+        .filter_map(|cidr| {
+            // Accept bare addresses as single-host networks.
+            let parsed = cidr.parse::<IpNet>().or_else(|_| cidr.parse::<IpAddr>().map(IpNet::from));
+            match parsed {
+                Ok(net) => Some(net),
+                Err(e) => {
+                    log::warn!("ignoring {} entry '{}': {}", name, cidr, e);
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+/// Reverse proxies whose `Forwarded`/`X-Forwarded-For` headers are believed
+/// when working out a client's address (`TRUSTED_PROXY_CIDRS`). Independent
+/// of `ProxyAuth`, since most proxies only forward and do not authenticate.
+#[derive(Debug, Default)]
+pub struct TrustedProxies {
+    networks: Vec<IpNet>,
+}
+
+impl TrustedProxies {
+    pub fn from_env() -> TrustedProxies {
+        let networks = cidrs("TRUSTED_PROXY_CIDRS");
+        if !networks.is_empty() {
+            log::info!("trusting forwarded client addresses from {:?}", networks);
+        }
+        TrustedProxies { networks }
+    }
+
+    #[cfg(test)]
+    pub fn new(networks: &[&str]) -> TrustedProxies {
+        TrustedProxies {
+            networks: networks.iter().map(|cidr| cidr.parse().unwrap()).collect(),
+        }
+    }
+
+    pub fn trusts(&self, peer: IpAddr) -> bool {
+        self.networks.iter().any(|net| net.contains(&peer))
+    }
+}
+
+/// Opt-in authentication by an upstream proxy such as oauth2-proxy or
+/// Authelia. Identity headers are only honoured from `trusted_proxies`.
+pub struct ProxyAuth {
+    user_header: String,
+    groups_header: String,
+    trusted_proxies: Vec<IpNet>,
+    group_roles: GroupRoles,
+}
+
+impl ProxyAuth {
+    /// Reads the `PROXY_AUTH_*` variables. Returns `None` unless both a user
+    /// header and at least one trusted CIDR are configured.
+    pub fn from_env() -> Option<ProxyAuth> {
+        let user_header = env::var("PROXY_AUTH_USER_HEADER").ok().filter(|v| !v.is_empty())?;
+        let trusted_proxies = cidrs("PROXY_AUTH_TRUSTED_CIDRS");
+        if trusted_proxies.is_empty() {
+            log::warn!("PROXY_AUTH_USER_HEADER is set but PROXY_AUTH_TRUSTED_CIDRS is empty; proxy authentication disabled");
+            return None;
+        }
+        let groups_header = env::var("PROXY_AUTH_GROUPS_HEADER").unwrap_or_else(|_| "X-Forwarded-Groups".to_string());
+        log::info!("trusting {} from {:?}", user_header, trusted_proxies);
+        Some(ProxyAuth {
+            user_header,
+            groups_header,
+            trusted_proxies,
+            group_roles: GroupRoles::from_env("PROXY_AUTH"),
+        })
+    }
+
+    #[cfg(test)]
+    pub fn new(user_header: &str, groups_header: &str, trusted_proxies: &[&str], group_roles: GroupRoles) -> ProxyAuth {
+        ProxyAuth {
+            user_header: user_header.to_string(),
+            groups_header: groups_header.to_string(),
+            trusted_proxies: trusted_proxies.iter().map(|cidr| cidr.parse().unwrap()).collect(),
+            group_roles,
+        }
+    }
+
+    fn is_trusted(&self, req: &ServiceRequest) -> bool {
+        req.peer_addr()
+            .map(|addr| self.trusted_proxies.iter().any(|net| net.contains(&addr.ip())))
+            .unwrap_or(false)
+    }
+
+    /// Returns the proxy-asserted identity, or `None` when the request did
+    /// not come through a trusted proxy or carries no user header. A user
+    /// whose groups map to no role gets `Err` so it is not silently
+    /// downgraded to Bearer authentication.
+    pub fn authenticate(&self, store: &Store, req: &ServiceRequest) -> Option<Result<Identity, &'static str>> {
+        let username = req.headers().get(&self.user_header)?.to_str().ok()?.trim();
+        if username.is_empty() {
+            return None;
+        }
+        if !self.is_trusted(req) {
+            log::warn!("ignoring {} header from untrusted peer {:?}", self.user_header, req.peer_addr());
+            return None;
+        }
+        let groups: Vec<String> = req
+            .headers()
+            .get(&self.groups_header)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .split(',')
+            .map(|g| g.trim().to_string())
+            .filter(|g| !g.is_empty())
+            .collect();
+        // A disabled local account stays disabled behind the proxy too.
+        if let Ok(Some(user)) = store.find_user(username) {
+            if user.disabled {
+                return Some(Err("Account is disabled"));
+            }
+        }
+        Some(match self.group_roles.role_for(&groups) {
+            Some(role) => Ok(Identity {
+                username: username.to_string(),
+                role,
+                session_id: None,
+                api_key_id: None,
+            }),
+            None => Err("No dashboard role is mapped for the proxy user"),
+        })
+    }
+}
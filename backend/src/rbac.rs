@@ -0,0 +1,184 @@
+use std::fmt;
+use std::str::FromStr;
+use actix_web::{http::Method, HttpResponse};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use serde::{Deserialize, Serialize};
+
+/// Dashboard roles, ordered from least to most privileged.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Viewer,
+    Operator,
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Role::Viewer => "viewer",
+            Role::Operator => "operator",
+            Role::Admin => "admin",
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Role, String> {
+        match s {
+            "viewer" => Ok(Role::Viewer),
+            "operator" => Ok(Role::Operator),
+            "admin" => Ok(Role::Admin),
+            _ => Err(format!("unknown role '{}'", s)),
+        }
+    }
+}
+
+impl ToSql for Role {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for Role {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Role> {
+        value.as_str()?.parse().map_err(|e: String| FromSqlError::Other(e.into()))
+    }
+}
+
+/// Maps groups asserted by an external identity provider to dashboard roles.
+pub struct GroupRoles {
+    mappings: Vec<(String, Role)>,
+    default_role: Option<Role>,
+}
+
+impl GroupRoles {
+    /// Reads `<PREFIX>_GROUP_ROLES` (`group:role,...`) and
+    /// `<PREFIX>_DEFAULT_ROLE`.
+    pub fn from_env(prefix: &str) -> GroupRoles {
+        let mappings = std::env::var(format!("{}_GROUP_ROLES", prefix))
+            .unwrap_or_default()
+            .split(',')
+            .filter(|pair| !pair.trim().is_empty())
+            .filter_map(|pair| {
+                let parsed = pair
+                    .split_once(':')
+                    .ok_or_else(|| "expected group:role".to_string())
+                    .and_then(|(group, role)| Ok((group.trim().to_string(), role.trim().parse()?)));
+                match parsed {
+                    Ok(mapping) => Some(mapping),
+                    Err(e) => {
+                        log::warn!("ignoring {}_GROUP_ROLES entry '{}': {}", prefix, pair, e);
+                        None
+                    }
+                }
+            })
+            .collect();
+        let default_role = std::env::var(format!("{}_DEFAULT_ROLE", prefix))
+            .ok()
+            .and_then(|role| role.parse().ok());
+        GroupRoles { mappings, default_role }
+    }
+
+    /// Highest role granted by any of `groups`, falling back to the default.
+    pub fn role_for(&self, groups: &[String]) -> Option<Role> {
+        self.mappings
+            .iter()
+            .filter(|(group, _)| groups.contains(group))
+            .map(|(_, role)| *role)
+            .max()
+            .or(self.default_role)
+    }
+}
+
+#[cfg(test)]
+impl GroupRoles {
+    pub fn new(mappings: &[(&str, Role)], default_role: Option<Role>) -> GroupRoles {
+        let mappings = mappings.iter().map(|(group, role)| (group.to_string(), *role)).collect();
+        GroupRoles { mappings, default_role }
+    }
+}
+
+/// Minimum role per route. Patterns use `{param}` for a single path segment;
+/// routes that are not listed require `Admin`.
+const PERMISSIONS: &[(&str, &str, Role)] = &[
+    ("GET", "/", Role::Viewer),
+    ("GET", "/docker_info", Role::Viewer),
+    ("GET", "/containers", Role::Viewer),
+    ("POST", "/containers", Role::Operator),
+    ("POST", "/container/{id}/start", Role::Operator),
+    ("POST", "/container/{id}/stop", Role::Operator),
+    ("POST", "/container/{id}/restart", Role::Operator),
+    ("POST", "/container/{id}/pause", Role::Operator),
+    ("POST", "/container/{id}/unpause", Role::Operator),
+    ("POST", "/container/{id}/kill", Role::Operator),
+    ("DELETE", "/container/{id}", Role::Operator),
+    ("GET", "/hosts", Role::Viewer),
+    ("GET", "/hosts/{host}/docker_info", Role::Viewer),
+    ("GET", "/hosts/{host}/containers", Role::Viewer),
+    ("POST", "/hosts/{host}/containers", Role::Operator),
+    ("POST", "/hosts/{host}/container/{id}/start", Role::Operator),
+    ("POST", "/hosts/{host}/container/{id}/stop", Role::Operator),
+    ("POST", "/hosts/{host}/container/{id}/restart", Role::Operator),
+    ("POST", "/hosts/{host}/container/{id}/pause", Role::Operator),
+    ("POST", "/hosts/{host}/container/{id}/unpause", Role::Operator),
+    ("POST", "/hosts/{host}/container/{id}/kill", Role::Operator),
+    ("DELETE", "/hosts/{host}/container/{id}", Role::Operator),
+    ("GET", "/api_keys", Role::Viewer),
+    ("POST", "/api_keys", Role::Viewer),
+    ("DELETE", "/api_keys/{id}", Role::Viewer),
+    ("POST", "/2fa/setup", Role::Viewer),
+    ("POST", "/2fa/enable", Role::Viewer),
+    ("POST", "/2fa/disable", Role::Viewer),
+    ("POST", "/auth/password", Role::Viewer),
+    ("GET", "/sessions", Role::Viewer),
+    ("DELETE", "/sessions/{id}", Role::Viewer),
+];
+
+fn pattern_matches(pattern: &str, path: &str) -> bool {
+    let mut pattern = pattern.split('/');
+    let mut path = path.split('/');
+    loop {
+        match (pattern.next(), path.next()) {
+            (None, None) => return true,
+            (Some(p), Some(s)) if p.starts_with('{') && p.ends_with('}') && !s.is_empty() => {}
+            (Some(p), Some(s)) if p == s => {}
+            _ => return false,
+        }
+    }
+}
+
+pub fn required_role(method: &Method, path: &str) -> Role {
+    PERMISSIONS
+        .iter()
+        .find(|(m, pattern, _)| method.as_str() == *m && pattern_matches(pattern, path))
+        .map(|(_, _, role)| *role)
+        .unwrap_or(Role::Admin)
+}
+
+#[derive(Debug, Serialize)]
+struct ForbiddenBody<'a> {
+    error: &'static str,
+    message: String,
+    role: Role,
+    required_role: Role,
+    path: &'a str,
+}
+
+pub fn forbidden(role: Role, required_role: Role, path: &str) -> HttpResponse {
+    HttpResponse::Forbidden().json(ForbiddenBody {
+        error: "forbidden",
+        message: format!("Role {} is not allowed here, {} required", role, required_role),
+        role,
+        required_role,
+        path,
+    })
+}
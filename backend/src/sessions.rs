@@ -0,0 +1,171 @@
+use actix_web::{error, web, HttpRequest, Responder};
+use rusqlite::{params, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use crate::db::{self, Store};
+use crate::rbac::Role;
+use crate::{audit, Identity};
+
+/// Minimum seconds between two last-seen updates of the same session, so
+/// busy clients do not write on every request.
+const TOUCH_INTERVAL: i64 = 60;
+
+/// One sign-in, from login until logout, expiry or remote sign-out. The id is
+/// carried in every access token (`sid`) and refresh token of the chain.
+#[derive(Debug, Clone, Serialize)]
+pub struct SessionRecord {
+    pub id: String,
+    pub username: String,
+    pub user_agent: Option<String>,
+    pub ip: Option<String>,
+    pub created_at: i64,
+    pub last_seen_at: i64,
+    pub expires_at: i64,
+    /// Whether this is the session making the request.
+    pub current: bool,
+}
+
+impl SessionRecord {
+    fn from_row(row: &Row) -> rusqlite::Result<SessionRecord> {
+        Ok(SessionRecord {
+            id: row.get("id")?,
+            username: row.get("username")?,
+            user_agent: row.get("user_agent")?,
+            ip: row.get("ip")?,
+            created_at: row.get("created_at")?,
+            last_seen_at: row.get("last_seen_at")?,
+            expires_at: row.get("expires_at")?,
+            current: false,
+        })
+    }
+}
+
+impl Store {
+    pub fn create_session(&self, id: &str, username: &str, user_agent: Option<&str>, ip: Option<&str>, expires_at: i64) -> rusqlite::Result<()> {
+        let now = db::now();
+        self.conn().execute(
+            "INSERT INTO sessions (id, username, user_agent, ip, created_at, last_seen_at, expires_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?5, ?6)",
+            params![id, username, user_agent, ip, now, expires_at],
+        )?;
+        Ok(())
+    }
+
+    /// Active sessions of `username`, or of everyone when `None`, most
+    /// recently used first.
+    pub fn list_sessions(&self, username: Option<&str>) -> rusqlite::Result<Vec<SessionRecord>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(
+            "SELECT * FROM sessions
+             WHERE (?1 IS NULL OR username = ?1) AND revoked = 0 AND expires_at > ?2
+             ORDER BY last_seen_at DESC",
+        )?;
+        let sessions = stmt.query_map(params![username, db::now()], SessionRecord::from_row)?.collect();
+        sessions
+    }
+
+    pub fn find_session(&self, id: &str) -> rusqlite::Result<Option<SessionRecord>> {
+        self.conn()
+            .query_row(
+                "SELECT * FROM sessions WHERE id = ?1 AND revoked = 0 AND expires_at > ?2",
+                params![id, db::now()],
+                SessionRecord::from_row,
+            )
+            .optional()
+    }
+
+    pub fn is_session_active(&self, id: &str) -> rusqlite::Result<bool> {
+        Ok(self.find_session(id)?.is_some())
+    }
+
+    /// Records activity, at most once per `TOUCH_INTERVAL`.
+    pub fn touch_session(&self, id: &str, user_agent: Option<&str>, ip: Option<&str>) -> rusqlite::Result<()> {
+        let now = db::now();
+        self.conn().execute(
+            "UPDATE sessions SET last_seen_at = ?2, user_agent = COALESCE(?3, user_agent), ip = COALESCE(?4, ip)
+             WHERE id = ?1 AND last_seen_at <= ?5",
+            params![id, now, user_agent, ip, now - TOUCH_INTERVAL],
+        )?;
+        Ok(())
+    }
+
+    /// Moves the expiry along with each new refresh token.
+    pub fn extend_session(&self, id: &str, expires_at: i64) -> rusqlite::Result<()> {
+        self.conn().execute("UPDATE sessions SET expires_at = ?2 WHERE id = ?1", params![id, expires_at])?;
+        Ok(())
+    }
+
+    /// Ends a session and burns its refresh tokens. Its access tokens stop
+    /// working at once because `verify_jwt` checks the session.
+    pub fn revoke_session(&self, id: &str) -> rusqlite::Result<()> {
+        let conn = self.conn();
+        conn.execute("UPDATE sessions SET revoked = 1 WHERE id = ?1", params![id])?;
+        conn.execute("UPDATE refresh_tokens SET revoked = 1 WHERE session_id = ?1", params![id])?;
+        Ok(())
+    }
+}
+
+pub fn user_agent(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get("User-Agent")
+        .and_then(|v| v.to_str().ok())
+        .map(str::to_string)
+}
+
+/// Opens a session for a login from `req` and returns its id.
+pub fn start(store: &Store, req: &HttpRequest, username: &str) -> Result<String, actix_web::Error> {
+    let id = uuid::Uuid::new_v4().to_string();
+    store
+        .create_session(
+            &id,
+            username,
+            user_agent(req).as_deref(),
+            audit::client_ip(req).as_deref(),
+            db::now() + crate::tokens::refresh_token_ttl(),
+        )
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(id)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SessionQuery {
+    /// Admins only: limit the list to one user.
+    user: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+struct SessionsResponse {
+    message: String,
+    sessions: Vec<SessionRecord>,
+}
+
+/// Lists the caller's sessions; admins see everyone's.
+pub async fn list_sessions(store: web::Data<Store>, identity: web::ReqData<Identity>, query: web::Query<SessionQuery>) -> Result<impl Responder, actix_web::Error> {
+    let username = if identity.role == Role::Admin {
+        query.user.as_deref()
+    } else {
+        Some(identity.username.as_str())
+    };
+    let mut sessions = store.list_sessions(username).map_err(error::ErrorInternalServerError)?;
+    for session in &mut sessions {
+        session.current = identity.session_id.as_deref() == Some(session.id.as_str());
+    }
+    Ok(web::Json(SessionsResponse {
+        message: "Sessions List".to_string(),
+        sessions,
+    }))
+}
+
+pub async fn revoke_session(store: web::Data<Store>, identity: web::ReqData<Identity>, id: web::Path<String>) -> Result<impl Responder, actix_web::Error> {
+    let session = store.find_session(&id).map_err(error::ErrorInternalServerError)?;
+    // Other users' sessions look the same as missing ones to non-admins.
+    let session = match session {
+        Some(session) if session.username == identity.username || identity.role == Role::Admin => session,
+        _ => return Err(error::ErrorNotFound(format!("Session {} not found", id))),
+    };
+    store.revoke_session(&session.id).map_err(error::ErrorInternalServerError)?;
+    Ok(web::Json(crate::ApiResponse {
+        message: format!("Session of {} signed out", session.username),
+        docker_info: None,
+        containers: None,
+    }))
+}
@@ -0,0 +1,258 @@
+use std::env;
+use std::fs;
+use std::io::Read;
+use std::net::Ipv6Addr;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+use std::process::{Child, Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+use tempfile::TempDir;
+
+/// How long to wait for the forwarded socket to appear.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(15);
+const DEFAULT_REMOTE_SOCKET: &str = "/var/run/docker.sock";
+
+/// Settings of an `ssh://user@host[:port][/path/to/docker.sock]` endpoint.
+#[derive(Debug, Clone)]
+pub struct SshTarget {
+    /// `ssh://user@host[:port]`, which OpenSSH accepts as a destination.
+    destination: String,
+    remote_socket: String,
+    /// Private key to authenticate with; the ssh client defaults otherwise.
+    key_path: Option<String>,
+    /// known_hosts file the host key must be listed in; the ssh client
+    /// defaults (`~/.ssh/known_hosts`) otherwise.
+    known_hosts_path: Option<String>,
+}
+
+impl SshTarget {
+    /// Parses an endpoint. The user may be left out for ssh's default, and
+    /// IPv6 hosts are bracketed as in `ssh://user@[::1]:2222`.
+    pub fn parse(endpoint: &str, key_path: Option<String>, known_hosts_path: Option<String>) -> Result<SshTarget, String> {
+        let rest = endpoint
+            .strip_prefix("ssh://")
+            .ok_or_else(|| format!("'{}' is not an ssh:// endpoint", endpoint))?;
+        let (authority, remote_socket) = match rest.find('/') {
+            Some(i) => (&rest[..i], &rest[i..]),
+            None => (rest, DEFAULT_REMOTE_SOCKET),
+        };
+        let invalid = || format!("'{}' must look like ssh://user@host[:port]", endpoint);
+        let (user, host_port) = match authority.rsplit_once('@') {
+            Some((user, host_port)) => (Some(user), host_port),
+            None => (None, authority),
+        };
+        let (host, port) = match host_port.strip_prefix('[') {
+            Some(bracketed) => {
+                let (ip, port) = bracketed.split_once(']').ok_or_else(invalid)?;
+                ip.parse::<Ipv6Addr>().map_err(|_| invalid())?;
+                match port {
+                    "" => (ip, None),
+                    port => (ip, Some(port.strip_prefix(':').ok_or_else(invalid)?)),
+                }
+            }
+            None => match host_port.split_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (host_port, None),
+            },
+        };
+        // A leading '-' would be read by ssh as an option.
+        let bad_part = |part: &str| part.is_empty() || part.starts_with('-') || part.contains(char::is_whitespace);
+        let bad_port = |port: &str| !matches!(port.parse::<u16>(), Ok(1..));
+        if user.is_some_and(bad_part) || bad_part(host) || port.is_some_and(bad_port) {
+            return Err(invalid());
+        }
+        Ok(SshTarget {
+            destination: format!("ssh://{}", authority),
+            remote_socket: remote_socket.to_string(),
+            key_path,
+            known_hosts_path,
+        })
+    }
+
+    /// Starts `ssh` (or `DOCKER_SSH_COMMAND`) forwarding a local socket to the
+    /// remote Docker socket. Only key authentication is attempted and the
+    /// host key must already be known, so a changed or unknown host fails
+    /// instead of prompting.
+    pub fn open(&self, name: &str) -> Result<SshTunnel, String> {
+        // A fresh directory only we can write to, so nobody else can put
+        // their own socket where we expect ssh's.
+        let dir = tempfile::Builder::new()
+            .prefix(&format!("docker-dashboard-{}-", name))
+            .permissions(fs::Permissions::from_mode(0o700))
+            .tempdir()
+            .map_err(|e| format!("cannot create a directory for the ssh socket: {}", e))?;
+        let socket = dir.path().join("docker.sock");
+        if socket.exists() {
+            fs::remove_file(&socket).map_err(|e| format!("cannot remove stale socket {}: {}", socket.display(), e))?;
+        }
+        let mut command = Command::new(env::var("DOCKER_SSH_COMMAND").unwrap_or_else(|_| "ssh".to_string()));
+        command
+            .args(["-N", "-T"])
+            .args(["-o", "BatchMode=yes"])
+            .args(["-o", "StrictHostKeyChecking=yes"])
+            .args(["-o", "PreferredAuthentications=publickey"])
+            .args(["-o", "ExitOnForwardFailure=yes"])
+            .args(["-o", "ServerAliveInterval=15"])
+            .args(["-o", "ConnectTimeout=10"])
+            .args(["-o", "StreamLocalBindUnlink=yes"])
+            .args(["-o", "LogLevel=ERROR"]);
+        if let Some(key_path) = &self.key_path {
+            command.args(["-o", "IdentitiesOnly=yes", "-i", key_path]);
+        }
+        if let Some(known_hosts_path) = &self.known_hosts_path {
+            command.arg("-o").arg(format!("UserKnownHostsFile={}", known_hosts_path));
+        }
+        command
+            .arg("-L")
+            .arg(format!("{}:{}", socket.display(), self.remote_socket))
+            .arg(&self.destination)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::piped());
+        let mut child = command.spawn().map_err(|e| format!("cannot run ssh: {}", e))?;
+
+        let started = Instant::now();
+        while !socket.exists() {
+            if let Ok(Some(status)) = child.try_wait() {
+                let mut stderr = String::new();
+                if let Some(mut pipe) = child.stderr.take() {
+                    let _ = pipe.read_to_string(&mut stderr);
+                }
+                let reason = stderr.trim();
+                return Err(if reason.is_empty() {
+                    format!("ssh to {} exited with {}", self.destination, status)
+                } else {
+                    format!("ssh to {} failed: {}", self.destination, reason)
+                });
+            }
+            if started.elapsed() > CONNECT_TIMEOUT {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(format!("ssh to {} did not forward the Docker socket in time", self.destination));
+            }
+            thread::sleep(Duration::from_millis(100));
+        }
+        log::info!("forwarding {} to {} on {}", socket.display(), self.remote_socket, self.destination);
+        Ok(SshTunnel { child, socket, _dir: dir })
+    }
+}
+
+/// A running `ssh -L` process; stopped, and its socket directory removed,
+/// when dropped.
+pub struct SshTunnel {
+    child: Child,
+    pub socket: PathBuf,
+    _dir: TempDir,
+}
+
+impl Drop for SshTunnel {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(endpoint: &str) -> Result<SshTarget, String> {
+        SshTarget::parse(endpoint, None, None)
+    }
+
+    #[test]
+    fn parses_user_host_and_port() {
+        let target = parse("ssh://deploy@docker.example.com").unwrap();
+        assert_eq!(target.destination, "ssh://deploy@docker.example.com");
+        assert_eq!(target.remote_socket, DEFAULT_REMOTE_SOCKET);
+        let target = parse("ssh://deploy@docker.example.com:2222/run/user/1000/docker.sock").unwrap();
+        assert_eq!(target.destination, "ssh://deploy@docker.example.com:2222");
+        assert_eq!(target.remote_socket, "/run/user/1000/docker.sock");
+        assert_eq!(parse("ssh://docker.example.com").unwrap().destination, "ssh://docker.example.com");
+    }
+
+    #[test]
+    fn parses_bracketed_ipv6_hosts() {
+        assert_eq!(parse("ssh://deploy@[::1]").unwrap().destination, "ssh://deploy@[::1]");
+        let target = parse("ssh://deploy@[fe80::1]:2222/var/run/docker.sock").unwrap();
+        assert_eq!(target.destination, "ssh://deploy@[fe80::1]:2222");
+        assert!(parse("ssh://deploy@::1").is_err());
+        assert!(parse("ssh://deploy@[::1").is_err());
+        assert!(parse("ssh://deploy@[not-an-ip]").is_err());
+        assert!(parse("ssh://deploy@[::1]2222").is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_endpoints() {
+        for endpoint in [
+            "tcp://docker.example.com:2376",
+            "ssh://",
+            "ssh:///var/run/docker.sock",
+            "ssh://deploy@",
+            "ssh://@docker.example.com",
+            "ssh://-oProxyCommand=touch%20x",
+            "ssh://deploy@-oProxyCommand=x",
+            "ssh://deploy@docker.example.com:",
+            "ssh://deploy@docker.example.com:ssh",
+            "ssh://deploy@docker.example.com:0",
+            "ssh://deploy@docker.example.com:65536",
+            "ssh://deploy@docker example.com",
+        ] {
+            assert!(parse(endpoint).is_err(), "{}", endpoint);
+        }
+    }
+
+    /// Writes an executable `DOCKER_SSH_COMMAND` stand-in to `dir`.
+    fn stub(dir: &std::path::Path, name: &str, script: &str) -> String {
+        let path = dir.join(name);
+        fs::write(&path, format!("#!/bin/sh\n{}", script)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn tunnels_through_the_configured_ssh_command() {
+        let dir = env::temp_dir().join(format!("docker-dashboard-ssh-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let args_file = dir.join("args");
+        // Records its arguments and creates the forwarded socket's path, the
+        // way ssh -L does once connected.
+        let forwarding = stub(&dir, "ssh-ok", &format!(
+            "echo \"$@\" > {}\nwhile [ \"$1\" != -L ]; do shift; done\ntouch \"${{2%%:*}}\"\nexec sleep 30\n",
+            args_file.display()
+        ));
+        let failing = stub(&dir, "ssh-fail", "echo 'Host key verification failed.' >&2\nexit 255\n");
+
+        let target = SshTarget::parse(
+            "ssh://deploy@[::1]:2222/run/docker.sock",
+            Some("/keys/id_ed25519".to_string()),
+            Some("/keys/known_hosts".to_string()),
+        )
+        .unwrap();
+        env::set_var("DOCKER_SSH_COMMAND", &forwarding);
+        let tunnel = target.open("ssh-test").unwrap();
+        let socket = tunnel.socket.clone();
+        assert!(socket.exists());
+        let socket_dir = fs::metadata(socket.parent().unwrap()).unwrap();
+        assert_eq!(socket_dir.permissions().mode() & 0o777, 0o700);
+        let args = fs::read_to_string(&args_file).unwrap();
+        for expected in [
+            "-o BatchMode=yes",
+            "-o StrictHostKeyChecking=yes",
+            "-o IdentitiesOnly=yes -i /keys/id_ed25519",
+            "-o UserKnownHostsFile=/keys/known_hosts",
+        ] {
+            assert!(args.contains(expected), "{} in {}", expected, args);
+        }
+        assert!(args.trim_end().ends_with(&format!("-L {}:/run/docker.sock ssh://deploy@[::1]:2222", socket.display())), "{}", args);
+        drop(tunnel);
+        assert!(!socket.parent().unwrap().exists());
+
+        env::set_var("DOCKER_SSH_COMMAND", &failing);
+        let error = target.open("ssh-test").err().unwrap();
+        env::remove_var("DOCKER_SSH_COMMAND");
+        assert_eq!(error, "ssh to ssh://deploy@[::1]:2222 failed: Host key verification failed.");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
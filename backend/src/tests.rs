@@ -0,0 +1,1444 @@
+//! Route tests against an in-memory store and fake container engines.
+
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use actix_http::Request;
+use actix_web::body::MessageBody;
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::http::{header, StatusCode};
+use actix_web::test::{self, TestRequest};
+use actix_web::{web, App, HttpResponse, HttpServer};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use ring::rand::SystemRandom;
+use ring::signature::{Ed25519KeyPair, KeyPair};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use totp_rs::{Algorithm, Secret, TOTP};
+use crate::engine::fake::{FakeContainer, FakeEngine};
+use crate::engine::ContainerEngine;
+use crate::rbac::{GroupRoles, Role};
+use crate::{db::Store, hosts::{HostConfig, Hosts}, keys::KeySet, mfa, oidc, proxy_auth::{ProxyAuth, TrustedProxies}, routes, throttle::LoginThrottle};
+
+const PASSWORD: &str = "Passw0rd!";
+
+/// Shared state for one test, with an admin, an operator and a viewer and two
+/// hosts: the default `local` and a registered `remote`.
+struct TestContext {
+    store: web::Data<Store>,
+    hosts: web::Data<Hosts>,
+    keys: web::Data<KeySet>,
+    oidc: web::Data<oidc::Oidc>,
+    proxy_auth: web::Data<Option<ProxyAuth>>,
+    trusted_proxies: web::Data<TrustedProxies>,
+    mfa_challenges: web::Data<mfa::MfaChallenges>,
+    login_throttle: web::Data<LoginThrottle>,
+    local: Arc<FakeEngine>,
+    remote: Arc<FakeEngine>,
+}
+
+impl TestContext {
+    async fn new() -> TestContext {
+        let store = Store::open(":memory:").unwrap();
+        // The lowest cost keeps logins fast; verification accepts any cost.
+        let password_hash = bcrypt::hash(PASSWORD, 4).unwrap();
+        for (username, role) in [("admin", Role::Admin), ("ops", Role::Operator), ("viewer", Role::Viewer)] {
+            store.create_user(username, &password_hash, role).unwrap();
+        }
+        let local = Arc::new(FakeEngine::with([
+            FakeContainer::new("web1", "web", "nginx")
+                .label("com.docker.compose.project", "shop")
+                .label("team", "pay")
+                .running(),
+            FakeContainer::new("db1", "db", "postgres").label("com.docker.compose.project", "shop"),
+            FakeContainer::new("solo1", "solo", "redis"),
+        ]));
+        let remote = Arc::new(FakeEngine::with([FakeContainer::new("far1", "far", "busybox")]));
+        let hosts = Hosts::fixed(vec![
+            ("local", local.clone() as Arc<dyn ContainerEngine>),
+            ("remote", remote.clone() as Arc<dyn ContainerEngine>),
+        ])
+        .await;
+        TestContext {
+            store: web::Data::new(store),
+            hosts: web::Data::new(hosts),
+            keys: web::Data::new(KeySet::from_secret("test-secret")),
+            oidc: web::Data::new(oidc::Oidc::from_env()),
+            proxy_auth: web::Data::new(None),
+            trusted_proxies: web::Data::new(TrustedProxies::default()),
+            mfa_challenges: web::Data::new(mfa::MfaChallenges::default()),
+            login_throttle: web::Data::new(LoginThrottle::from_env()),
+            local,
+            remote,
+        }
+    }
+
+    async fn app(&self) -> impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = actix_web::Error> {
+        test::init_service(
+            App::new()
+                .app_data(self.store.clone())
+                .app_data(self.hosts.clone())
+                .app_data(self.keys.clone())
+                .app_data(self.oidc.clone())
+                .app_data(self.proxy_auth.clone())
+                .app_data(self.trusted_proxies.clone())
+                .app_data(self.mfa_challenges.clone())
+                .app_data(self.login_throttle.clone())
+                .configure(routes),
+        )
+        .await
+    }
+}
+
+/// Sends `req` and returns the status and the body, parsed as JSON when it is.
+async fn send<S, B>(app: &S, req: TestRequest) -> (StatusCode, Value)
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    // Errors from middleware only become responses in the HTTP layer.
+    let (status, body) = match app.call(req.to_request()).await {
+        Ok(response) => (response.status(), test::read_body(response).await),
+        Err(e) => {
+            let response = e.error_response();
+            (response.status(), actix_web::body::to_bytes(response.into_body()).await.unwrap_or_default())
+        }
+    };
+    let body = serde_json::from_slice(&body).unwrap_or_else(|_| Value::String(String::from_utf8_lossy(&body).into_owned()));
+    (status, body)
+}
+
+async fn login<S, B>(app: &S, username: &str) -> Value
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    let (status, body) = send(app, TestRequest::post().uri("/auth/login").set_json(json!({"username": username, "password": PASSWORD}))).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    body
+}
+
+async fn token<S, B>(app: &S, username: &str) -> String
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    login(app, username).await["token"].as_str().unwrap().to_string()
+}
+
+fn testdata(file: &str) -> String {
+    format!("{}/testdata/{}", env!("CARGO_MANIFEST_DIR"), file)
+}
+
+fn get(uri: &str, token: &str) -> TestRequest {
+    TestRequest::get().uri(uri).insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+}
+
+fn post(uri: &str, token: &str) -> TestRequest {
+    TestRequest::post().uri(uri).insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+}
+
+fn delete(uri: &str, token: &str) -> TestRequest {
+    TestRequest::delete().uri(uri).insert_header((header::AUTHORIZATION, format!("Bearer {}", token)))
+}
+
+fn container_names(body: &Value) -> Vec<&str> {
+    body["containers"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|c| c["Names"][0].as_str().unwrap())
+        .collect()
+}
+
+#[actix_web::test]
+async fn hello_requires_a_token() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let (status, _) = send(&app, TestRequest::get().uri("/")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = send(&app, get("/", "not-a-token")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let token = token(&app, "viewer").await;
+    let (status, body) = send(&app, get("/", &token)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["message"], "Hello from backend!");
+}
+
+#[actix_web::test]
+async fn login_rejects_wrong_passwords_and_disabled_users() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let wrong = json!({"username": "viewer", "password": "nope"});
+    let (status, _) = send(&app, TestRequest::post().uri("/auth/login").set_json(&wrong)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let unknown = json!({"username": "ghost", "password": PASSWORD});
+    let (status, _) = send(&app, TestRequest::post().uri("/auth/login").set_json(&unknown)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    ctx.store.set_user_disabled("viewer", true).unwrap();
+    let disabled = json!({"username": "viewer", "password": PASSWORD});
+    let (status, _) = send(&app, TestRequest::post().uri("/auth/login").set_json(&disabled)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn repeated_failed_logins_are_locked_out() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let wrong = json!({"username": "viewer", "password": "nope"});
+    for _ in 0..4 {
+        let (status, _) = send(&app, TestRequest::post().uri("/auth/login").set_json(&wrong)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+    let response = test::call_service(&app, TestRequest::post().uri("/auth/login").set_json(&wrong).to_request()).await;
+    assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "60");
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body["retry_after"], 60);
+    // The right password waits out the lockout too.
+    let right = json!({"username": "viewer", "password": PASSWORD});
+    let (status, _) = send(&app, TestRequest::post().uri("/auth/login").set_json(&right)).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    // Other users are unaffected; no peer address means no per-IP key.
+    login(&app, "ops").await;
+}
+
+#[actix_web::test]
+async fn clients_behind_a_trusted_proxy_are_locked_out_separately() {
+    let mut ctx = TestContext::new().await;
+    ctx.trusted_proxies = web::Data::new(TrustedProxies::new(&["10.0.0.0/8"]));
+    let app = ctx.app().await;
+    let attempt = |client: &str, spoofed: usize, username: &str, password: &str| {
+        TestRequest::post()
+            .uri("/auth/login")
+            .peer_addr("10.0.0.1:40000".parse().unwrap())
+            .insert_header(("X-Forwarded-For", format!("198.51.100.{}, {}", spoofed, client)))
+            .set_json(json!({"username": username, "password": password}))
+    };
+    // Different usernames and made-up addresses do not dodge the per-IP limit.
+    for i in 0..20 {
+        send(&app, attempt("203.0.113.7", i, &format!("guess{}", i), "nope")).await;
+    }
+    let (status, _) = send(&app, attempt("203.0.113.7", 99, "ops", PASSWORD)).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    // Other clients of the same proxy can still sign in.
+    let (status, body) = send(&app, attempt("203.0.113.8", 0, "ops", PASSWORD)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+}
+
+#[actix_web::test]
+async fn refresh_rotates_the_refresh_token() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let refresh_token = login(&app, "viewer").await["refresh_token"].clone();
+    let (status, body) = send(&app, TestRequest::post().uri("/auth/refresh").set_json(json!({"refresh_token": refresh_token}))).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let (status, _) = send(&app, get("/", body["token"].as_str().unwrap())).await;
+    assert_eq!(status, StatusCode::OK);
+    // A refresh token only works once.
+    let (status, _) = send(&app, TestRequest::post().uri("/auth/refresh").set_json(json!({"refresh_token": refresh_token}))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn logout_revokes_both_tokens() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let tokens = login(&app, "viewer").await;
+    let token = tokens["token"].as_str().unwrap();
+    let (status, _) = send(&app, post("/auth/logout", token).set_json(json!({"refresh_token": tokens["refresh_token"]}))).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&app, get("/", token)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = send(&app, TestRequest::post().uri("/auth/refresh").set_json(json!({"refresh_token": tokens["refresh_token"]}))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn sso_routes_report_sso_as_disabled() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let (status, body) = send(&app, TestRequest::get().uri("/auth/config")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["sso_enabled"], false);
+    let (status, _) = send(&app, TestRequest::get().uri("/auth/oidc/login")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send(&app, TestRequest::get().uri("/auth/oidc/callback?code=c&state=s")).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send(&app, TestRequest::post().uri("/auth/oidc/exchange").set_json(json!({"code": "c"}))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+/// An OpenID provider on a local port. It signs whatever ID token claims the
+/// test last set, for the code `good-code` and the PKCE verifier matching
+/// the last challenge it was given.
+struct MockIdp {
+    url: String,
+    state: web::Data<MockIdpState>,
+    server: actix_web::dev::ServerHandle,
+}
+
+struct MockIdpState {
+    url: String,
+    encoding: jsonwebtoken::EncodingKey,
+    jwk: Value,
+    claims: Mutex<Value>,
+    code_challenge: Mutex<String>,
+}
+
+impl MockIdp {
+    async fn start() -> MockIdp {
+        let pkcs8 = Ed25519KeyPair::generate_pkcs8(&SystemRandom::new()).unwrap();
+        let key_pair = Ed25519KeyPair::from_pkcs8(pkcs8.as_ref()).unwrap();
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let state = web::Data::new(MockIdpState {
+            url: url.clone(),
+            encoding: jsonwebtoken::EncodingKey::from_ed_der(pkcs8.as_ref()),
+            jwk: json!({
+                "kty": "OKP",
+                "crv": "Ed25519",
+                "alg": "EdDSA",
+                "kid": "idp-1",
+                "x": URL_SAFE_NO_PAD.encode(key_pair.public_key().as_ref()),
+            }),
+            claims: Mutex::new(Value::Null),
+            code_challenge: Mutex::new(String::new()),
+        });
+        let server = HttpServer::new({
+            let state = state.clone();
+            move || {
+                App::new()
+                    .app_data(state.clone())
+                    .route("/.well-known/openid-configuration", web::get().to(Self::discovery))
+                    .route("/jwks", web::get().to(Self::jwks))
+                    .route("/token", web::post().to(Self::token))
+            }
+        })
+        .workers(1)
+        .disable_signals()
+        .listen(listener)
+        .unwrap()
+        .run();
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+        MockIdp { url, state, server: handle }
+    }
+
+    /// Answers the next token request with an ID token for `username` in
+    /// `groups`, carrying `nonce`.
+    fn sign_in(&self, username: &str, groups: &[&str], nonce: &str, code_challenge: &str) {
+        let now = crate::db::now();
+        *self.state.claims.lock().unwrap() = json!({
+            "iss": self.url,
+            "aud": "dashboard",
+            "sub": format!("id-{}", username),
+            "preferred_username": username,
+            "groups": groups,
+            "nonce": nonce,
+            "iat": now,
+            "exp": now + 300,
+        });
+        *self.state.code_challenge.lock().unwrap() = code_challenge.to_string();
+    }
+
+    async fn discovery(state: web::Data<MockIdpState>) -> HttpResponse {
+        HttpResponse::Ok().json(json!({
+            "issuer": state.url,
+            "authorization_endpoint": format!("{}/authorize", state.url),
+            "token_endpoint": format!("{}/token", state.url),
+            "jwks_uri": format!("{}/jwks", state.url),
+        }))
+    }
+
+    async fn jwks(state: web::Data<MockIdpState>) -> HttpResponse {
+        HttpResponse::Ok().json(json!({"keys": [state.jwk]}))
+    }
+
+    async fn token(state: web::Data<MockIdpState>, form: web::Form<std::collections::HashMap<String, String>>) -> HttpResponse {
+        let verifier = form.get("code_verifier").map(String::as_str).unwrap_or_default();
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+        if form.get("grant_type").map(String::as_str) != Some("authorization_code")
+            || form.get("code").map(String::as_str) != Some("good-code")
+            || challenge != *state.code_challenge.lock().unwrap()
+        {
+            return HttpResponse::BadRequest().json(json!({"error": "invalid_grant"}));
+        }
+        let mut header = jsonwebtoken::Header::new(jsonwebtoken::Algorithm::EdDSA);
+        header.kid = Some("idp-1".to_string());
+        let id_token = jsonwebtoken::encode(&header, &*state.claims.lock().unwrap(), &state.encoding).unwrap();
+        HttpResponse::Ok().json(json!({"id_token": id_token, "access_token": "idp-access-token", "token_type": "Bearer"}))
+    }
+}
+
+/// An SSO login the test browser started: what it sent to the IdP, and the
+/// cookie tying the login to it.
+struct SsoLogin {
+    state: String,
+    nonce: String,
+    challenge: String,
+    cookie: actix_web::cookie::Cookie<'static>,
+}
+
+impl SsoLogin {
+    /// The IdP's redirect back to the dashboard, from the same browser.
+    fn callback(&self) -> TestRequest {
+        TestRequest::get()
+            .uri(&format!("/auth/oidc/callback?code=good-code&state={}", self.state))
+            .cookie(self.cookie.clone())
+    }
+}
+
+async fn start_sso<S, B>(app: &S) -> SsoLogin
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    let response = test::call_service(app, TestRequest::get().uri("/auth/oidc/login").to_request()).await;
+    assert_eq!(response.status(), StatusCode::FOUND);
+    let cookie = response.response().cookies().find(|c| c.name() == "dd_oidc_state").unwrap().into_owned();
+    assert_eq!(cookie.http_only(), Some(true));
+    let location = response.headers().get(header::LOCATION).unwrap().to_str().unwrap();
+    let url = reqwest::Url::parse(location).unwrap();
+    let param = |name: &str| url.query_pairs().find(|(key, _)| key == name).unwrap().1.into_owned();
+    assert_eq!(param("client_id"), "dashboard");
+    assert_eq!(param("code_challenge_method"), "S256");
+    assert_eq!(cookie.value(), param("state"));
+    SsoLogin { state: param("state"), nonce: param("nonce"), challenge: param("code_challenge"), cookie }
+}
+
+#[actix_web::test]
+async fn sso_logs_in_through_the_identity_provider() {
+    let idp = MockIdp::start().await;
+    let mut ctx = TestContext::new().await;
+    let group_roles = GroupRoles::new(&[("dash-ops", Role::Operator), ("dash-admins", Role::Admin)], None);
+    ctx.oidc = web::Data::new(oidc::Oidc::for_issuer(&idp.url, group_roles));
+    let app = ctx.app().await;
+    let (_, body) = send(&app, TestRequest::get().uri("/auth/config")).await;
+    assert_eq!(body["sso_enabled"], true);
+
+    let sso = start_sso(&app).await;
+    idp.sign_in("alice", &["staff", "dash-ops"], &sso.nonce, &sso.challenge);
+    let response = test::call_service(&app, sso.callback().to_request()).await;
+    assert_eq!(response.status(), StatusCode::FOUND, "{:?}", test::read_body(response).await);
+    let location = response.headers().get(header::LOCATION).unwrap().to_str().unwrap().to_string();
+    let sso_code = location.strip_prefix("http://dashboard.test/?sso_code=").unwrap();
+    let (status, body) = send(&app, TestRequest::post().uri("/auth/oidc/exchange").set_json(json!({"code": sso_code}))).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let (status, _) = send(&app, post("/container/db1/start", body["token"].as_str().unwrap())).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(ctx.store.find_user("alice").unwrap().unwrap().role, Role::Operator);
+    // The code only works once, and the state was used up by the callback.
+    let (status, _) = send(&app, TestRequest::post().uri("/auth/oidc/exchange").set_json(json!({"code": sso_code}))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = send(&app, sso.callback()).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // A callback for a login another browser started is refused, so an
+    // attacker cannot sign a victim in to the attacker's account.
+    let attackers = start_sso(&app).await;
+    let victims = start_sso(&app).await;
+    idp.sign_in("alice", &["dash-ops"], &attackers.nonce, &attackers.challenge);
+    let (status, body) = send(&app, attackers.callback().cookie(victims.cookie.clone())).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body, "SSO login was not started by this browser");
+    let (status, _) = send(&app, TestRequest::get().uri(&format!("/auth/oidc/callback?code=good-code&state={}", attackers.state))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    // An ID token minted for another login is refused.
+    let sso = start_sso(&app).await;
+    idp.sign_in("alice", &["dash-admins"], "someone-elses-nonce", &sso.challenge);
+    let (status, body) = send(&app, sso.callback()).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(body, "ID token nonce mismatch");
+    assert_eq!(ctx.store.find_user("alice").unwrap().unwrap().role, Role::Operator);
+
+    // Users in no mapped group get no account.
+    let sso = start_sso(&app).await;
+    idp.sign_in("bob", &["staff"], &sso.nonce, &sso.challenge);
+    let (status, _) = send(&app, sso.callback()).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert!(ctx.store.find_user("bob").unwrap().is_none());
+
+    // Local accounts are not taken over by an IdP user of the same name.
+    let sso = start_sso(&app).await;
+    idp.sign_in("admin", &["dash-admins"], &sso.nonce, &sso.challenge);
+    let (status, _) = send(&app, sso.callback()).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    idp.server.stop(false).await;
+}
+
+#[actix_web::test]
+async fn sso_refuses_a_provider_claiming_another_issuer() {
+    let idp = MockIdp::start().await;
+    let mut ctx = TestContext::new().await;
+    // The same server, configured under a name its documents do not use.
+    let configured = idp.url.replace("127.0.0.1", "localhost");
+    ctx.oidc = web::Data::new(oidc::Oidc::for_issuer(&configured, GroupRoles::new(&[], Some(Role::Viewer))));
+    let app = ctx.app().await;
+    let (status, body) = send(&app, TestRequest::get().uri("/auth/oidc/login")).await;
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    assert_eq!(body, format!("OIDC discovery document is for issuer {}, not {}", idp.url, configured));
+    idp.server.stop(false).await;
+}
+
+#[actix_web::test]
+async fn jwks_and_health_need_no_token() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let (status, body) = send(&app, TestRequest::get().uri("/.well-known/jwks.json")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(body["keys"].is_array());
+    let (status, body) = send(&app, TestRequest::get().uri("/healthz")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "ok");
+    let (status, body) = send(&app, TestRequest::get().uri("/readyz")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["status"], "ready");
+    let hosts = body["hosts"].as_array().unwrap();
+    assert_eq!(hosts.len(), 2);
+    assert_eq!(hosts[0], json!({"name": "local", "default": true, "status": "up"}));
+    assert!(hosts[1].get("endpoint").is_none());
+}
+
+#[actix_web::test]
+async fn docker_info_reports_the_engine() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let token = token(&app, "viewer").await;
+    let (status, body) = send(&app, get("/docker_info", &token)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["docker_info"]["Containers"], 3);
+    assert_eq!(body["docker_info"]["ContainersRunning"], 1);
+    assert_eq!(body["engine"]["kind"], "docker");
+    let (status, body) = send(&app, get("/hosts/remote/docker_info", &token)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["docker_info"]["Containers"], 1);
+    let (status, _) = send(&app, get("/hosts/nowhere/docker_info", &token)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn containers_are_listed_with_their_service() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let token = token(&app, "viewer").await;
+    let (status, body) = send(&app, get("/containers", &token)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(container_names(&body), ["/db", "/solo", "/web"]);
+    let services: Vec<&str> = body["containers"].as_array().unwrap().iter().map(|c| c["Service"].as_str().unwrap()).collect();
+    assert_eq!(services, ["shop", "Unknown", "shop"]);
+    let (status, body) = send(&app, get("/hosts/remote/containers", &token)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(container_names(&body), ["/far"]);
+    let (status, _) = send(&app, get("/hosts/nowhere/containers", &token)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn operators_start_stop_and_restart_containers() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let token = token(&app, "ops").await;
+    let (status, body) = send(&app, post("/container/db1/start", &token)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["message"], "Container db1 started");
+    assert!(ctx.local.container("db1").unwrap().running);
+    let (status, _) = send(&app, post("/container/web1/stop", &token)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!ctx.local.container("web1").unwrap().running);
+    let (status, _) = send(&app, post("/container/web1/restart", &token)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(ctx.local.container("web1").unwrap().running);
+    assert_eq!(ctx.local.calls().iter().filter(|c| *c != "list").collect::<Vec<_>>(), ["start db1", "stop web1", "restart web1"]);
+    assert!(ctx.remote.calls().is_empty());
+}
+
+#[actix_web::test]
+async fn host_prefixed_actions_reach_that_host() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let token = token(&app, "ops").await;
+    let (status, _) = send(&app, post("/hosts/remote/container/far1/start", &token)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&app, post("/hosts/remote/container/far1/restart", &token)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&app, post("/hosts/remote/container/far1/stop", &token)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(ctx.remote.calls(), ["start far1", "restart far1", "stop far1"]);
+    assert!(ctx.local.calls().is_empty());
+    let (status, _) = send(&app, post("/hosts/nowhere/container/far1/start", &token)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn viewers_cannot_change_containers() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let token = token(&app, "viewer").await;
+    for action in ["start", "stop", "restart", "pause", "unpause", "kill"] {
+        let (status, body) = send(&app, post(&format!("/container/db1/{}", action), &token)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+        assert_eq!(body["required_role"], "operator");
+        let (status, _) = send(&app, post(&format!("/hosts/remote/container/far1/{}", action), &token)).await;
+        assert_eq!(status, StatusCode::FORBIDDEN);
+    }
+    assert!(ctx.local.calls().is_empty());
+    assert!(ctx.remote.calls().is_empty());
+}
+
+#[actix_web::test]
+async fn container_actions_are_audited() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let ops = token(&app, "ops").await;
+    send(&app, post("/container/db1/start", &ops)).await;
+    send(&app, post("/hosts/remote/container/far1/start", &ops)).await;
+    let admin = token(&app, "admin").await;
+    let (status, body) = send(&app, get("/audit?user=ops", &admin)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let actions: Vec<&str> = body["entries"].as_array().unwrap().iter().map(|e| e["action"].as_str().unwrap()).collect();
+    assert_eq!(actions, ["start", "start"]);
+    let (_, body) = send(&app, get("/audit?container=db", &admin)).await;
+    assert_eq!(body["entries"][0]["container_id"], "db1");
+    let (status, body) = send(&app, get("/audit?host=remote", &admin)).await;
+    assert_eq!(status, StatusCode::OK);
+    let entries = body["entries"].as_array().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["username"], "ops");
+    assert_eq!(entries[0]["container_name"], "far");
+    assert_eq!(entries[0]["container_image"], "busybox");
+    assert_eq!(entries[0]["success"], true);
+    let (status, _) = send(&app, get("/audit", &ops)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[actix_web::test]
+async fn policies_limit_what_a_user_sees_and_controls() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let admin = token(&app, "admin").await;
+    let policy = json!({"username": "ops", "project": "shop", "role": "operator"});
+    let (status, body) = send(&app, post("/policies", &admin).set_json(policy)).await;
+    assert_eq!(status, StatusCode::CREATED, "{}", body);
+    let id = body["policy"]["id"].as_i64().unwrap();
+    let (status, body) = send(&app, get("/policies?user=ops", &admin)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["policies"].as_array().unwrap().len(), 1);
+
+    let ops = token(&app, "ops").await;
+    let (_, body) = send(&app, get("/containers", &ops)).await;
+    assert_eq!(container_names(&body), ["/db", "/web"]);
+    let (status, _) = send(&app, post("/container/db1/start", &ops)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&app, post("/container/solo1/start", &ops)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(!ctx.local.container("solo1").unwrap().running);
+
+    let (status, _) = send(&app, delete(&format!("/policies/{}", id), &admin)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&app, delete(&format!("/policies/{}", id), &admin)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (_, body) = send(&app, get("/containers", &ops)).await;
+    assert_eq!(container_names(&body).len(), 3);
+    let (status, _) = send(&app, get("/policies", &ops)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[actix_web::test]
+async fn policies_belong_to_existing_users() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let admin = token(&app, "admin").await;
+    let ghost = json!({"username": "ghost", "project": "shop", "role": "operator"});
+    let (status, _) = send(&app, post("/policies", &admin).set_json(ghost)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let dev = json!({"username": "dev", "password": "An0ther-pass", "role": "operator"});
+    let (status, _) = send(&app, post("/users", &admin).set_json(&dev)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let policy = json!({"username": "dev", "project": "shop", "role": "operator"});
+    let (status, _) = send(&app, post("/policies", &admin).set_json(policy)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (status, _) = send(&app, delete("/users/dev", &admin)).await;
+    assert_eq!(status, StatusCode::OK);
+
+    // A new account with the same name starts without the old restrictions.
+    let (status, _) = send(&app, post("/users", &admin).set_json(&dev)).await;
+    assert_eq!(status, StatusCode::CREATED);
+    let (_, body) = send(&app, get("/policies?user=dev", &admin)).await;
+    assert!(body["policies"].as_array().unwrap().is_empty(), "{}", body);
+}
+
+#[actix_web::test]
+async fn admins_manage_users() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let admin = token(&app, "admin").await;
+    let (status, body) = send(&app, get("/users", &admin)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["users"].as_array().unwrap().len(), 3);
+
+    let new_user = json!({"username": "dev", "password": "An0ther-pass", "role": "viewer"});
+    let (status, body) = send(&app, post("/users", &admin).set_json(&new_user)).await;
+    assert_eq!(status, StatusCode::CREATED, "{}", body);
+    let (status, _) = send(&app, post("/users", &admin).set_json(&new_user)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let weak = json!({"username": "weak", "password": "short"});
+    let (status, _) = send(&app, post("/users", &admin).set_json(weak)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, _) = send(&app, post("/users/dev/role", &admin).set_json(json!({"role": "operator"}))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(ctx.store.find_user("dev").unwrap().unwrap().role, Role::Operator);
+    let (status, _) = send(&app, post("/users/dev/disable", &admin)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(ctx.store.find_user("dev").unwrap().unwrap().disabled);
+    let (status, _) = send(&app, post("/users/dev/enable", &admin)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!ctx.store.find_user("dev").unwrap().unwrap().disabled);
+    let (status, _) = send(&app, delete("/users/dev", &admin)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&app, delete("/users/dev", &admin)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+
+    let viewer = token(&app, "viewer").await;
+    let (status, _) = send(&app, get("/users", &viewer)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[actix_web::test]
+async fn revoking_tokens_signs_a_user_out() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let viewer = token(&app, "viewer").await;
+    let admin = token(&app, "admin").await;
+    // Tokens issued within the same second as the revocation stay valid.
+    actix_web::rt::time::sleep(Duration::from_millis(1100)).await;
+    let (status, _) = send(&app, post("/users/viewer/revoke_tokens", &admin)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&app, get("/", &viewer)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = send(&app, post("/users/ghost/revoke_tokens", &admin)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn cookie_sessions_need_the_csrf_token() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let credentials = json!({"username": "ops", "password": PASSWORD, "cookie": true});
+    let res = test::call_service(&app, TestRequest::post().uri("/auth/login").set_json(credentials).to_request()).await;
+    assert_eq!(res.status(), StatusCode::OK);
+    let cookies: Vec<_> = res.response().cookies().map(|c| c.into_owned()).collect();
+    let session = cookies.iter().find(|c| c.name() == "dd_session").unwrap().clone();
+    let refresh = cookies.iter().find(|c| c.name() == "dd_refresh").unwrap().clone();
+    assert_eq!(session.http_only(), Some(true));
+    let body: Value = test::read_body_json(res).await;
+    assert!(body.get("token").is_none(), "{}", body);
+    let csrf = body["csrf_token"].as_str().unwrap().to_string();
+
+    let (status, _) = send(&app, TestRequest::get().uri("/containers").cookie(session.clone())).await;
+    assert_eq!(status, StatusCode::OK);
+    let start = || TestRequest::post().uri("/container/db1/start").cookie(session.clone());
+    let (status, _) = send(&app, start()).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = send(&app, start().insert_header(("X-CSRF-Token", "guess"))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert!(!ctx.local.container("db1").unwrap().running);
+    let (status, _) = send(&app, start().insert_header(("X-CSRF-Token", csrf.as_str()))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(ctx.local.container("db1").unwrap().running);
+
+    let (status, body) = send(&app, TestRequest::post().uri("/auth/refresh").cookie(refresh)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert!(body["csrf_token"].is_string());
+    let (status, _) = send(&app, TestRequest::post().uri("/auth/logout").cookie(session.clone())).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&app, TestRequest::get().uri("/containers").cookie(session)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn role_changes_sign_a_user_out() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let body = login(&app, "ops").await;
+    let ops = body["token"].as_str().unwrap().to_string();
+    let admin = token(&app, "admin").await;
+    let (status, _) = send(&app, post("/users/ops/role", &admin).set_json(json!({"role": "viewer"}))).await;
+    assert_eq!(status, StatusCode::OK);
+    // The old token still says operator, so it must stop working.
+    let (status, _) = send(&app, post("/container/db1/start", &ops)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let refresh = json!({"refresh_token": body["refresh_token"]});
+    let (status, _) = send(&app, TestRequest::post().uri("/auth/refresh").set_json(refresh)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = send(&app, post("/users/ghost/role", &admin).set_json(json!({"role": "viewer"}))).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(ctx.local.calls().is_empty());
+}
+
+#[actix_web::test]
+async fn reset_passwords_must_be_changed() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let admin = token(&app, "admin").await;
+    let (status, body) = send(&app, post("/users/viewer/password", &admin).set_json(json!({"password": "Temp0rary!"}))).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+
+    let (status, body) = send(&app, TestRequest::post().uri("/auth/login").set_json(json!({"username": "viewer", "password": "Temp0rary!"}))).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["password_change_required"], true);
+    let token = body["token"].as_str().unwrap();
+    let (status, body) = send(&app, get("/containers", token)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["error"], "password_change_required");
+
+    let change = json!({"current_password": "Temp0rary!", "new_password": "Brand-n3w-pass"});
+    let (status, body) = send(&app, post("/auth/password", token).set_json(change)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["password_change_required"], false);
+    let (status, _) = send(&app, get("/containers", body["token"].as_str().unwrap())).await;
+    assert_eq!(status, StatusCode::OK);
+}
+
+/// Turns on 2FA for the holder of `token` and returns the recovery codes.
+async fn enable_two_factor<S, B>(app: &S, token: &str) -> Vec<String>
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = actix_web::Error>,
+    B: MessageBody,
+{
+    let (status, body) = send(app, post("/2fa/setup", token)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let secret = Secret::Encoded(body["secret"].as_str().unwrap().to_string()).to_bytes().unwrap();
+    let totp = TOTP::new(Algorithm::SHA1, 6, 1, 30, secret, None, "viewer".to_string()).unwrap();
+    let (status, _) = send(app, post("/2fa/enable", token).set_json(json!({"code": "000000x"}))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, body) = send(app, post("/2fa/enable", token).set_json(json!({"code": totp.generate_current().unwrap()}))).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    serde_json::from_value(body["recovery_codes"].clone()).unwrap()
+}
+
+#[actix_web::test]
+async fn trusted_proxies_authenticate_users() {
+    let mut ctx = TestContext::new().await;
+    let group_roles = GroupRoles::new(&[("dash-ops", Role::Operator)], Some(Role::Viewer));
+    ctx.proxy_auth = web::Data::new(Some(ProxyAuth::new("X-Forwarded-User", "X-Forwarded-Groups", &["10.0.0.0/8"], group_roles)));
+    let app = ctx.app().await;
+    let proxied = |peer: &str, user: &str, groups: &str| {
+        TestRequest::post()
+            .uri("/container/db1/start")
+            .peer_addr(format!("{}:40000", peer).parse().unwrap())
+            .insert_header(("X-Forwarded-User", user))
+            .insert_header(("X-Forwarded-Groups", groups))
+    };
+    let (status, body) = send(&app, proxied("10.1.2.3", "carol", "staff, dash-ops")).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    // Without the operator group carol falls back to the default role.
+    let (status, _) = send(&app, proxied("10.1.2.3", "carol", "staff")).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    // Anyone else could send the header, so it is ignored from them.
+    let (status, _) = send(&app, proxied("192.168.1.5", "carol", "dash-ops")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = send(&app, proxied("192.168.1.5", "carol", "dash-ops").insert_header((header::AUTHORIZATION, "Bearer x"))).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    ctx.store.set_user_disabled("ops", true).unwrap();
+    let (status, _) = send(&app, proxied("10.1.2.3", "ops", "dash-ops")).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    assert_eq!(ctx.local.calls(), ["start db1"]);
+}
+
+#[actix_web::test]
+async fn forwarded_client_addresses_are_only_believed_from_trusted_proxies() {
+    let mut ctx = TestContext::new().await;
+    let group_roles = GroupRoles::new(&[("dash-ops", Role::Operator)], None);
+    ctx.proxy_auth = web::Data::new(Some(ProxyAuth::new("X-Forwarded-User", "X-Forwarded-Groups", &["10.0.0.0/8"], group_roles)));
+    ctx.trusted_proxies = web::Data::new(TrustedProxies::new(&["10.0.0.0/8"]));
+    let app = ctx.app().await;
+    let proxied = TestRequest::post()
+        .uri("/container/db1/start")
+        .peer_addr("10.1.2.3:40000".parse().unwrap())
+        .insert_header(("X-Forwarded-User", "carol"))
+        .insert_header(("X-Forwarded-Groups", "dash-ops"))
+        // The proxy appended the real address to what the client sent.
+        .insert_header(("X-Forwarded-For", "198.51.100.9, 203.0.113.7"));
+    let (status, _) = send(&app, proxied).await;
+    assert_eq!(status, StatusCode::OK);
+    let ops = token(&app, "ops").await;
+    let spoofed = post("/container/web1/start", &ops)
+        .peer_addr("192.168.1.5:40000".parse().unwrap())
+        .insert_header(("X-Forwarded-For", "203.0.113.7"));
+    let (status, _) = send(&app, spoofed).await;
+    assert_eq!(status, StatusCode::OK);
+
+    let admin = token(&app, "admin").await;
+    let (_, body) = send(&app, get("/audit?user=carol", &admin)).await;
+    assert_eq!(body["entries"][0]["ip"], "203.0.113.7");
+    let (_, body) = send(&app, get("/audit?user=ops", &admin)).await;
+    assert_eq!(body["entries"][0]["ip"], "192.168.1.5");
+}
+
+#[actix_web::test]
+async fn two_factor_login() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let token = token(&app, "viewer").await;
+    let recovery_codes = enable_two_factor(&app, &token).await;
+
+    let body = login(&app, "viewer").await;
+    assert_eq!(body["mfa_required"], true);
+    let challenge = json!({"mfa_token": body["mfa_token"], "code": recovery_codes[0]});
+    let (status, body) = send(&app, TestRequest::post().uri("/auth/2fa/verify").set_json(challenge)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let token = body["token"].as_str().unwrap().to_string();
+
+    // The current code was spent enabling 2FA, so use another recovery code
+    // rather than wait for the next time step.
+    let (status, _) = send(&app, post("/2fa/disable", &token).set_json(json!({"code": recovery_codes[0]}))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = send(&app, post("/2fa/disable", &token).set_json(json!({"code": recovery_codes[1]}))).await;
+    assert_eq!(status, StatusCode::OK);
+    let body = login(&app, "viewer").await;
+    assert!(body["token"].is_string());
+
+    let admin = self::token(&app, "admin").await;
+    let (status, _) = send(&app, post("/users/viewer/2fa/reset", &admin)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&app, post("/users/ghost/2fa/reset", &admin)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn disabling_two_factor_cannot_be_brute_forced() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let token = token(&app, "viewer").await;
+    let recovery_codes = enable_two_factor(&app, &token).await;
+    let guess = json!({"code": "000000"});
+    for _ in 0..4 {
+        let (status, _) = send(&app, post("/2fa/disable", &token).set_json(&guess)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+    }
+    let (status, _) = send(&app, post("/2fa/disable", &token).set_json(&guess)).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    // Even the right code waits out the lockout.
+    let (status, _) = send(&app, post("/2fa/disable", &token).set_json(json!({"code": recovery_codes[0]}))).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    assert!(ctx.store.find_user("viewer").unwrap().unwrap().totp_enabled);
+}
+
+#[actix_web::test]
+async fn wrong_two_factor_codes_count_towards_the_lockout() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let token = token(&app, "viewer").await;
+    enable_two_factor(&app, &token).await;
+    let wrong = json!({"username": "viewer", "password": "nope"});
+    for _ in 0..3 {
+        let (status, _) = send(&app, TestRequest::post().uri("/auth/login").set_json(&wrong)).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+    }
+    // The right password alone does not clear those failures.
+    let body = login(&app, "viewer").await;
+    let challenge = json!({"mfa_token": body["mfa_token"], "code": "000000"});
+    let (status, _) = send(&app, TestRequest::post().uri("/auth/2fa/verify").set_json(&challenge)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    // Nor does starting a new challenge.
+    let body = login(&app, "viewer").await;
+    let challenge = json!({"mfa_token": body["mfa_token"], "code": "000000"});
+    let (status, body) = send(&app, TestRequest::post().uri("/auth/2fa/verify").set_json(&challenge)).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS, "{}", body);
+    let (status, _) = send(&app, TestRequest::post().uri("/auth/2fa/verify").set_json(&challenge)).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+    let right = json!({"username": "viewer", "password": PASSWORD});
+    let (status, _) = send(&app, TestRequest::post().uri("/auth/login").set_json(&right)).await;
+    assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+    let admin = self::token(&app, "admin").await;
+    let (_, body) = send(&app, get("/audit?user=viewer", &admin)).await;
+    assert_eq!(body["entries"][0]["action"], "login_lockout");
+}
+
+#[actix_web::test]
+async fn api_keys_authenticate_until_revoked() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let token = token(&app, "ops").await;
+    let (status, _) = send(&app, post("/api_keys", &token).set_json(json!({"name": "ci", "role": "admin"}))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    for days in [0, i64::MAX, i64::MAX / (24 * 3600)] {
+        let (status, body) = send(&app, post("/api_keys", &token).set_json(json!({"name": "ci", "expires_in_days": days}))).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}: {}", days, body);
+    }
+    let (status, body) = send(&app, post("/api_keys", &token).set_json(json!({"name": "ci", "role": "operator"}))).await;
+    assert_eq!(status, StatusCode::CREATED, "{}", body);
+    let key = body["key"].as_str().unwrap().to_string();
+    let id = body["api_key"]["id"].as_i64().unwrap();
+
+    let (status, _) = send(&app, post("/container/db1/start", &key)).await;
+    assert_eq!(status, StatusCode::OK);
+    // A key cannot mint keys that would outlive it.
+    let (status, _) = send(&app, post("/api_keys", &key).set_json(json!({"name": "ci-forever", "role": "operator"}))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, body) = send(&app, get("/api_keys", &token)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body["api_keys"].as_array().unwrap().len(), 1);
+
+    let viewer = self::token(&app, "viewer").await;
+    let (status, _) = send(&app, delete(&format!("/api_keys/{}", id), &viewer)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send(&app, delete(&format!("/api_keys/{}", id), &token)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&app, get("/containers", &key)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[actix_web::test]
+async fn sessions_can_be_signed_out_remotely() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let first = token(&app, "viewer").await;
+    let second = token(&app, "viewer").await;
+    let (status, body) = send(&app, get("/sessions", &second)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let sessions = body["sessions"].as_array().unwrap();
+    assert_eq!(sessions.len(), 2);
+    let other = sessions.iter().find(|s| s["current"] == false).unwrap()["id"].as_str().unwrap();
+
+    let admin = token(&app, "admin").await;
+    let (_, body) = send(&app, get("/sessions?user=viewer", &admin)).await;
+    assert_eq!(body["sessions"].as_array().unwrap().len(), 2);
+
+    let (status, _) = send(&app, delete(&format!("/sessions/{}", other), &second)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&app, get("/", &first)).await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+    let (status, _) = send(&app, get("/", &second)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&app, delete("/sessions/unknown", &second)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn hosts_are_listed_registered_and_removed() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let viewer = token(&app, "viewer").await;
+    let (status, body) = send(&app, get("/hosts", &viewer)).await;
+    assert_eq!(status, StatusCode::OK);
+    let hosts = body["hosts"].as_array().unwrap();
+    assert_eq!(hosts.len(), 2);
+    assert_eq!(hosts[0]["name"], "local");
+    assert_eq!(hosts[0]["default"], true);
+    assert_eq!(hosts[0]["status"], "up");
+    // Where and how hosts are reached is for admins only.
+    assert!(hosts.iter().all(|host| host.get("endpoint").is_none() && host.get("tls_key_path").is_none()));
+
+    let admin = token(&app, "admin").await;
+    let (_, body) = send(&app, get("/hosts", &admin)).await;
+    assert!(body["hosts"][0]["endpoint"].is_string());
+    // Nothing listens on port 1, so the host is added but down.
+    let new_host = json!({"name": "lab", "endpoint": "tcp://127.0.0.1:1"});
+    let (status, body) = send(&app, post("/hosts", &admin).set_json(&new_host)).await;
+    assert_eq!(status, StatusCode::CREATED, "{}", body);
+    assert_eq!(body["status"], "down");
+    let (status, _) = send(&app, post("/hosts", &admin).set_json(&new_host)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    // A concurrent request stored the name after the in-memory check passed.
+    let raced = HostConfig {
+        name: "raced".to_string(),
+        endpoint: "tcp://127.0.0.1:1".to_string(),
+        tls_ca_path: None,
+        tls_cert_path: None,
+        tls_key_path: None,
+        ssh_key_path: None,
+        ssh_known_hosts_path: None,
+    };
+    ctx.store.create_host(&raced).unwrap();
+    let (status, body) = send(&app, post("/hosts", &admin).set_json(json!({"name": "raced", "endpoint": "tcp://127.0.0.1:1"}))).await;
+    assert_eq!(status, StatusCode::CONFLICT, "{}", body);
+    let (status, _) = send(&app, post("/hosts", &admin).set_json(json!({"name": "bad", "endpoint": "ftp://x"}))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+
+    let (status, body) = send(&app, get("/hosts/lab/containers", &viewer)).await;
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body["error"], "docker_unavailable");
+    assert_eq!(body["host"], "lab");
+
+    let (status, _) = send(&app, delete("/hosts/lab", &viewer)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let (status, _) = send(&app, delete("/hosts/local", &admin)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = send(&app, delete("/hosts/lab", &admin)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&app, get("/hosts/lab/containers", &viewer)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let (status, _) = send(&app, delete("/hosts/lab", &admin)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn tls_hosts_with_unusable_pem_files_are_refused() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let admin = token(&app, "admin").await;
+    let cases = [
+        ("missing.pem", "tls-client-alice.pem", "tls-client-alice-key.pem", "TLS CA certificate: cannot open"),
+        ("tls-garbage.pem", "tls-client-alice.pem", "tls-client-alice-key.pem", "TLS CA certificate: cannot parse certificates in"),
+        ("tls-ca.pem", "jwt-ed25519.pem", "tls-client-alice-key.pem", "TLS client certificate: no certificates found in"),
+        ("tls-ca.pem", "tls-client-alice.pem", "missing.pem", "TLS client key: cannot open"),
+        ("tls-ca.pem", "tls-client-alice.pem", "tls-ca.pem", "TLS client key: no private key found in"),
+    ];
+    for (ca, cert, key, expected) in cases {
+        let new_host = json!({
+            "name": "secure",
+            "endpoint": "tcp://127.0.0.1:2376",
+            "tls_ca_path": testdata(ca),
+            "tls_cert_path": testdata(cert),
+            "tls_key_path": testdata(key),
+        });
+        let (status, body) = send(&app, post("/hosts", &admin).set_json(new_host)).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST, "{}", body);
+        let message = body.as_str().unwrap();
+        assert!(message.starts_with(&format!("Cannot set up Docker host: {}", expected)), "{}", message);
+    }
+    // Nothing was registered.
+    let (status, _) = send(&app, get("/hosts/secure/containers", &admin)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert!(ctx.store.list_hosts().unwrap().is_empty());
+}
+
+#[actix_web::test]
+async fn hosts_that_go_down_are_retried_with_backoff() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let admin = token(&app, "admin").await;
+    let host = ctx.hosts.get("local").unwrap();
+    ctx.local.fail_checks(2);
+
+    let mut backoff = Duration::from_secs(1);
+    assert_eq!(host.check(&mut backoff).await, Duration::from_secs(1));
+    let response = test::call_service(&app, get("/containers", &admin).to_request()).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(response.headers().get(header::RETRY_AFTER).unwrap(), "1");
+    let body: Value = test::read_body_json(response).await;
+    assert_eq!(body["error"], "docker_unavailable");
+    assert_eq!(body["host"], "local");
+    assert_eq!(body["retry_after"], 1);
+    assert!(body["message"].as_str().unwrap().contains("Connection refused"), "{}", body);
+    let (_, body) = send(&app, get("/hosts", &admin)).await;
+    let local = &body["hosts"][0];
+    assert_eq!(local["status"], "down");
+    assert!(local["retry_at"].as_i64().is_some(), "{}", body);
+
+    // Each failure doubles the wait before the next check.
+    assert_eq!(host.check(&mut backoff).await, Duration::from_secs(2));
+    assert_eq!(backoff, Duration::from_secs(4));
+    let response = test::call_service(&app, get("/containers", &admin).to_request()).await;
+    assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+    let retry_after: i64 = response.headers().get(header::RETRY_AFTER).unwrap().to_str().unwrap().parse().unwrap();
+    assert!((1..=2).contains(&retry_after), "{}", retry_after);
+
+    // The engine answers again: the host reconnects and the backoff resets.
+    host.check(&mut backoff).await;
+    assert_eq!(backoff, Duration::from_secs(1));
+    let (status, body) = send(&app, get("/containers", &admin)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let (_, body) = send(&app, get("/hosts", &admin)).await;
+    assert_eq!(body["hosts"][0]["status"], "up");
+    assert!(body["hosts"][0]["retry_at"].is_null(), "{}", body);
+}
+
+#[actix_web::test]
+async fn containers_are_created_and_started() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    // Named volumes need an admin.
+    let token = token(&app, "admin").await;
+    let spec = json!({
+        "image": "nginx",
+        "name": "shop-web",
+        "command": "nginx -g 'daemon off;'",
+        "env": ["MODE=prod", ""],
+        "ports": ["8080:80", "127.0.0.1::443/tcp", "[::1]:5353:53/udp"],
+        "volumes": ["data:/var/lib/data:ro", "/cache"],
+        "restart_policy": "on-failure:3",
+        "labels": ["com.docker.compose.project=shop", "tier"],
+        "network": "bridge",
+        "memory_mb": 256,
+        "cpus": 0.5,
+    });
+    let (status, body) = send(&app, post("/containers", &token).set_json(spec)).await;
+    assert_eq!(status, StatusCode::CREATED, "{}", body);
+    assert_eq!(body["started"], true);
+    assert_eq!(body["message"], "Container shop-web created and started");
+
+    let container = ctx.local.container(body["id"].as_str().unwrap()).unwrap();
+    assert!(container.running);
+    assert_eq!(container.name, "shop-web");
+    let config = container.config.unwrap();
+    assert_eq!(config.cmd.unwrap(), ["nginx", "-g", "daemon off;"]);
+    assert_eq!(config.env.unwrap(), ["MODE=prod"]);
+    assert_eq!(container.labels["tier"], "");
+    assert!(config.volumes.unwrap().contains_key("/cache"));
+    let host_config = config.host_config.unwrap();
+    assert_eq!(host_config.binds.unwrap(), ["data:/var/lib/data:ro"]);
+    let ports = host_config.port_bindings.unwrap();
+    assert_eq!(ports["80/tcp"].as_ref().unwrap()[0].host_port.as_deref(), Some("8080"));
+    assert_eq!(ports["443/tcp"].as_ref().unwrap()[0].host_port.as_deref(), Some(""));
+    assert_eq!(ports["443/tcp"].as_ref().unwrap()[0].host_ip.as_deref(), Some("127.0.0.1"));
+    assert_eq!(ports["53/udp"].as_ref().unwrap()[0].host_ip.as_deref(), Some("::1"));
+    assert_eq!(host_config.restart_policy.unwrap().maximum_retry_count, Some(3));
+    assert_eq!(host_config.network_mode.as_deref(), Some("bridge"));
+    assert_eq!(host_config.memory, Some(256 * 1024 * 1024));
+    assert_eq!(host_config.nano_cpus, Some(500_000_000));
+
+    let (_, body) = send(&app, get("/audit?user=admin", &token)).await;
+    let actions: Vec<&str> = body["entries"].as_array().unwrap().iter().map(|e| e["action"].as_str().unwrap()).collect();
+    assert_eq!(actions, ["start", "create"]);
+    assert_eq!(body["entries"][1]["container_name"], "shop-web");
+}
+
+#[actix_web::test]
+async fn invalid_container_fields_are_reported_by_name() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let token = token(&app, "ops").await;
+    let spec = json!({
+        "image": " ",
+        "name": "-web",
+        "command": "echo 'oops",
+        "env": ["MODE=prod", "NOVALUE"],
+        "ports": ["http:80"],
+        "volumes": ["relative/path"],
+        "restart_policy": "always:3",
+        "labels": ["=x"],
+        "network": "my net",
+        "memory_mb": 1,
+        "cpus": 0,
+    });
+    let (status, body) = send(&app, post("/containers", &token).set_json(spec)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["error"], "invalid_container");
+    let fields = body["fields"].as_object().unwrap();
+    let names: Vec<&str> = fields.keys().map(String::as_str).collect();
+    assert_eq!(
+        names,
+        ["command", "cpus", "env", "image", "labels", "memory_mb", "name", "network", "ports", "restart_policy", "volumes"]
+    );
+    assert_eq!(fields["env"], "line 2: 'NOVALUE' must look like KEY=value");
+    assert_eq!(fields["ports"], "line 1: invalid host port 'http'");
+    let (status, body) = send(&app, post("/containers", &token).set_json(json!({"image": "nginx", "memory_mb": i64::MAX}))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body["fields"]["memory_mb"].as_str().unwrap().starts_with("must be at most"));
+    assert!(ctx.local.calls().is_empty());
+}
+
+#[actix_web::test]
+async fn only_admins_mount_host_paths() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let spec = json!({"image": "redis", "volumes": ["/etc:/host-etc:ro"], "start": false});
+    let ops = token(&app, "ops").await;
+    let (status, body) = send(&app, post("/containers", &ops).set_json(&spec)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["fields"]["volumes"], "line 1: mounting host paths requires the admin role");
+    let admin = token(&app, "admin").await;
+    let (status, body) = send(&app, post("/containers", &admin).set_json(&spec)).await;
+    assert_eq!(status, StatusCode::CREATED, "{}", body);
+    assert_eq!(body["started"], false);
+    assert!(!ctx.local.container(body["id"].as_str().unwrap()).unwrap().running);
+}
+
+#[actix_web::test]
+async fn missing_images_are_pulled() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let token = token(&app, "ops").await;
+    ctx.local.publish("alpine");
+    let (status, body) = send(&app, post("/containers", &token).set_json(json!({"image": "alpine"}))).await;
+    assert_eq!(status, StatusCode::CREATED, "{}", body);
+    assert_eq!(ctx.local.calls()[..3], ["create alpine", "pull alpine", "create alpine"]);
+
+    let (status, body) = send(&app, post("/containers", &token).set_json(json!({"image": "nope"}))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body["fields"]["image"].as_str().unwrap().contains("repository does not exist"));
+}
+
+#[actix_web::test]
+async fn engine_refusals_are_mapped_to_fields() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let token = token(&app, "ops").await;
+    let (status, body) = send(&app, post("/containers", &token).set_json(json!({"image": "nginx", "name": "web"}))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body["fields"]["name"].as_str().unwrap().contains("already in use"));
+    let admin = self::token(&app, "admin").await;
+    let (status, body) = send(&app, post("/containers", &admin).set_json(json!({"image": "nginx", "network": "backend"}))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["fields"]["network"], "network backend not found");
+}
+
+#[actix_web::test]
+async fn creating_containers_is_limited_by_role_and_policy() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let viewer = token(&app, "viewer").await;
+    let (status, _) = send(&app, post("/containers", &viewer).set_json(json!({"image": "nginx"}))).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+
+    ctx.store.create_policy("ops", Some("shop"), None, Role::Operator).unwrap();
+    let ops = token(&app, "ops").await;
+    let (status, body) = send(&app, post("/containers", &ops).set_json(json!({"image": "nginx"}))).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert!(body["fields"]["labels"].is_string());
+    let spec = json!({"image": "nginx", "labels": ["com.docker.compose.project=shop"], "network": "bridge"});
+    let (status, body) = send(&app, post("/containers", &ops).set_json(spec)).await;
+    assert_eq!(status, StatusCode::CREATED, "{}", body);
+
+    // Other projects' networks and volumes are out of reach too.
+    let spec = json!({
+        "image": "nginx",
+        "labels": ["com.docker.compose.project=shop"],
+        "network": "host",
+        "volumes": ["billing-data:/data"],
+    });
+    let (status, body) = send(&app, post("/containers", &ops).set_json(spec)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(body["fields"]["network"], "networks other than bridge, default, none require the admin role");
+    assert_eq!(body["fields"]["volumes"], "line 1: mounting named volumes requires the admin role");
+    let spec = json!({"image": "nginx", "network": "host", "start": false});
+    let admin = token(&app, "admin").await;
+    let (status, body) = send(&app, post("/containers", &admin).set_json(spec)).await;
+    assert_eq!(status, StatusCode::CREATED, "{}", body);
+}
+
+#[actix_web::test]
+async fn containers_are_created_on_the_chosen_host() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let token = token(&app, "ops").await;
+    let (status, body) = send(&app, post("/hosts/remote/containers", &token).set_json(json!({"image": "busybox", "name": "job"}))).await;
+    assert_eq!(status, StatusCode::CREATED, "{}", body);
+    assert!(ctx.remote.container(body["id"].as_str().unwrap()).is_some());
+    assert!(ctx.local.calls().is_empty());
+}
+
+#[actix_web::test]
+async fn stopped_containers_are_removed() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let token = token(&app, "ops").await;
+    let (status, body) = send(&app, delete("/container/db1?v=true", &token)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["message"], "Container db1 removed");
+    assert!(ctx.local.container("db1").is_none());
+    let (status, body) = send(&app, delete("/container/db1", &token)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(body["error"], "not_found");
+    let (status, _) = send(&app, post("/container/db1/start", &token)).await;
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(ctx.local.calls(), ["remove db1 force=false v=true", "remove db1 force=false v=false", "start db1"]);
+}
+
+#[actix_web::test]
+async fn running_containers_are_only_removed_by_force() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let token = token(&app, "ops").await;
+    let (status, body) = send(&app, delete("/container/web1", &token)).await;
+    assert_eq!(status, StatusCode::CONFLICT, "{}", body);
+    assert_eq!(body["error"], "conflict");
+    assert!(ctx.local.container("web1").is_some());
+    let (status, _) = send(&app, delete("/container/web1?force=true", &token)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(ctx.local.container("web1").is_none());
+    let (status, _) = send(&app, delete("/hosts/remote/container/far1?force=true", &token)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(ctx.remote.container("far1").is_none());
+}
+
+#[actix_web::test]
+async fn removals_are_limited_by_role_and_audited() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let viewer = token(&app, "viewer").await;
+    let (status, body) = send(&app, delete("/container/db1", &viewer)).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert_eq!(body["required_role"], "operator");
+    assert!(ctx.local.container("db1").is_some());
+    let ops = token(&app, "ops").await;
+    send(&app, delete("/container/db1", &ops)).await;
+    let admin = token(&app, "admin").await;
+    let (status, body) = send(&app, get("/audit?container=db", &admin)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let entries = body["entries"].as_array().unwrap();
+    assert_eq!(entries.len(), 1);
+    assert_eq!(entries[0]["action"], "remove");
+    assert_eq!(entries[0]["container_name"], "db");
+    assert_eq!(entries[0]["container_image"], "postgres");
+    assert_eq!(entries[0]["success"], true);
+}
+
+#[actix_web::test]
+async fn stop_and_restart_pass_the_timeout() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let token = token(&app, "ops").await;
+    let (status, body) = send(&app, post("/container/web1/restart?t=30", &token)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    let (status, _) = send(&app, post("/hosts/local/container/web1/stop?t=0", &token)).await;
+    assert_eq!(status, StatusCode::OK);
+    let (status, _) = send(&app, post("/container/web1/stop?t=-5", &token)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = send(&app, post("/container/web1/stop?t=soon", &token)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(ctx.local.calls(), ["restart web1 t=30", "stop web1 t=0"]);
+}
+
+#[actix_web::test]
+async fn containers_are_paused_and_unpaused() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let token = token(&app, "ops").await;
+    let (status, body) = send(&app, post("/container/web1/pause", &token)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["message"], "Container web1 paused");
+    assert!(ctx.local.container("web1").unwrap().paused);
+    let (status, body) = send(&app, post("/container/web1/pause", &token)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(body["error"], "conflict");
+    let (status, _) = send(&app, post("/container/web1/unpause", &token)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!ctx.local.container("web1").unwrap().paused);
+    let (status, _) = send(&app, post("/container/db1/pause", &token)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let (status, _) = send(&app, post("/hosts/remote/container/far1/unpause", &token)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    assert_eq!(ctx.remote.calls(), ["unpause far1"]);
+}
+
+#[actix_web::test]
+async fn containers_are_sent_the_chosen_signal() {
+    let ctx = TestContext::new().await;
+    let app = ctx.app().await;
+    let ops = token(&app, "ops").await;
+    let (status, body) = send(&app, post("/container/web1/kill?signal=hup", &ops)).await;
+    assert_eq!(status, StatusCode::OK, "{}", body);
+    assert_eq!(body["message"], "Sent SIGHUP to container web1");
+    assert!(ctx.local.container("web1").unwrap().running);
+    let (status, _) = send(&app, post("/container/web1/kill?signal=SIGBOGUS", &ops)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = send(&app, post("/container/web1/kill?signal=99", &ops)).await;
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    let (status, _) = send(&app, post("/container/web1/kill", &ops)).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(!ctx.local.container("web1").unwrap().running);
+    assert_eq!(ctx.local.container("web1").unwrap().signals, ["SIGHUP", "SIGKILL"]);
+    let (status, _) = send(&app, post("/container/web1/kill", &ops)).await;
+    assert_eq!(status, StatusCode::CONFLICT);
+    let admin = token(&app, "admin").await;
+    let (_, body) = send(&app, get("/audit?container=web", &admin)).await;
+    let actions: Vec<&str> = body["entries"].as_array().unwrap().iter().map(|e| e["action"].as_str().unwrap()).collect();
+    assert_eq!(actions, ["kill SIGKILL", "kill SIGKILL", "kill SIGHUP"]);
+}
@@ -0,0 +1,191 @@
+use std::collections::HashMap;
+use std::env;
+use std::sync::Mutex;
+use actix_web::{http::header, HttpResponse};
+use serde::Serialize;
+use crate::audit::{self, AuditEntry};
+use crate::db::{self, Store};
+
+fn env_or(name: &str, default: i64) -> i64 {
+    env::var(name).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    User(String),
+    Ip(String),
+}
+
+#[derive(Debug, Default)]
+struct Attempts {
+    failures: i64,
+    last_failure: i64,
+    locked_until: i64,
+}
+
+/// Tracks failed logins per username and per client IP. Once a key reaches
+/// its limit it is locked out, and every further failure doubles the lockout.
+pub struct LoginThrottle {
+    user_max_failures: i64,
+    ip_max_failures: i64,
+    base_lockout: i64,
+    max_lockout: i64,
+    /// Failures older than this are forgotten.
+    window: i64,
+    attempts: Mutex<HashMap<Key, Attempts>>,
+}
+
+#[derive(Debug, Serialize)]
+struct LockedOutBody {
+    error: &'static str,
+    message: String,
+    retry_after: i64,
+}
+
+impl LoginThrottle {
+    pub fn from_env() -> LoginThrottle {
+        LoginThrottle {
+            user_max_failures: env_or("LOGIN_MAX_FAILURES", 5),
+            ip_max_failures: env_or("LOGIN_IP_MAX_FAILURES", 20),
+            base_lockout: env_or("LOGIN_LOCKOUT_SECS", 60),
+            max_lockout: env_or("LOGIN_MAX_LOCKOUT_SECS", 3600),
+            window: env_or("LOGIN_FAILURE_WINDOW_SECS", 15 * 60),
+            attempts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    fn keys(username: &str, ip: Option<&str>) -> Vec<Key> {
+        let mut keys = vec![Key::User(username.to_string())];
+        keys.extend(ip.map(|ip| Key::Ip(ip.to_string())));
+        keys
+    }
+
+    /// Seconds until the caller may try again, if either the username or the
+    /// IP is currently locked out.
+    pub fn retry_after(&self, username: &str, ip: Option<&str>) -> Option<i64> {
+        let attempts = self.attempts.lock().unwrap_or_else(|e| e.into_inner());
+        let now = db::now();
+        Self::keys(username, ip)
+            .iter()
+            .filter_map(|key| attempts.get(key))
+            .map(|a| a.locked_until - now)
+            .filter(|secs| *secs > 0)
+            .max()
+    }
+
+    /// Counts a failed login. Returns the lockout length in seconds when this
+    /// failure caused a new lockout.
+    pub fn record_failure(&self, username: &str, ip: Option<&str>) -> Option<i64> {
+        let mut attempts = self.attempts.lock().unwrap_or_else(|e| e.into_inner());
+        let now = db::now();
+        // Keep the map bounded to keys that still matter.
+        attempts.retain(|_, a| a.locked_until > now || now - a.last_failure < self.window);
+        let mut lockout = None;
+        for key in Self::keys(username, ip) {
+            let max_failures = match key {
+                Key::User(_) => self.user_max_failures,
+                Key::Ip(_) => self.ip_max_failures,
+            };
+            let entry = attempts.entry(key).or_default();
+            entry.failures += 1;
+            entry.last_failure = now;
+            if entry.failures >= max_failures {
+                let doublings = (entry.failures - max_failures).min(30) as u32;
+                let secs = self.base_lockout.saturating_mul(1 << doublings).min(self.max_lockout);
+                entry.locked_until = now + secs;
+                lockout = lockout.max(Some(secs));
+            }
+        }
+        lockout
+    }
+
+    /// Counts a failed password or second-factor check like
+    /// `record_failure`, auditing any lockout it causes. Returns the response
+    /// to send when the caller is now locked out.
+    pub fn record_failed_login(&self, store: &Store, username: &str, ip: Option<String>) -> Option<HttpResponse> {
+        let lockout = self.record_failure(username, ip.as_deref())?;
+        audit::record(store, AuditEntry {
+            username: username.to_string(),
+            action: "login_lockout".to_string(),
+            ip,
+            success: false,
+            error: Some(format!("Locked out for {} seconds after repeated failed logins", lockout)),
+            ..Default::default()
+        });
+        Some(too_many_requests(lockout))
+    }
+
+    /// Forgets failures for `username` once a login has issued a session. The IP
+    /// counter is kept so one valid account cannot reset it.
+    pub fn record_success(&self, username: &str) {
+        self.attempts
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .remove(&Key::User(username.to_string()));
+    }
+}
+
+pub fn too_many_requests(retry_after: i64) -> HttpResponse {
+    HttpResponse::TooManyRequests()
+        .insert_header((header::RETRY_AFTER, retry_after.to_string()))
+        .json(LockedOutBody {
+            error: "too_many_requests",
+            message: format!("Too many failed logins, try again in {} seconds", retry_after),
+            retry_after,
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn throttle() -> LoginThrottle {
+        LoginThrottle {
+            user_max_failures: 3,
+            ip_max_failures: 5,
+            base_lockout: 60,
+            max_lockout: 300,
+            window: 15 * 60,
+            attempts: Mutex::new(HashMap::new()),
+        }
+    }
+
+    #[test]
+    fn lockouts_double_up_to_the_maximum() {
+        let throttle = throttle();
+        let ip = Some("10.0.0.1");
+        assert_eq!(throttle.record_failure("alice", ip), None);
+        assert_eq!(throttle.record_failure("alice", ip), None);
+        assert_eq!(throttle.retry_after("alice", ip), None);
+        assert_eq!(throttle.record_failure("alice", ip), Some(60));
+        assert!(throttle.retry_after("alice", None).is_some_and(|secs| secs > 55 && secs <= 60));
+        assert_eq!(throttle.record_failure("alice", ip), Some(120));
+        assert_eq!(throttle.record_failure("alice", ip), Some(240));
+        assert_eq!(throttle.record_failure("alice", ip), Some(300));
+        assert_eq!(throttle.record_failure("alice", ip), Some(300));
+    }
+
+    #[test]
+    fn an_address_is_locked_out_across_usernames() {
+        let throttle = throttle();
+        for username in ["a", "b", "c", "d"] {
+            assert_eq!(throttle.record_failure(username, Some("10.0.0.1")), None);
+        }
+        assert_eq!(throttle.record_failure("e", Some("10.0.0.1")), Some(60));
+        assert!(throttle.retry_after("f", Some("10.0.0.1")).is_some());
+        assert_eq!(throttle.retry_after("f", Some("10.0.0.2")), None);
+    }
+
+    #[test]
+    fn success_forgets_the_user_but_not_the_address() {
+        let throttle = throttle();
+        for _ in 0..3 {
+            throttle.record_failure("alice", Some("10.0.0.1"));
+        }
+        throttle.record_failure("bob", Some("10.0.0.1"));
+        throttle.record_success("alice");
+        assert_eq!(throttle.retry_after("alice", None), None);
+        // The fifth failure from the address locks it out.
+        assert_eq!(throttle.record_failure("alice", Some("10.0.0.1")), Some(60));
+    }
+}
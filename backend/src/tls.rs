@@ -0,0 +1,301 @@
+use std::any::Any;
+use std::env;
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::sync::{Arc, RwLock};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use actix_tls::accept::rustls_0_23::TlsStream;
+use actix_web::dev::{Extensions, ServiceRequest};
+use actix_web::rt::net::TcpStream;
+use rustls::crypto::ring::{default_provider, sign::any_supported_type};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::{ClientHello, ResolvesServerCert, WebPkiClientVerifier};
+use rustls::sign::CertifiedKey;
+use rustls::{RootCertStore, ServerConfig};
+use simple_asn1::ASN1Block;
+use crate::db::Store;
+use crate::Identity;
+
+/// HTTPS settings, read from `TLS_*` variables. Only enabled when both
+/// `TLS_CERT_PATH` and `TLS_KEY_PATH` are set.
+pub struct TlsConfig {
+    cert_path: String,
+    key_path: String,
+    /// CA bundle that client certificates must chain to (`TLS_CLIENT_CA_PATH`).
+    client_ca_path: Option<String>,
+    /// Refuse handshakes without a client certificate instead of falling
+    /// back to token authentication (`TLS_CLIENT_AUTH=required`).
+    client_auth_required: bool,
+    reload_interval: Duration,
+}
+
+pub fn read_certs(path: &str) -> Result<Vec<CertificateDer<'static>>, String> {
+    let file = File::open(path).map_err(|e| format!("cannot open {}: {}", path, e))?;
+    let certs = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("cannot parse certificates in {}: {}", path, e))?;
+    if certs.is_empty() {
+        return Err(format!("no certificates found in {}", path));
+    }
+    Ok(certs)
+}
+
+pub fn read_private_key(path: &str) -> Result<PrivateKeyDer<'static>, String> {
+    let file = File::open(path).map_err(|e| format!("cannot open {}: {}", path, e))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .map_err(|e| format!("cannot parse private key in {}: {}", path, e))?
+        .ok_or_else(|| format!("no private key found in {}", path))
+}
+
+fn load_certified_key(cert_path: &str, key_path: &str) -> Result<CertifiedKey, String> {
+    let certs = read_certs(cert_path)?;
+    let key = read_private_key(key_path)?;
+    let key = any_supported_type(&key).map_err(|e| format!("unsupported private key in {}: {}", key_path, e))?;
+    let certified = CertifiedKey::new(certs, key);
+    certified
+        .keys_match()
+        .map_err(|e| format!("{} does not match {}: {}", key_path, cert_path, e))?;
+    Ok(certified)
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Serves the current certificate and swaps in a new one when the files on
+/// disk change, so renewals need no restart.
+#[derive(Debug)]
+struct ReloadingCert {
+    current: RwLock<Arc<CertifiedKey>>,
+}
+
+impl ResolvesServerCert for ReloadingCert {
+    fn resolve(&self, _client_hello: ClientHello<'_>) -> Option<Arc<CertifiedKey>> {
+        Some(self.current.read().unwrap_or_else(|e| e.into_inner()).clone())
+    }
+}
+
+impl TlsConfig {
+    pub fn from_env() -> Result<Option<TlsConfig>, String> {
+        let var = |name: &str| env::var(name).ok().filter(|v| !v.is_empty());
+        let (cert_path, key_path) = match (var("TLS_CERT_PATH"), var("TLS_KEY_PATH")) {
+            (Some(cert), Some(key)) => (cert, key),
+            (None, None) => return Ok(None),
+            _ => return Err("TLS_CERT_PATH and TLS_KEY_PATH must be set together".to_string()),
+        };
+        let client_ca_path = var("TLS_CLIENT_CA_PATH");
+        let client_auth_required = match var("TLS_CLIENT_AUTH").as_deref() {
+            None | Some("optional") => false,
+            Some("required") => true,
+            Some(other) => return Err(format!("TLS_CLIENT_AUTH must be 'optional' or 'required', not '{}'", other)),
+        };
+        if client_auth_required && client_ca_path.is_none() {
+            return Err("TLS_CLIENT_AUTH=required needs TLS_CLIENT_CA_PATH".to_string());
+        }
+        let reload_interval = var("TLS_RELOAD_INTERVAL_SECS").and_then(|v| v.parse().ok()).unwrap_or(30);
+        Ok(Some(TlsConfig {
+            cert_path,
+            key_path,
+            client_ca_path,
+            client_auth_required,
+            reload_interval: Duration::from_secs(reload_interval),
+        }))
+    }
+
+    /// Builds the rustls configuration and starts watching the certificate
+    /// and key for changes.
+    pub fn server_config(&self) -> Result<ServerConfig, String> {
+        let provider = Arc::new(default_provider());
+        let resolver = Arc::new(ReloadingCert {
+            current: RwLock::new(Arc::new(load_certified_key(&self.cert_path, &self.key_path)?)),
+        });
+        let builder = ServerConfig::builder_with_provider(provider.clone())
+            .with_safe_default_protocol_versions()
+            .map_err(|e| e.to_string())?;
+        let builder = match &self.client_ca_path {
+            Some(ca_path) => {
+                let mut roots = RootCertStore::empty();
+                for cert in read_certs(ca_path)? {
+                    roots.add(cert).map_err(|e| format!("invalid CA certificate in {}: {}", ca_path, e))?;
+                }
+                let verifier = WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider);
+                let verifier = if self.client_auth_required {
+                    verifier
+                } else {
+                    verifier.allow_unauthenticated()
+                };
+                builder.with_client_cert_verifier(verifier.build().map_err(|e| e.to_string())?)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let config = builder.with_cert_resolver(resolver.clone());
+        self.watch(resolver);
+        Ok(config)
+    }
+
+    fn watch(&self, resolver: Arc<ReloadingCert>) {
+        let (cert_path, key_path, interval) = (self.cert_path.clone(), self.key_path.clone(), self.reload_interval);
+        thread::spawn(move || {
+            let mut seen = (modified(&cert_path), modified(&key_path));
+            loop {
+                thread::sleep(interval);
+                let now = (modified(&cert_path), modified(&key_path));
+                if now == seen {
+                    continue;
+                }
+                // Renewals may write the two files separately; a failed load
+                // is retried on the next tick and the old pair stays live.
+                match load_certified_key(&cert_path, &key_path) {
+                    Ok(certified) => {
+                        *resolver.current.write().unwrap_or_else(|e| e.into_inner()) = Arc::new(certified);
+                        log::info!("reloaded TLS certificate from {}", cert_path);
+                        seen = now;
+                    }
+                    Err(e) => log::warn!("keeping the current TLS certificate: {}", e),
+                }
+            }
+        });
+    }
+}
+
+/// The verified client certificate of a mutual-TLS connection.
+#[derive(Debug, Clone)]
+struct ClientCert {
+    common_name: Option<String>,
+}
+
+/// Extracts the subject common name (OID 2.5.4.3) from a DER certificate.
+fn common_name(der: &[u8]) -> Option<String> {
+    let blocks = simple_asn1::from_der(der).ok()?;
+    let Some(ASN1Block::Sequence(_, cert)) = blocks.first() else { return None };
+    let Some(ASN1Block::Sequence(_, tbs)) = cert.first() else { return None };
+    // Skip the optional [0] version: serial, signature, issuer, validity, subject.
+    let mut fields = tbs.iter().skip_while(|block| matches!(block, ASN1Block::Explicit(..)));
+    let Some(ASN1Block::Sequence(_, subject)) = fields.nth(4) else { return None };
+    let attributes = subject.iter().filter_map(|rdn| match rdn {
+        ASN1Block::Set(_, attributes) => Some(attributes),
+        _ => None,
+    });
+    attributes.flatten().find_map(|attribute| {
+        let ASN1Block::Sequence(_, pair) = attribute else { return None };
+        let [ASN1Block::ObjectIdentifier(_, oid), value] = pair.as_slice() else { return None };
+        if oid.as_vec::<u64>().ok()? != [2, 5, 4, 3] {
+            return None;
+        }
+        match value {
+            ASN1Block::UTF8String(_, s) | ASN1Block::PrintableString(_, s) | ASN1Block::IA5String(_, s) => Some(s.clone()),
+            _ => None,
+        }
+    })
+}
+
+/// `HttpServer::on_connect` hook that keeps the client certificate, if any,
+/// with the connection so `client_cert_identity` can read it.
+pub fn on_connect(conn: &dyn Any, data: &mut Extensions) {
+    let Some(tls) = conn.downcast_ref::<TlsStream<TcpStream>>() else { return };
+    if let Some(cert) = tls.get_ref().1.peer_certificates().and_then(|certs| certs.first()) {
+        data.insert(ClientCert {
+            common_name: common_name(cert.as_ref()),
+        });
+    }
+}
+
+/// Maps a verified client certificate to the local user named by its common
+/// name. Returns `None` when the connection presented no certificate.
+pub fn client_cert_identity(store: &Store, req: &ServiceRequest) -> Option<Result<Identity, &'static str>> {
+    let cert = req.conn_data::<ClientCert>()?;
+    let Some(common_name) = &cert.common_name else {
+        return Some(Err("Client certificate has no common name"));
+    };
+    Some(match store.find_user(common_name) {
+        Ok(Some(user)) if !user.disabled => Ok(Identity {
+            username: user.username,
+            role: user.role,
+            session_id: None,
+            api_key_id: None,
+        }),
+        Ok(Some(_)) => Err("Account is disabled"),
+        _ => Err("Client certificate does not match a dashboard user"),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testdata(file: &str) -> String {
+        format!("{}/testdata/{}", env!("CARGO_MANIFEST_DIR"), file)
+    }
+
+    fn config(client_ca_path: Option<&str>, client_auth_required: bool) -> TlsConfig {
+        TlsConfig {
+            cert_path: testdata("tls-server.pem"),
+            key_path: testdata("tls-server-key.pem"),
+            client_ca_path: client_ca_path.map(testdata),
+            client_auth_required,
+            reload_interval: Duration::from_secs(3600),
+        }
+    }
+
+    #[test]
+    fn common_name_is_read_from_the_subject() {
+        let cert = &read_certs(&testdata("tls-client-alice.pem")).unwrap()[0];
+        assert_eq!(common_name(cert.as_ref()).as_deref(), Some("alice"));
+        let cert = &read_certs(&testdata("tls-server.pem")).unwrap()[0];
+        assert_eq!(common_name(cert.as_ref()).as_deref(), Some("localhost"));
+    }
+
+    #[test]
+    fn common_name_is_none_when_missing_or_unparsable() {
+        let cert = &read_certs(&testdata("tls-client-nameless.pem")).unwrap()[0];
+        assert_eq!(common_name(cert.as_ref()), None);
+        assert_eq!(common_name(b"not a certificate"), None);
+        assert_eq!(common_name(&[]), None);
+    }
+
+    #[test]
+    fn server_config_checks_the_certificate_and_key() {
+        assert!(config(None, false).server_config().is_ok());
+        assert!(config(Some("tls-ca.pem"), false).server_config().is_ok());
+        assert!(config(Some("tls-ca.pem"), true).server_config().is_ok());
+
+        let mismatched = TlsConfig {
+            key_path: testdata("tls-client-alice-key.pem"),
+            ..config(None, false)
+        };
+        let error = mismatched.server_config().unwrap_err();
+        assert!(error.contains("does not match"), "{}", error);
+        let missing = TlsConfig {
+            cert_path: testdata("missing.pem"),
+            ..config(None, false)
+        };
+        assert!(missing.server_config().unwrap_err().starts_with("cannot open"));
+        // A file without certificates is refused rather than trusting nothing.
+        let error = config(Some("jwt-ed25519.pem"), false).server_config().unwrap_err();
+        assert!(error.starts_with("no certificates found"), "{}", error);
+    }
+
+    /// Runs every `from_env` case in one test, since they share variables.
+    #[test]
+    fn from_env_validates_the_settings() {
+        let clear = || {
+            for name in ["TLS_CERT_PATH", "TLS_KEY_PATH", "TLS_CLIENT_CA_PATH", "TLS_CLIENT_AUTH"] {
+                env::remove_var(name);
+            }
+        };
+        clear();
+        assert!(TlsConfig::from_env().unwrap().is_none());
+        env::set_var("TLS_CERT_PATH", testdata("tls-server.pem"));
+        assert!(TlsConfig::from_env().is_err());
+        env::set_var("TLS_KEY_PATH", testdata("tls-server-key.pem"));
+        assert!(TlsConfig::from_env().unwrap().is_some());
+        env::set_var("TLS_CLIENT_AUTH", "required");
+        assert!(TlsConfig::from_env().is_err());
+        env::set_var("TLS_CLIENT_CA_PATH", testdata("tls-ca.pem"));
+        assert!(TlsConfig::from_env().unwrap().unwrap().client_auth_required);
+        env::set_var("TLS_CLIENT_AUTH", "sometimes");
+        assert!(TlsConfig::from_env().is_err());
+        clear();
+    }
+}
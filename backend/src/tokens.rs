@@ -0,0 +1,263 @@
+use std::env;
+use actix_web::cookie::{time::Duration, Cookie, SameSite};
+use actix_web::{error, web, HttpRequest, HttpResponse, Responder};
+use rand::RngCore;
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use crate::db::{self, Store};
+use crate::keys::KeySet;
+use crate::sessions;
+use crate::users::UserRecord;
+
+/// Cookie carrying the access token in session mode.
+pub const SESSION_COOKIE: &str = "dd_session";
+const REFRESH_COOKIE: &str = "dd_refresh";
+/// Header that must echo the session's CSRF token on state-changing
+/// requests authenticated by `SESSION_COOKIE`.
+pub const CSRF_HEADER: &str = "X-CSRF-Token";
+
+pub fn access_token_ttl() -> i64 {
+    env::var("ACCESS_TOKEN_TTL_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(15 * 60)
+}
+
+pub fn refresh_token_ttl() -> i64 {
+    env::var("REFRESH_TOKEN_TTL_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(7 * 24 * 3600)
+}
+
+pub fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+pub fn hash_token(token: &str) -> String {
+    hex::encode(Sha256::digest(token.as_bytes()))
+}
+
+impl Store {
+    pub fn insert_refresh_token(&self, token_hash: &str, username: &str, session_id: &str, expires_at: i64) -> rusqlite::Result<()> {
+        self.conn().execute(
+            "INSERT INTO refresh_tokens (token_hash, username, session_id, expires_at, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![token_hash, username, session_id, expires_at, db::now()],
+        )?;
+        Ok(())
+    }
+
+    /// Revokes a live refresh token and returns the user and session it
+    /// belonged to, so each refresh token can only be exchanged once.
+    pub fn take_refresh_token(&self, token_hash: &str) -> rusqlite::Result<Option<(String, Option<String>)>> {
+        let conn = self.conn();
+        let owner = conn
+            .query_row(
+                "SELECT username, session_id FROM refresh_tokens WHERE token_hash = ?1 AND revoked = 0 AND expires_at > ?2",
+                params![token_hash, db::now()],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        conn.execute("UPDATE refresh_tokens SET revoked = 1 WHERE token_hash = ?1", params![token_hash])?;
+        Ok(owner)
+    }
+
+    pub fn revoke_jti(&self, jti: &str, expires_at: i64) -> rusqlite::Result<()> {
+        let conn = self.conn();
+        conn.execute("DELETE FROM revoked_tokens WHERE expires_at < ?1", params![db::now()])?;
+        conn.execute(
+            "INSERT OR IGNORE INTO revoked_tokens (jti, expires_at) VALUES (?1, ?2)",
+            params![jti, expires_at],
+        )?;
+        Ok(())
+    }
+
+    pub fn is_jti_revoked(&self, jti: &str) -> rusqlite::Result<bool> {
+        self.conn().query_row(
+            "SELECT EXISTS(SELECT 1 FROM revoked_tokens WHERE jti = ?1)",
+            params![jti],
+            |row| row.get(0),
+        )
+    }
+
+    /// Invalidates every access and refresh token issued to `username` so far.
+    pub fn revoke_user_tokens(&self, username: &str) -> rusqlite::Result<bool> {
+        let conn = self.conn();
+        conn.execute("UPDATE refresh_tokens SET revoked = 1 WHERE username = ?1", params![username])?;
+        conn.execute("UPDATE sessions SET revoked = 1 WHERE username = ?1", params![username])?;
+        let n = conn.execute(
+            "UPDATE users SET tokens_valid_after = ?2 WHERE username = ?1",
+            params![username, db::now()],
+        )?;
+        Ok(n > 0)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct LoginResponse {
+    pub token: String,
+    pub refresh_token: String,
+    pub expires_in: i64,
+    /// The tokens only work for `/auth/password` until this is resolved.
+    pub password_change_required: bool,
+    pub message: String,
+}
+
+/// Mints an access token plus a fresh refresh token for `user` in session
+/// `session_id`.
+pub fn issue_tokens(store: &Store, keys: &KeySet, user: &UserRecord, session_id: &str, message: &str) -> Result<LoginResponse, actix_web::Error> {
+    let token = crate::create_jwt(keys, &user.username, user.role, Some(session_id), None).map_err(error::ErrorInternalServerError)?;
+    let refresh_token = new_refresh_token(store, &user.username, session_id)?;
+    Ok(LoginResponse {
+        token,
+        refresh_token,
+        expires_in: access_token_ttl(),
+        password_change_required: user.must_change_password,
+        message: message.to_string(),
+    })
+}
+
+fn new_refresh_token(store: &Store, username: &str, session_id: &str) -> Result<String, actix_web::Error> {
+    let refresh_token = random_token();
+    let expires_at = db::now() + refresh_token_ttl();
+    store
+        .insert_refresh_token(&hash_token(&refresh_token), username, session_id, expires_at)
+        .and_then(|_| store.extend_session(session_id, expires_at))
+        .map_err(error::ErrorInternalServerError)?;
+    Ok(refresh_token)
+}
+
+/// Body of a session-mode login. The tokens travel only in HttpOnly cookies.
+#[derive(Debug, Serialize)]
+pub struct SessionResponse {
+    pub csrf_token: String,
+    pub expires_in: i64,
+    pub password_change_required: bool,
+    pub message: String,
+}
+
+/// Whether cookies get the `Secure` flag; `SESSION_COOKIE_SECURE=false`
+/// allows plain-HTTP development setups.
+pub fn secure_cookies() -> bool {
+    env::var("SESSION_COOKIE_SECURE").map(|v| v != "false" && v != "0").unwrap_or(true)
+}
+
+fn session_cookie(name: &'static str, value: String, path: &'static str, max_age: i64) -> Cookie<'static> {
+    let same_site = match env::var("SESSION_COOKIE_SAMESITE").unwrap_or_default().to_lowercase().as_str() {
+        "lax" => SameSite::Lax,
+        "none" => SameSite::None,
+        _ => SameSite::Strict,
+    };
+    Cookie::build(name, value)
+        .path(path)
+        .http_only(true)
+        .secure(secure_cookies())
+        .same_site(same_site)
+        .max_age(Duration::seconds(max_age))
+        .finish()
+}
+
+/// Like `issue_tokens`, but sets the access and refresh tokens as HttpOnly
+/// cookies and binds the access token to a new CSRF token.
+pub fn issue_session(store: &Store, keys: &KeySet, user: &UserRecord, session_id: &str, message: &str) -> Result<HttpResponse, actix_web::Error> {
+    let csrf_token = random_token();
+    let token = crate::create_jwt(keys, &user.username, user.role, Some(session_id), Some(hash_token(&csrf_token)))
+        .map_err(error::ErrorInternalServerError)?;
+    let refresh_token = new_refresh_token(store, &user.username, session_id)?;
+    Ok(HttpResponse::Ok()
+        .cookie(session_cookie(SESSION_COOKIE, token, "/", access_token_ttl()))
+        .cookie(session_cookie(REFRESH_COOKIE, refresh_token, "/auth", refresh_token_ttl()))
+        .json(SessionResponse {
+            csrf_token,
+            expires_in: access_token_ttl(),
+            password_change_required: user.must_change_password,
+            message: message.to_string(),
+        }))
+}
+
+/// Answers a successful login with tokens in the body or, if the client
+/// asked for session mode, with cookies.
+pub fn login_response(store: &Store, keys: &KeySet, user: &UserRecord, session_id: &str, message: &str, cookie: bool) -> Result<HttpResponse, actix_web::Error> {
+    if cookie {
+        issue_session(store, keys, user, session_id, message)
+    } else {
+        Ok(HttpResponse::Ok().json(issue_tokens(store, keys, user, session_id, message)?))
+    }
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RefreshRequest {
+    /// Omitted in session mode, where the refresh cookie is used instead.
+    refresh_token: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct LogoutRequest {
+    refresh_token: Option<String>,
+}
+
+pub async fn refresh(req: HttpRequest, store: web::Data<Store>, keys: web::Data<KeySet>, body: Option<web::Json<RefreshRequest>>) -> Result<impl Responder, actix_web::Error> {
+    let (refresh_token, cookie) = match body.and_then(|b| b.into_inner().refresh_token) {
+        Some(refresh_token) => (refresh_token, false),
+        None => match req.cookie(REFRESH_COOKIE) {
+            Some(cookie) => (cookie.value().to_string(), true),
+            None => return Err(error::ErrorUnauthorized("Invalid refresh token")),
+        },
+    };
+    let (username, session_id) = store
+        .take_refresh_token(&hash_token(&refresh_token))
+        .map_err(error::ErrorInternalServerError)?
+        .ok_or_else(|| error::ErrorUnauthorized("Invalid refresh token"))?;
+    // Pick up role changes and refuse accounts disabled since the last login.
+    let user = match store.find_user(&username).map_err(error::ErrorInternalServerError)? {
+        Some(user) if !user.disabled => user,
+        _ => return Err(error::ErrorUnauthorized("Invalid refresh token")),
+    };
+    // Tokens from before sessions were tracked start one now.
+    let session_id = match session_id {
+        Some(session_id) => session_id,
+        None => sessions::start(&store, &req, &user.username)?,
+    };
+    login_response(&store, &keys, &user, &session_id, "Token refreshed", cookie)
+}
+
+pub async fn logout(req: HttpRequest, store: web::Data<Store>, keys: web::Data<KeySet>, body: Option<web::Json<LogoutRequest>>) -> Result<impl Responder, actix_web::Error> {
+    let bearer = req
+        .headers()
+        .get("Authorization")
+        .and_then(|h| h.to_str().ok())
+        .and_then(|h| h.strip_prefix("Bearer "))
+        .map(str::to_string)
+        .or_else(|| req.cookie(SESSION_COOKIE).map(|c| c.value().to_string()));
+    if let Some(token) = bearer {
+        if let Ok(claims) = crate::verify_jwt(&store, &keys, &token) {
+            store.revoke_jti(&claims.jti, claims.exp as i64).map_err(error::ErrorInternalServerError)?;
+            if let Some(session_id) = &claims.sid {
+                store.revoke_session(session_id).map_err(error::ErrorInternalServerError)?;
+            }
+        }
+    }
+    let refresh_token = body
+        .and_then(|b| b.into_inner().refresh_token)
+        .or_else(|| req.cookie(REFRESH_COOKIE).map(|c| c.value().to_string()));
+    if let Some(refresh_token) = refresh_token {
+        store.take_refresh_token(&hash_token(&refresh_token)).map_err(error::ErrorInternalServerError)?;
+    }
+    let mut response = HttpResponse::Ok();
+    for (name, path) in [(SESSION_COOKIE, "/"), (REFRESH_COOKIE, "/auth")] {
+        response.cookie(session_cookie(name, String::new(), path, 0));
+    }
+    Ok(response.json(crate::ApiResponse {
+        message: "Logged out".to_string(),
+        docker_info: None,
+        containers: None,
+    }))
+}
+
+pub async fn revoke_user_tokens(store: web::Data<Store>, username: web::Path<String>) -> Result<impl Responder, actix_web::Error> {
+    if !store.revoke_user_tokens(&username).map_err(error::ErrorInternalServerError)? {
+        return Err(error::ErrorNotFound(format!("User {} not found", username)));
+    }
+    Ok(web::Json(crate::ApiResponse {
+        message: format!("Tokens for {} revoked", username),
+        docker_info: None,
+        containers: None,
+    }))
+}
@@ -0,0 +1,270 @@
+use actix_web::{error, web, HttpResponse, Responder};
+use bcrypt::{hash, DEFAULT_COST};
+use rusqlite::{params, OptionalExtension, Row};
+use serde::{Deserialize, Serialize};
+use crate::db::{self, Store};
+use crate::passwords;
+use crate::rbac::Role;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct UserRecord {
+    pub id: i64,
+    pub username: String,
+    #[serde(skip_serializing)]
+    pub password_hash: String,
+    pub role: Role,
+    /// `local` for password accounts, `oidc` for accounts provisioned by SSO.
+    pub auth_source: String,
+    #[serde(skip_serializing)]
+    pub totp_secret: Option<String>,
+    pub totp_enabled: bool,
+    /// Set by an admin password reset; cleared by `/auth/password`.
+    pub must_change_password: bool,
+    pub disabled: bool,
+    pub created_at: i64,
+    #[serde(skip_serializing)]
+    pub tokens_valid_after: i64,
+}
+
+impl UserRecord {
+    fn from_row(row: &Row) -> rusqlite::Result<UserRecord> {
+        Ok(UserRecord {
+            id: row.get("id")?,
+            username: row.get("username")?,
+            password_hash: row.get("password_hash")?,
+            role: row.get("role")?,
+            auth_source: row.get("auth_source")?,
+            totp_secret: row.get("totp_secret")?,
+            totp_enabled: row.get("totp_enabled")?,
+            must_change_password: row.get("must_change_password")?,
+            disabled: row.get("disabled")?,
+            created_at: row.get("created_at")?,
+            tokens_valid_after: row.get("tokens_valid_after")?,
+        })
+    }
+}
+
+const USER_COLUMNS: &str = "id, username, password_hash, role, auth_source, totp_secret, totp_enabled, must_change_password, disabled, created_at, tokens_valid_after";
+
+impl Store {
+    pub fn create_user(&self, username: &str, password_hash: &str, role: Role) -> rusqlite::Result<UserRecord> {
+        self.create_user_with_source(username, password_hash, role, "local")
+    }
+
+    pub fn create_user_with_source(&self, username: &str, password_hash: &str, role: Role, auth_source: &str) -> rusqlite::Result<UserRecord> {
+        let conn = self.conn();
+        conn.execute(
+            "INSERT INTO users (username, password_hash, role, auth_source, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![username, password_hash, role, auth_source, db::now()],
+        )?;
+        conn.query_row(
+            &format!("SELECT {} FROM users WHERE id = ?1", USER_COLUMNS),
+            params![conn.last_insert_rowid()],
+            UserRecord::from_row,
+        )
+    }
+
+    pub fn find_user(&self, username: &str) -> rusqlite::Result<Option<UserRecord>> {
+        self.conn()
+            .query_row(
+                &format!("SELECT {} FROM users WHERE username = ?1", USER_COLUMNS),
+                params![username],
+                UserRecord::from_row,
+            )
+            .optional()
+    }
+
+    pub fn list_users(&self) -> rusqlite::Result<Vec<UserRecord>> {
+        let conn = self.conn();
+        let mut stmt = conn.prepare(&format!("SELECT {} FROM users ORDER BY id", USER_COLUMNS))?;
+        let users = stmt.query_map([], UserRecord::from_row)?.collect();
+        users
+    }
+
+    pub fn count_users(&self) -> rusqlite::Result<i64> {
+        self.conn().query_row("SELECT COUNT(*) FROM users", [], |row| row.get(0))
+    }
+
+    /// Returns false if no such user exists.
+    pub fn delete_user(&self, username: &str) -> rusqlite::Result<bool> {
+        let n = self.conn().execute("DELETE FROM users WHERE username = ?1", params![username])?;
+        Ok(n > 0)
+    }
+
+    /// Returns false if no such user exists.
+    pub fn set_user_disabled(&self, username: &str, disabled: bool) -> rusqlite::Result<bool> {
+        let n = self.conn().execute(
+            "UPDATE users SET disabled = ?2 WHERE username = ?1",
+            params![username, disabled],
+        )?;
+        Ok(n > 0)
+    }
+
+    /// Changes the role and invalidates every token issued so far, since
+    /// tokens carry the role they were issued with. Returns false if no such
+    /// user exists.
+    pub fn set_user_role(&self, username: &str, role: Role) -> rusqlite::Result<bool> {
+        let n = self.conn().execute(
+            "UPDATE users SET role = ?2 WHERE username = ?1",
+            params![username, role],
+        )?;
+        if n > 0 {
+            self.revoke_user_tokens(username)?;
+        }
+        Ok(n > 0)
+    }
+}
+
+pub async fn hash_password(password: String) -> Result<String, actix_web::Error> {
+    web::block(move || hash(password, DEFAULT_COST))
+        .await?
+        .map_err(error::ErrorInternalServerError)
+}
+
+/// Creates the initial admin account from `ADMIN_USERNAME`/`ADMIN_PASSWORD`
+/// when the user table is still empty. The password must meet the policy
+/// and, since it sits in the environment, be changed at the first login.
+pub fn bootstrap_admin(store: &Store) -> Result<(), Box<dyn std::error::Error>> {
+    if store.count_users()? > 0 {
+        return Ok(());
+    }
+    match (std::env::var("ADMIN_USERNAME"), std::env::var("ADMIN_PASSWORD")) {
+        (Ok(username), Ok(password)) if !username.is_empty() && !password.is_empty() => {
+            passwords::check_policy(&username, &password).map_err(|e| format!("ADMIN_PASSWORD is refused: {}", e))?;
+            let password_hash = hash(password, DEFAULT_COST)?;
+            store.create_user(&username, &password_hash, Role::Admin)?;
+            store.set_password(&username, &password_hash, true)?;
+            log::info!("created initial admin account '{}'; its password must be changed at the first login", username);
+        }
+        _ => log::warn!("no users exist and ADMIN_USERNAME/ADMIN_PASSWORD are not set; nobody can log in"),
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NewUser {
+    username: String,
+    password: String,
+    role: Option<Role>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RoleUpdate {
+    role: Role,
+}
+
+#[derive(Debug, Serialize)]
+struct UsersResponse {
+    message: String,
+    users: Vec<UserRecord>,
+}
+
+#[derive(Debug, Serialize)]
+struct UserResponse {
+    message: String,
+    user: UserRecord,
+}
+
+fn not_found(username: &str) -> actix_web::Error {
+    error::ErrorNotFound(format!("User {} not found", username))
+}
+
+pub async fn list_users(store: web::Data<Store>) -> Result<impl Responder, actix_web::Error> {
+    let users = store.list_users().map_err(error::ErrorInternalServerError)?;
+    Ok(web::Json(UsersResponse {
+        message: "Users List".to_string(),
+        users,
+    }))
+}
+
+pub async fn create_user(store: web::Data<Store>, user: web::Json<NewUser>) -> Result<impl Responder, actix_web::Error> {
+    let NewUser { username, password, role } = user.into_inner();
+    let username = username.trim().to_string();
+    if username.is_empty() || password.is_empty() {
+        return Err(error::ErrorBadRequest("Username and password are required"));
+    }
+    passwords::check_policy(&username, &password)?;
+    let password_hash = hash_password(password).await?;
+    let user = store.create_user(&username, &password_hash, role.unwrap_or(Role::Viewer)).map_err(|e| {
+        if db::is_constraint_violation(&e) {
+            error::ErrorConflict(format!("User {} already exists", username))
+        } else {
+            error::ErrorInternalServerError(e)
+        }
+    })?;
+    Ok(HttpResponse::Created().json(UserResponse {
+        message: format!("User {} created", user.username),
+        user,
+    }))
+}
+
+pub async fn delete_user(store: web::Data<Store>, username: web::Path<String>) -> Result<impl Responder, actix_web::Error> {
+    if !store.delete_user(&username).map_err(error::ErrorInternalServerError)? {
+        return Err(not_found(&username));
+    }
+    Ok(web::Json(crate::ApiResponse {
+        message: format!("User {} deleted", username),
+        docker_info: None,
+        containers: None,
+    }))
+}
+
+async fn set_disabled(store: web::Data<Store>, username: &str, disabled: bool) -> Result<web::Json<crate::ApiResponse>, actix_web::Error> {
+    if !store.set_user_disabled(username, disabled).map_err(error::ErrorInternalServerError)? {
+        return Err(not_found(username));
+    }
+    Ok(web::Json(crate::ApiResponse {
+        message: format!("User {} {}", username, if disabled { "disabled" } else { "enabled" }),
+        docker_info: None,
+        containers: None,
+    }))
+}
+
+pub async fn disable_user(store: web::Data<Store>, username: web::Path<String>) -> Result<impl Responder, actix_web::Error> {
+    set_disabled(store, &username, true).await
+}
+
+pub async fn enable_user(store: web::Data<Store>, username: web::Path<String>) -> Result<impl Responder, actix_web::Error> {
+    set_disabled(store, &username, false).await
+}
+
+pub async fn set_user_role(store: web::Data<Store>, username: web::Path<String>, update: web::Json<RoleUpdate>) -> Result<impl Responder, actix_web::Error> {
+    if !store.set_user_role(&username, update.role).map_err(error::ErrorInternalServerError)? {
+        return Err(not_found(&username));
+    }
+    Ok(web::Json(crate::ApiResponse {
+        message: format!("User {} is now {}", username, update.role),
+        docker_info: None,
+        containers: None,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use super::*;
+
+    /// Runs every bootstrap case in one test, since they share variables.
+    #[test]
+    fn bootstrap_admin_needs_a_strong_password_changed_at_first_login() {
+        let store = Store::open(":memory:").unwrap();
+        env::set_var("ADMIN_USERNAME", "root");
+        for weak in ["password", "root", "Sh0rt!"] {
+            env::set_var("ADMIN_PASSWORD", weak);
+            assert!(bootstrap_admin(&store).is_err(), "{}", weak);
+        }
+        assert_eq!(store.count_users().unwrap(), 0);
+
+        env::set_var("ADMIN_PASSWORD", "Corr3ct-horse");
+        bootstrap_admin(&store).unwrap();
+        let admin = store.find_user("root").unwrap().unwrap();
+        assert_eq!(admin.role, Role::Admin);
+        assert!(admin.must_change_password);
+        // Once anyone exists the variables are ignored.
+        env::set_var("ADMIN_USERNAME", "other");
+        bootstrap_admin(&store).unwrap();
+        assert_eq!(store.count_users().unwrap(), 1);
+        env::remove_var("ADMIN_USERNAME");
+        env::remove_var("ADMIN_PASSWORD");
+    }
+}
@@ -0,0 +1,27 @@
+#!/bin/sh
+# Regenerates the keys the tests load. They are for tests only.
+set -e
+cd "$(dirname "$0")"
+
+# JWT signing keys.
+openssl genpkey -algorithm RSA -pkeyopt rsa_keygen_bits:2048 -out jwt-rs256.pem
+openssl genpkey -algorithm ED25519 -out jwt-ed25519.pem
+
+# A CA, a server certificate and client certificates it signed, one of them
+# without a common name.
+openssl req -x509 -newkey ec -pkeyopt ec_paramgen_curve:P-256 -nodes -days 36500 \
+    -subj "/CN=Dashboard Test CA" -keyout tls-ca-key.pem -out tls-ca.pem
+issue() {
+    openssl req -newkey ec -pkeyopt ec_paramgen_curve:P-256 -nodes -subj "$2" -keyout "$1-key.pem" -out "$1.csr"
+    printf 'basicConstraints=CA:FALSE\n%s\n' "$3" > "$1.ext"
+    openssl x509 -req -in "$1.csr" -CA tls-ca.pem -CAkey tls-ca-key.pem -CAcreateserial \
+        -days 36500 -extfile "$1.ext" -out "$1.pem"
+    rm "$1.csr" "$1.ext"
+}
+issue tls-server "/CN=localhost" "subjectAltName=DNS:localhost"
+issue tls-client-alice "/O=Dashboard/CN=alice" "extendedKeyUsage=clientAuth"
+issue tls-client-nameless "/O=Dashboard" "extendedKeyUsage=clientAuth"
+rm -f tls-ca-key.pem tls-ca.srl
+
+# A certificate block whose body is not base64.
+printf -- '-----BEGIN CERTIFICATE-----\nThis is not base64!\n-----END CERTIFICATE-----\n' > tls-garbage.pem
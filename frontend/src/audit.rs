@@ -0,0 +1,175 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use dioxus::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::send_authorized_with;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct AuditEntry {
+    id: i64,
+    timestamp: i64,
+    username: String,
+    action: String,
+    #[serde(default)]
+    host: Option<String>,
+    container_id: Option<String>,
+    container_name: Option<String>,
+    container_image: Option<String>,
+    ip: Option<String>,
+    success: bool,
+    error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct AuditResponse {
+    message: String,
+    entries: Vec<AuditEntry>,
+}
+
+// datetime-local输入框的值，按UTC转换为时间戳
+fn parse_datetime(value: &str) -> Option<i64> {
+    NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M")
+        .ok()
+        .map(|dt| dt.and_utc().timestamp())
+}
+
+#[component]
+pub fn Audit() -> Element {
+    let mut user = use_signal(String::new);
+    let mut container = use_signal(String::new);
+    let mut since = use_signal(String::new);
+    let mut until = use_signal(String::new);
+
+    // 过滤条件用peek读取，只在点击Search时重新查询
+    let mut entries = use_resource(move || async move {
+        let mut query: Vec<(&str, String)> = Vec::new();
+        if !user.peek().is_empty() {
+            query.push(("user", user.peek().clone()));
+        }
+        if !container.peek().is_empty() {
+            query.push(("container", container.peek().clone()));
+        }
+        if let Some(ts) = parse_datetime(&since.peek()) {
+            query.push(("since", ts.to_string()));
+        }
+        if let Some(ts) = parse_datetime(&until.peek()) {
+            query.push(("until", ts.to_string()));
+        }
+        let response = send_authorized_with(reqwest::Method::GET, "/audit", |request| request.query(&query))
+            .await
+            .map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(response.text().await.unwrap_or_default());
+        }
+        response
+            .json::<AuditResponse>()
+            .await
+            .map(|r| r.entries)
+            .map_err(|e| e.to_string())
+    });
+
+    rsx! {
+        div {
+            class: "container-list",
+            h2 { "Audit Log" }
+            div { class: "row g-2 align-items-center mb-3",
+                div { class: "col-auto",
+                    input {
+                        class: "form-control",
+                        placeholder: "User",
+                        value: "{user}",
+                        oninput: move |e| user.set(e.value())
+                    }
+                }
+                div { class: "col-auto",
+                    input {
+                        class: "form-control",
+                        placeholder: "Container ID or name",
+                        value: "{container}",
+                        oninput: move |e| container.set(e.value())
+                    }
+                }
+                div { class: "col-auto",
+                    input {
+                        class: "form-control",
+                        r#type: "datetime-local",
+                        value: "{since}",
+                        oninput: move |e| since.set(e.value())
+                    }
+                }
+                div { class: "col-auto",
+                    input {
+                        class: "form-control",
+                        r#type: "datetime-local",
+                        value: "{until}",
+                        oninput: move |e| until.set(e.value())
+                    }
+                }
+                div { class: "col-auto",
+                    button {
+                        class: "btn btn-primary",
+                        onclick: move |_| entries.restart(),
+                        i { class: "bi bi-search" }
+                        " Search"
+                    }
+                }
+            }
+
+            match &*entries.read_unchecked() {
+                Some(Ok(entries)) => rsx! {
+                    table {
+                        class: "container-table",
+                        thead {
+                            tr {
+                                th { "Time" }
+                                th { "User" }
+                                th { "Action" }
+                                th { "Host" }
+                                th { "Container" }
+                                th { "Image" }
+                                th { "IP" }
+                                th { "Result" }
+                            }
+                        }
+                        tbody {
+                            for entry in entries.iter() {
+                                {
+                                    let datetime: DateTime<Utc> = DateTime::from_timestamp(entry.timestamp, 0).unwrap_or_default();
+                                    let time = datetime.format("%Y-%m-%d %H:%M:%S").to_string();
+                                    let container = entry
+                                        .container_name
+                                        .clone()
+                                        .or_else(|| entry.container_id.as_ref().map(|id| id.chars().take(12).collect()))
+                                        .unwrap_or_default();
+                                    let result = match (&entry.success, &entry.error) {
+                                        (true, _) => "OK".to_string(),
+                                        (false, Some(error)) => error.clone(),
+                                        (false, None) => "Failed".to_string(),
+                                    };
+                                    rsx! {
+                                        tr {
+                                            td { "{time}" }
+                                            td { "{entry.username}" }
+                                            td { "{entry.action}" }
+                                            td { {entry.host.clone().unwrap_or_default()} }
+                                            td { "{container}" }
+                                            td { {entry.container_image.clone().unwrap_or_default()} }
+                                            td { {entry.ip.clone().unwrap_or_default()} }
+                                            td { "{result}" }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                },
+                Some(Err(error)) => rsx! {
+                    p { class: "error", "{error}" }
+                },
+                None => rsx! {
+                    div { "Loading..." }
+                },
+            }
+        }
+    }
+}
@@ -0,0 +1,315 @@
+use std::collections::BTreeMap;
+use dioxus::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{host_path, send_authorized_with, SelectedHost};
+
+#[derive(Serialize, Debug, Clone)]
+struct NewContainer {
+    image: String,
+    name: Option<String>,
+    command: Option<String>,
+    env: Vec<String>,
+    ports: Vec<String>,
+    volumes: Vec<String>,
+    restart_policy: Option<String>,
+    labels: Vec<String>,
+    network: Option<String>,
+    memory_mb: Option<i64>,
+    cpus: Option<f64>,
+    start: bool,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+struct CreatedContainer {
+    message: String,
+}
+
+// 校验失败时后端按字段返回错误，例如 {"ports": "line 2: invalid host port 'x'"}
+#[derive(Deserialize, Debug, Clone)]
+struct CreateError {
+    message: String,
+    #[serde(default)]
+    fields: BTreeMap<String, String>,
+}
+
+fn optional(value: String) -> Option<String> {
+    let value = value.trim().to_string();
+    (!value.is_empty()).then_some(value)
+}
+
+// 每行一项；空行也原样发送，保证后端报告的行号与输入框一致
+fn lines(text: &str) -> Vec<String> {
+    if text.trim().is_empty() {
+        Vec::new()
+    } else {
+        text.lines().map(str::to_string).collect()
+    }
+}
+
+fn input_class(errors: &BTreeMap<String, String>, field: &str) -> &'static str {
+    if errors.contains_key(field) {
+        "form-control is-invalid"
+    } else {
+        "form-control"
+    }
+}
+
+fn field_error(errors: &BTreeMap<String, String>, field: &str) -> Element {
+    rsx! {
+        if let Some(error) = errors.get(field) {
+            div { class: "invalid-feedback d-block", "{error}" }
+        }
+    }
+}
+
+#[component]
+pub fn CreateContainer(on_created: EventHandler<String>, on_cancel: EventHandler<()>) -> Element {
+    let SelectedHost(host) = use_context::<SelectedHost>();
+    let mut image = use_signal(String::new);
+    let mut name = use_signal(String::new);
+    let mut command = use_signal(String::new);
+    let mut env = use_signal(String::new);
+    let mut ports = use_signal(String::new);
+    let mut volumes = use_signal(String::new);
+    let mut restart_policy = use_signal(String::new);
+    let mut labels = use_signal(String::new);
+    let mut network = use_signal(String::new);
+    let mut memory_mb = use_signal(String::new);
+    let mut cpus = use_signal(String::new);
+    let mut start = use_signal(|| true);
+    let mut errors = use_signal(BTreeMap::<String, String>::new);
+    let mut error = use_signal(|| None::<String>);
+    let mut submitting = use_signal(|| false);
+
+    let submit = move |evt: FormEvent| async move {
+        evt.prevent_default();
+        // 数字字段先在前端检查
+        let mut invalid = BTreeMap::new();
+        let memory = optional(memory_mb());
+        let memory = match memory.map(|v| v.parse::<i64>()) {
+            Some(Ok(mb)) => Some(mb),
+            Some(Err(_)) => {
+                invalid.insert("memory_mb".to_string(), "must be a whole number of MB".to_string());
+                None
+            }
+            None => None,
+        };
+        let cpu_limit = match optional(cpus()).map(|v| v.parse::<f64>()) {
+            Some(Ok(cpus)) => Some(cpus),
+            Some(Err(_)) => {
+                invalid.insert("cpus".to_string(), "must be a number, e.g. 0.5".to_string());
+                None
+            }
+            None => None,
+        };
+        if optional(image()).is_none() {
+            invalid.insert("image".to_string(), "is required".to_string());
+        }
+        if !invalid.is_empty() {
+            errors.set(invalid);
+            return;
+        }
+
+        let request = NewContainer {
+            image: image().trim().to_string(),
+            name: optional(name()),
+            command: optional(command()),
+            env: lines(&env()),
+            ports: lines(&ports()),
+            volumes: lines(&volumes()),
+            restart_policy: optional(restart_policy()),
+            labels: lines(&labels()),
+            network: optional(network()),
+            memory_mb: memory,
+            cpus: cpu_limit,
+            start: start(),
+        };
+        submitting.set(true);
+        let response = send_authorized_with(reqwest::Method::POST, &host_path(&host.peek(), "/containers"), |r| r.json(&request)).await;
+        submitting.set(false);
+        match response {
+            Ok(response) if response.status().is_success() => {
+                errors.set(BTreeMap::new());
+                error.set(None);
+                let message = match response.json::<CreatedContainer>().await {
+                    Ok(created) => created.message,
+                    Err(_) => "Container created".to_string(),
+                };
+                on_created.call(message);
+            }
+            Ok(response) => {
+                let text = response.text().await.unwrap_or_default();
+                match serde_json::from_str::<CreateError>(&text) {
+                    Ok(body) if !body.fields.is_empty() => {
+                        error.set(None);
+                        errors.set(body.fields);
+                    }
+                    Ok(body) => error.set(Some(body.message)),
+                    Err(_) => error.set(Some(text)),
+                }
+            }
+            Err(e) => error.set(Some(e.to_string())),
+        }
+    };
+
+    let errs = errors();
+    rsx! {
+        form {
+            class: "create-container",
+            onsubmit: submit,
+            h3 { "New container" }
+            if let Some(error) = error() {
+                p { class: "error", "{error}" }
+            }
+            div { class: "row g-3",
+                div { class: "col-md-6",
+                    label { class: "form-label", "Image" }
+                    input {
+                        class: input_class(&errs, "image"),
+                        placeholder: "nginx:1.27",
+                        value: "{image}",
+                        oninput: move |e| image.set(e.value())
+                    }
+                    {field_error(&errs, "image")}
+                }
+                div { class: "col-md-6",
+                    label { class: "form-label", "Name" }
+                    input {
+                        class: input_class(&errs, "name"),
+                        placeholder: "optional",
+                        value: "{name}",
+                        oninput: move |e| name.set(e.value())
+                    }
+                    {field_error(&errs, "name")}
+                }
+                div { class: "col-12",
+                    label { class: "form-label", "Command" }
+                    input {
+                        class: input_class(&errs, "command"),
+                        placeholder: "default from the image, e.g. sh -c 'echo hi'",
+                        value: "{command}",
+                        oninput: move |e| command.set(e.value())
+                    }
+                    {field_error(&errs, "command")}
+                }
+                div { class: "col-md-6",
+                    label { class: "form-label", "Environment" }
+                    textarea {
+                        class: input_class(&errs, "env"),
+                        rows: 3,
+                        placeholder: "KEY=value, one per line",
+                        value: "{env}",
+                        oninput: move |e| env.set(e.value())
+                    }
+                    {field_error(&errs, "env")}
+                }
+                div { class: "col-md-6",
+                    label { class: "form-label", "Labels" }
+                    textarea {
+                        class: input_class(&errs, "labels"),
+                        rows: 3,
+                        placeholder: "key=value, one per line",
+                        value: "{labels}",
+                        oninput: move |e| labels.set(e.value())
+                    }
+                    {field_error(&errs, "labels")}
+                }
+                div { class: "col-md-6",
+                    label { class: "form-label", "Ports" }
+                    textarea {
+                        class: input_class(&errs, "ports"),
+                        rows: 3,
+                        placeholder: "8080:80 or 127.0.0.1:5353:53/udp, one per line",
+                        value: "{ports}",
+                        oninput: move |e| ports.set(e.value())
+                    }
+                    {field_error(&errs, "ports")}
+                }
+                div { class: "col-md-6",
+                    label { class: "form-label", "Volumes" }
+                    textarea {
+                        class: input_class(&errs, "volumes"),
+                        rows: 3,
+                        placeholder: "data:/var/lib/data or /host/path:/path:ro, one per line",
+                        value: "{volumes}",
+                        oninput: move |e| volumes.set(e.value())
+                    }
+                    {field_error(&errs, "volumes")}
+                }
+                div { class: "col-md-3",
+                    label { class: "form-label", "Restart policy" }
+                    select {
+                        class: if errs.contains_key("restart_policy") { "form-select is-invalid" } else { "form-select" },
+                        value: "{restart_policy}",
+                        onchange: move |e| restart_policy.set(e.value()),
+                        option { value: "", "default (no)" }
+                        option { value: "always", "always" }
+                        option { value: "unless-stopped", "unless-stopped" }
+                        option { value: "on-failure", "on-failure" }
+                        option { value: "on-failure:5", "on-failure, 5 retries" }
+                    }
+                    {field_error(&errs, "restart_policy")}
+                }
+                div { class: "col-md-3",
+                    label { class: "form-label", "Network" }
+                    input {
+                        class: input_class(&errs, "network"),
+                        placeholder: "bridge",
+                        value: "{network}",
+                        oninput: move |e| network.set(e.value())
+                    }
+                    {field_error(&errs, "network")}
+                }
+                div { class: "col-md-3",
+                    label { class: "form-label", "Memory (MB)" }
+                    input {
+                        class: input_class(&errs, "memory_mb"),
+                        r#type: "number",
+                        min: "6",
+                        value: "{memory_mb}",
+                        oninput: move |e| memory_mb.set(e.value())
+                    }
+                    {field_error(&errs, "memory_mb")}
+                }
+                div { class: "col-md-3",
+                    label { class: "form-label", "CPUs" }
+                    input {
+                        class: input_class(&errs, "cpus"),
+                        r#type: "number",
+                        min: "0",
+                        step: "0.1",
+                        value: "{cpus}",
+                        oninput: move |e| cpus.set(e.value())
+                    }
+                    {field_error(&errs, "cpus")}
+                }
+                div { class: "col-12 form-check ms-2",
+                    input {
+                        class: "form-check-input",
+                        r#type: "checkbox",
+                        id: "create-start",
+                        checked: start(),
+                        onchange: move |e| start.set(e.checked())
+                    }
+                    label { class: "form-check-label", r#for: "create-start", "Start after creating" }
+                }
+                div { class: "col-12 operation-buttons",
+                    button {
+                        class: "btn btn-primary",
+                        r#type: "submit",
+                        disabled: submitting(),
+                        i { class: "bi bi-plus-lg" }
+                        if submitting() { " Creating..." } else { " Create" }
+                    }
+                    button {
+                        class: "btn btn-secondary",
+                        r#type: "button",
+                        onclick: move |_| on_cancel.call(()),
+                        "Cancel"
+                    }
+                }
+            }
+        }
+    }
+}
@@ -1,10 +1,15 @@
-use std::{str::FromStr, sync::Arc};
-use std::env;
+mod audit;
+mod create;
+mod remove;
+mod sessions;
 
 use dioxus::prelude::*;
-use reqwest;
 use serde::{Serialize, Deserialize};
-use chrono::{DateTime, TimeZone, Utc};
+use chrono::{DateTime, Utc};
+use audit::Audit;
+use create::CreateContainer;
+use remove::RemoveContainer;
+use sessions::Sessions;
 // use web_sys::console;
 // use dotenv::dotenv;
 
@@ -12,26 +17,183 @@ use chrono::{DateTime, TimeZone, Utc};
 struct User {
     username: String,
     password: String,
+    // 使用HttpOnly cookie会话，token不再经过JS
+    cookie: bool,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
-struct LoginResponse {
-    token: String,
+struct SessionResponse {
+    csrf_token: String,
+    // 管理员重置密码后，必须先修改密码
+    #[serde(default)]
+    password_change_required: bool,
     message: String,
 }
 
+#[derive(Debug, Serialize, Deserialize)]
+struct PasswordChange {
+    current_password: String,
+    new_password: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MfaChallenge {
+    mfa_required: bool,
+    mfa_token: String,
+    message: String,
+}
+
+// 开启两步验证的账号登录时返回MfaChallenge，否则直接建立会话
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(untagged)]
+enum LoginResult {
+    Session(SessionResponse),
+    Mfa(MfaChallenge),
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct MfaVerifyRequest {
+    mfa_token: String,
+    code: String,
+    cookie: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct AuthConfig {
+    sso_enabled: bool,
+    sso_login_path: Option<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct SsoExchangeRequest {
+    code: String,
+    cookie: bool,
+}
+
+fn local_storage() -> web_sys::Storage {
+    web_sys::window().unwrap().local_storage().unwrap().unwrap()
+}
+
+fn storage_item(key: &str) -> Option<String> {
+    local_storage().get_item(key).ok().flatten()
+}
+
 fn get_api_url(path: &str) -> String {
-    if let Some(stored_url) = web_sys::window()
-    .unwrap()
-    .local_storage()
-    .unwrap()
-    .and_then(|ls| ls.get_item("api_base_url").unwrap()){
-        format!("{}{}", stored_url, path)
-    }else {
-        format!("{}", path)
+    match storage_item("api_base_url") {
+        Some(stored_url) => format!("{}{}", stored_url, path),
+        None => path.to_string(),
+    }
+}
+
+// 所有API请求都带上会话cookie（跨端口部署时也需要）
+fn api_request(method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+    let request = reqwest::Client::new().request(method, get_api_url(path));
+    #[cfg(target_arch = "wasm32")]
+    let request = request.fetch_credentials_include();
+    request
+}
+
+// token保存在HttpOnly cookie里，这里只保存CSRF token
+fn store_session(session: &SessionResponse) {
+    local_storage().set_item("csrf_token", &session.csrf_token).unwrap();
+}
+
+fn clear_session() {
+    let _ = local_storage().remove_item("csrf_token");
+}
+
+// 用refresh cookie换取新的会话
+async fn refresh_session() -> bool {
+    let response = api_request(reqwest::Method::POST, "/auth/refresh").send().await;
+    match response {
+        Ok(response) if response.status().is_success() => match response.json::<SessionResponse>().await {
+            Ok(session) => {
+                store_session(&session);
+                true
+            }
+            Err(_) => false,
+        },
+        _ => {
+            clear_session();
+            false
+        }
+    }
+}
+
+// 带会话cookie和CSRF token发送请求，会话过期时自动刷新后重试一次
+async fn send_authorized(method: reqwest::Method, path: &str) -> reqwest::Result<reqwest::Response> {
+    send_authorized_with(method, path, |request| request).await
+}
+
+// 同send_authorized，可通过customize追加query、body等
+async fn send_authorized_with(
+    method: reqwest::Method,
+    path: &str,
+    customize: impl Fn(reqwest::RequestBuilder) -> reqwest::RequestBuilder,
+) -> reqwest::Result<reqwest::Response> {
+    let send = || {
+        let request = api_request(method.clone(), path)
+            .header("X-CSRF-Token", storage_item("csrf_token").unwrap_or_default());
+        customize(request).send()
+    };
+    let response = send().await?;
+    if response.status() == reqwest::StatusCode::UNAUTHORIZED && refresh_session().await {
+        return send().await;
+    }
+    Ok(response)
+}
+
+#[derive(Debug, Deserialize)]
+struct ErrorBody {
+    message: String,
+}
+
+// 错误响应可能是JSON（带message字段）或纯文本
+async fn error_message(response: reqwest::Response) -> String {
+    let text = response.text().await.unwrap_or_default();
+    serde_json::from_str::<ErrorBody>(&text).map(|body| body.message).unwrap_or(text)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct EngineInfo {
+    kind: String,
+    version: Option<String>,
+    api_version: String,
+}
+
+impl EngineInfo {
+    // 例如 "podman 4.9.3 (API 1.41)"
+    fn label(&self) -> String {
+        format!("{} {} (API {})", self.kind, self.version.as_deref().unwrap_or("?"), self.api_version)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+struct HostInfo {
+    name: String,
+    default: bool,
+    status: String,
+    engine: Option<EngineInfo>,
+    error: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct HostsResponse {
+    message: String,
+    hosts: Vec<HostInfo>,
+}
+
+// Navbar中选中的Docker主机，空字符串表示默认主机
+#[derive(Clone, Copy)]
+struct SelectedHost(Signal<String>);
+
+// 给容器相关的API路径加上主机前缀
+fn host_path(host: &str, path: &str) -> String {
+    if host.is_empty() {
+        path.to_string()
+    } else {
+        format!("/hosts/{}{}", host, path)
     }
-    // let base_url = env::var("API_BASE_URL").unwrap_or_else(|_| "http://localhost:8081".to_string());
-    // format!("{}{}", base_url, path)
 }
 
 #[derive(Serialize, Deserialize, Debug,Clone)]
@@ -59,6 +221,9 @@ struct ApiResponse {
     docker_info: Option<serde_json::Value>,
     // containers: Option<serde_json::Value>,
     containers: Option<Vec<Container>>,
+    // 只有/docker_info返回
+    #[serde(default)]
+    engine: Option<EngineInfo>,
 }
 
 #[derive(Debug, Clone, Routable, PartialEq)]
@@ -69,6 +234,10 @@ enum Route {
     DockerInfo {},
     #[route("/containers")]
     Containers {},
+    #[route("/audit")]
+    Audit {},
+    #[route("/sessions")]
+    Sessions {},
     #[route("/")]
     #[route("/login")]
     Login {},
@@ -107,38 +276,78 @@ fn Settings(base_url_signal: Signal<String>) -> Element {
 #[component]
 fn Login() -> Element {
     let mut base_url_signal = use_signal(|| String::from("http://localhost:8081"));
-    let mut username = use_signal(|| String::new());
-    let mut password = use_signal(|| String::new());
-    let mut error = use_signal(|| String::new());
+    let mut username = use_signal(String::new);
+    let mut password = use_signal(String::new);
+    let mut error = use_signal(String::new);
+    let mut mfa_token = use_signal(|| None as Option<String>);
+    let mut mfa_code = use_signal(String::new);
+    let mut must_change_password = use_signal(|| false);
+    let mut new_password = use_signal(String::new);
     let navigator = use_navigator();
 
+    // 登录成功后进入首页，或先要求修改密码
+    let mut finish_login = move |session: SessionResponse| {
+        store_session(&session);
+        if session.password_change_required {
+            error.set("Your password was reset, please choose a new one".to_string());
+            must_change_password.set(true);
+        } else {
+            navigator.push(Route::DockerInfo {});
+        }
+    };
+
     // Load base URL from local storage on component mount
     use_effect(move || {
-        if let Some(stored_url) = web_sys::window()
-            .unwrap()
-            .local_storage()
-            .unwrap()
-            .and_then(|ls| ls.get_item("api_base_url").unwrap())
-        {
+        if let Some(stored_url) = storage_item("api_base_url") {
             base_url_signal.set(stored_url);
         }
         // async {}
     });
-    
 
+    // 后端开启SSO时显示"Sign in with SSO"
+    let auth_config = use_resource(move || async move {
+        reqwest::Client::new()
+            .get(get_api_url("/auth/config"))
+            .send()
+            .await
+            .ok()?
+            .json::<AuthConfig>()
+            .await
+            .ok()
+    });
+
+    // SSO回调后带着?sso_code=回到登录页，用它换取token
+    use_effect(move || {
+        let search = web_sys::window().unwrap().location().search().unwrap_or_default();
+        let code = search
+            .trim_start_matches('?')
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("sso_code="))
+            .map(str::to_string);
+        if let Some(code) = code {
+            spawn(async move {
+                let response = api_request(reqwest::Method::POST, "/auth/oidc/exchange")
+                    .json(&SsoExchangeRequest { code, cookie: true })
+                    .send()
+                    .await;
+                match response {
+                    Ok(response) if response.status().is_success() => {
+                        if let Ok(session) = response.json::<SessionResponse>().await {
+                            finish_login(session);
+                        }
+                    }
+                    _ => error.set("SSO login failed".to_string()),
+                }
+            });
+        }
+    });
 
     let mut save_base_url = move |_| {
         let base_url = base_url_signal.to_string();
         if base_url.is_empty() {
             error.set("Base URL cannot be empty".to_string());
         } else {
-            web_sys::window()
-                .unwrap()
-                .local_storage()
-                .unwrap()
-                .unwrap()
-                .set_item("api_base_url", &base_url)
-                .unwrap();
+            local_storage().set_item("api_base_url", &base_url).unwrap();
             error.set("Base URL saved successfully".to_string());
         }
     };
@@ -148,24 +357,31 @@ fn Login() -> Element {
         let user = User {
             username: username(),
             password: password(),
+            cookie: true,
         };
 
-        match reqwest::Client::new()
-            .post(get_api_url("/auth/login"))
+        match api_request(reqwest::Method::POST, "/auth/login")
             .json(&user)
             .send()
             .await {
                 Ok(response) => {
                     if response.status().is_success() {
-                        if let Ok(login_response) = response.json::<LoginResponse>().await {
-                            // 存储token
-                            web_sys::window()
-                                .unwrap()
-                                .local_storage().unwrap().unwrap()
-                                .set_item("token", &login_response.token)
-                                .unwrap();
-                            navigator.push(Route::DockerInfo {});
+                        match response.json::<LoginResult>().await {
+                            Ok(LoginResult::Session(session)) => finish_login(session),
+                            Ok(LoginResult::Mfa(challenge)) => {
+                                error.set(challenge.message);
+                                mfa_token.set(Some(challenge.mfa_token));
+                            }
+                            Err(_) => error.set("Login failed".to_string()),
                         }
+                    } else if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS {
+                        let retry_after = response
+                            .headers()
+                            .get("retry-after")
+                            .and_then(|v| v.to_str().ok())
+                            .unwrap_or("a few")
+                            .to_string();
+                        error.set(format!("Too many failed logins, try again in {} seconds", retry_after));
                     } else {
                         error.set("Invalid credentials".to_string());
                     }
@@ -174,27 +390,125 @@ fn Login() -> Element {
         }
     };
 
+    let handle_verify = move |_| async move {
+        let Some(token) = mfa_token() else {
+            return;
+        };
+        let request = MfaVerifyRequest {
+            mfa_token: token,
+            code: mfa_code(),
+            cookie: true,
+        };
+        match api_request(reqwest::Method::POST, "/auth/2fa/verify")
+            .json(&request)
+            .send()
+            .await {
+                Ok(response) if response.status().is_success() => {
+                    if let Ok(session) = response.json::<SessionResponse>().await {
+                        finish_login(session);
+                    }
+                }
+                Ok(response) => {
+                    let message = response.text().await.unwrap_or_default();
+                    // 验证已过期时需要重新输入密码
+                    if message.contains("expired") {
+                        mfa_token.set(None);
+                        mfa_code.set(String::new());
+                    }
+                    error.set(message);
+                }
+                Err(_) => error.set("Login failed".to_string()),
+        }
+    };
+
+    let handle_change_password = move |_| async move {
+        let request = PasswordChange {
+            current_password: password(),
+            new_password: new_password(),
+        };
+        let response = send_authorized_with(reqwest::Method::POST, "/auth/password", |r| r.json(&request)).await;
+        match response {
+            Ok(response) if response.status().is_success() => {
+                if let Ok(session) = response.json::<SessionResponse>().await {
+                    must_change_password.set(false);
+                    finish_login(session);
+                }
+            }
+            Ok(response) => error.set(response.text().await.unwrap_or_default()),
+            Err(_) => error.set("Password change failed".to_string()),
+        }
+    };
+
+    let handle_sso_login = move |evt| {
+        save_base_url(evt);
+        if let Some(Some(config)) = &*auth_config.read() {
+            let path = config.sso_login_path.clone().unwrap_or_default();
+            let _ = web_sys::window().unwrap().location().set_href(&get_api_url(&path));
+        }
+    };
+
+    let sso_enabled = matches!(&*auth_config.read(), Some(Some(config)) if config.sso_enabled);
+
     rsx! {
         div { class: "login-container",
             h2 { "Login" }
             Settings {base_url_signal:base_url_signal}
-            div { class: "login-form",
-                input {
-                    class: "form-control",
-                    placeholder: "Username",
-                    value: "{username}",
-                    oninput: move |e| username.set(e.value().clone())
+            if must_change_password() {
+                div { class: "login-form",
+                    input {
+                        class: "form-control",
+                        r#type: "password",
+                        placeholder: "New password",
+                        autocomplete: "new-password",
+                        value: "{new_password}",
+                        oninput: move |e| new_password.set(e.value())
+                    }
+                    button { onclick: handle_change_password, class: "btn btn-primary", "Change password" }
+                    if !error().is_empty() {
+                        p { class: "error", "{error}" }
+                    }
                 }
-                input {
-                    class: "form-control",
-                    r#type: "password",
-                    placeholder: "Password",
-                    value: "{password}",
-                    oninput: move |e| password.set(e.value().clone())
+            } else if mfa_token().is_some() {
+                div { class: "login-form",
+                    input {
+                        class: "form-control",
+                        placeholder: "Authenticator or recovery code",
+                        autocomplete: "one-time-code",
+                        value: "{mfa_code}",
+                        oninput: move |e| mfa_code.set(e.value())
+                    }
+                    button { onclick: handle_verify, class: "btn btn-primary", "Verify" }
+                    if !error().is_empty() {
+                        p { class: "error", "{error}" }
+                    }
                 }
-                button { onclick: handle_login, class: "btn btn-primary", "Login" }
-                if !error().is_empty() {
-                    p { class: "error", "{error}" }
+            } else {
+                div { class: "login-form",
+                    input {
+                        class: "form-control",
+                        placeholder: "Username",
+                        value: "{username}",
+                        oninput: move |e| username.set(e.value().clone())
+                    }
+                    input {
+                        class: "form-control",
+                        r#type: "password",
+                        placeholder: "Password",
+                        value: "{password}",
+                        oninput: move |e| password.set(e.value().clone())
+                    }
+                    button { onclick: handle_login, class: "btn btn-primary", "Login" }
+                    if sso_enabled {
+                        button {
+                            onclick: handle_sso_login,
+                            class: "btn btn-outline-primary",
+                            i { class: "bi bi-box-arrow-in-right" }
+                            " Sign in with SSO"
+                        }
+                    }
+                    if !error().is_empty() {
+                        p { class: "error", "{error}" }
+                    }
                 }
             }
         }
@@ -215,10 +529,36 @@ fn main() {
 fn Navbar() -> Element {
     let mut show_drawer = use_signal(|| true);
 
+    let navigator = use_navigator();
+    let SelectedHost(mut selected_host) =
+        use_context_provider(|| SelectedHost(Signal::new(storage_item("docker_host").unwrap_or_default())));
+
+    // 主机列表及各主机的连接状态
+    let mut hosts = use_resource(move || async move {
+        send_authorized(reqwest::Method::GET, "/hosts")
+            .await
+            .ok()?
+            .json::<HostsResponse>()
+            .await
+            .ok()
+            .map(|r| r.hosts)
+    });
+
+    let select_host = move |e: Event<FormData>| {
+        let _ = local_storage().set_item("docker_host", &e.value());
+        selected_host.set(e.value());
+    };
+
     let toggle_drawer = move |_| {
         show_drawer.set(!show_drawer());
     };
 
+    let logout = move |_| async move {
+        let _ = api_request(reqwest::Method::POST, "/auth/logout").send().await;
+        clear_session();
+        navigator.push(Route::Login {});
+    };
+
     rsx! {
         div {
             id: "navbar",
@@ -237,6 +577,50 @@ fn Navbar() -> Element {
                         to: Route::Containers {},
                         "Containers"
                     }
+                    Link {
+                        to: Route::Audit {},
+                        "Audit"
+                    }
+                    Link {
+                        to: Route::Sessions {},
+                        "Sessions"
+                    }
+                    if let Some(Some(hosts_list)) = &*hosts.read() {
+                        {
+                            let current = hosts_list
+                                .iter()
+                                .find(|h| h.name == selected_host() || (selected_host().is_empty() && h.default));
+                            let status = match current {
+                                Some(HostInfo { status, error: Some(error), .. }) => format!("{}: {}", status, error),
+                                Some(HostInfo { status, engine: Some(engine), .. }) => format!("{}, {}", status, engine.label()),
+                                Some(host) => host.status.clone(),
+                                None => String::new(),
+                            };
+                            rsx! {
+                                select {
+                                    class: "form-select form-select-sm host-switcher",
+                                    onchange: select_host,
+                                    for host in hosts_list.iter() {
+                                        option {
+                                            value: if host.default { String::new() } else { host.name.clone() },
+                                            selected: current.is_some_and(|c| c.name == host.name),
+                                            "{host.name} ({host.status})"
+                                        }
+                                    }
+                                }
+                                span { class: "host-status", title: "{status}", "{status}" }
+                                button {
+                                    class: "btn btn-sm btn-link",
+                                    onclick: move |_| hosts.restart(),
+                                    i { class: "bi bi-arrow-clockwise" }
+                                }
+                            }
+                        }
+                    }
+                    button {
+                        onclick: logout,
+                        "Logout"
+                    }
                     // Link {
                     //     to: Route::Login {},
                     //     "Login"
@@ -267,23 +651,23 @@ fn App() -> Element {
 #[component]
 pub fn DockerInfo() -> Element {
     let mut contents = use_signal(|| "".to_string());
+    let mut engine = use_signal(String::new);
+    let SelectedHost(host) = use_context::<SelectedHost>();
     let get_docker_info = move |_| async move {
-         // 获取token
-         let token = web_sys::window()
-         .unwrap()
-         .local_storage().unwrap().unwrap()
-         .get_item("token")
-         .unwrap().unwrap();
-        let response = reqwest::Client::new().get(get_api_url("/docker_info")).bearer_auth(token).send()
-            .await
-            .unwrap()
-            .json::<ApiResponse>()
-            .await
-            .unwrap();
+        let response = match send_authorized(reqwest::Method::GET, &host_path(&host(), "/docker_info")).await {
+            Ok(response) if response.status().is_success() => response,
+            Ok(response) => return contents.set(error_message(response).await),
+            Err(e) => return contents.set(e.to_string()),
+        };
+        let Ok(response) = response.json::<ApiResponse>().await else {
+            return contents.set("None".to_string());
+        };
 
+        engine.set(response.engine.as_ref().map(EngineInfo::label).unwrap_or_default());
         let message = match &response.docker_info {
-            Some(info) => format!("{}", serde_json::to_string_pretty(info).unwrap_or("None".to_string())),
-            None => "None".to_string()
+            Some(info) => serde_json::to_string_pretty(info).unwrap_or("None".to_string()),
+            // Podman或旧版本的info可能无法解析，显示后端的说明
+            None => response.message.clone(),
         };
         contents.set(message);
     };
@@ -291,6 +675,9 @@ pub fn DockerInfo() -> Element {
     rsx! {
         div {
             class:"container",
+            if !engine().is_empty() {
+                div { "Engine: {engine}" }
+            }
             div {
             
                 "Docker Info: "
@@ -308,34 +695,35 @@ pub fn DockerInfo() -> Element {
 
 #[component]
 pub fn Containers() -> Element {
+    let SelectedHost(host) = use_context::<SelectedHost>();
+    let mut show_create = use_signal(|| false);
+    let mut notice = use_signal(|| None::<String>);
+    let mut action_error = use_signal(|| None::<String>);
+    // 待确认删除的容器 (id, name)
+    let mut removing = use_signal(|| None::<(String, String)>);
     // let mut containers = use_signal(|| None as Option<Vec<Container>>);
     let mut get_containers = use_resource(move|| async move {
-        // 获取token
-        let token = web_sys::window()
-        .unwrap()
-        .local_storage().unwrap().unwrap()
-        .get_item("token")
-        .unwrap().unwrap();
-        let  response = reqwest::Client::new().get(get_api_url("/containers")).bearer_auth(token).send()
+        let response = send_authorized(reqwest::Method::GET, &host_path(&host(), "/containers"))
             .await
-            .unwrap()
-            .json::<ApiResponse>()
-            .await
-            .unwrap();
+            .map_err(|e| e.to_string())?;
+        // Docker不可用时后端返回503和错误说明
+        if !response.status().is_success() {
+            return Err(error_message(response).await);
+        }
+        let response = response.json::<ApiResponse>().await.map_err(|e| e.to_string())?;
 
-            let aaa = response.containers.map(|a| {
-                return a.iter().map(|x| {
+            // containers.set(aaa);
+            Ok(response.containers.map(|a| {
+                a.iter().map(|x| {
                     // let datetime: DateTime<Utc> = DateTime::from_timestamp(x.created, 0).unwrap();
-                    return Container{
+                    Container{
                     id: x.id.chars().take(12).collect::<String>(),
                     // created_datetime: datetime.format("%Y-%m-%d %H:%M:%S").to_string(),
                     ..x.clone()
-                    };
-                }).collect::<Vec<Container>>();
+                    }
+                }).collect::<Vec<Container>>()
 
-            });
-            // containers.set(aaa);
-            return aaa;
+            }))
     });
 
     // for bb in get_containers.read_unchecked().as_ref().unwrap().iter() {
@@ -344,20 +732,27 @@ pub fn Containers() -> Element {
     //     }
     // }
 
-    let start_container = move |id:String| {
-            // 获取token
-            let token = web_sys::window()
-            .unwrap()
-            .local_storage().unwrap().unwrap()
-            .get_item("token")
-            .unwrap().unwrap();
-            return async move {
-                let _ = reqwest::Client::new()
-                    .post(get_api_url(&format!("/container/{}/start", id))).bearer_auth(token)
-                    .send()
-                    .await;
-                get_containers.restart();
+    // 停止/重启的超时秒数，空表示使用容器自身的设置
+    let mut stop_timeout = use_signal(String::new);
+    // Kill发送的信号，例如SIGHUP让进程重新加载配置
+    let mut kill_signal = use_signal(|| "SIGKILL".to_string());
+
+    // 执行容器操作：start、stop、restart、pause、unpause、kill
+    let container_action = move |id: String, action: &'static str| async move {
+        let mut query = Vec::new();
+        match action {
+            "stop" | "restart" if !stop_timeout.peek().trim().is_empty() => query.push(("t", stop_timeout.peek().trim().to_string())),
+            "kill" => query.push(("signal", kill_signal.peek().clone())),
+            _ => {}
+        }
+        let path = host_path(&host.peek(), &format!("/container/{}/{}", id, action));
+        let response = send_authorized_with(reqwest::Method::POST, &path, |r| r.query(&query)).await;
+        match response {
+            Ok(response) if response.status().is_success() => action_error.set(None),
+            Ok(response) => action_error.set(Some(error_message(response).await)),
+            Err(e) => action_error.set(Some(e.to_string())),
         }
+        get_containers.restart();
     };
 
     // async {
@@ -366,27 +761,80 @@ pub fn Containers() -> Element {
     // };
 
 
-    let  stop_container = move |id:String| async move {
-        // 获取token
-        let token = web_sys::window()
-        .unwrap()
-        .local_storage().unwrap().unwrap()
-        .get_item("token")
-        .unwrap().unwrap();
-        let _ = reqwest::Client::new()
-            .post(format!("http://127.0.0.1:8081/container/{}/stop", id)).bearer_auth(token)
-            .send()
-            .await;
-        get_containers.restart();
-    };
 
     rsx! {
         div {
             class: "container-list",
             h2 { "Docker Containers" }
+            if show_create() {
+                CreateContainer {
+                    on_created: move |message: String| {
+                        notice.set(Some(message));
+                        show_create.set(false);
+                        get_containers.restart();
+                    },
+                    on_cancel: move |_| show_create.set(false),
+                }
+            } else {
+                button {
+                    class: "btn btn-success mb-3",
+                    onclick: move |_| {
+                        notice.set(None);
+                        show_create.set(true);
+                    },
+                    i { class: "bi bi-plus-lg" }
+                    " New container"
+                }
+            }
+            if let Some(notice) = notice() {
+                p { class: "text-success", "{notice}" }
+            }
+            if let Some(error) = action_error() {
+                p { class: "error", "{error}" }
+            }
+            div { class: "container-toolbar",
+                label { class: "form-label", r#for: "stop-timeout", "Stop timeout (s)" }
+                input {
+                    class: "form-control",
+                    id: "stop-timeout",
+                    r#type: "number",
+                    min: "0",
+                    placeholder: "default",
+                    value: "{stop_timeout}",
+                    oninput: move |e| stop_timeout.set(e.value())
+                }
+                label { class: "form-label", r#for: "kill-signal", "Kill signal" }
+                select {
+                    class: "form-select",
+                    id: "kill-signal",
+                    value: "{kill_signal}",
+                    onchange: move |e| kill_signal.set(e.value()),
+                    option { value: "SIGKILL", "SIGKILL" }
+                    option { value: "SIGTERM", "SIGTERM" }
+                    option { value: "SIGINT", "SIGINT" }
+                    option { value: "SIGHUP", "SIGHUP (reload)" }
+                    option { value: "SIGUSR1", "SIGUSR1" }
+                    option { value: "SIGUSR2", "SIGUSR2" }
+                }
+            }
+            if let Some((id, name)) = removing() {
+                RemoveContainer {
+                    id,
+                    name,
+                    on_removed: move |message: String| {
+                        notice.set(Some(message));
+                        removing.set(None);
+                        get_containers.restart();
+                    },
+                    on_cancel: move |_| removing.set(None),
+                }
+            }
 
             match &*get_containers.read_unchecked() {
-                Some(ccc) => rsx! {
+                Some(Err(error)) => rsx! {
+                    p { class: "error", "{error}" }
+                },
+                Some(Ok(ccc)) => rsx! {
                     table {
                         class: "container-table",
                         thead {
@@ -401,10 +849,12 @@ pub fn Containers() -> Element {
                             }
                         }
                         tbody {
-                            for c in ccc.as_ref().unwrap().iter() {
+                            for c in ccc.iter().flatten() {
                                 {
                                     let  c_id = c.id.clone();
-                                    let  c_id2 = c.id.clone();
+                                    let running = c.status.starts_with("Up");
+                                    let paused = c.status.contains("(Paused)");
+                                    let c_target = (c.id.clone(), c.names[0].trim_start_matches('/').to_string());
                                     let datetime: DateTime<Utc> = DateTime::from_timestamp(c.created, 0).unwrap();
                                     let created_datetime = datetime.format("%Y-%m-%d %H:%M:%S").to_string();
                                     // let  c_id3 = c.id.clone();
@@ -419,7 +869,7 @@ pub fn Containers() -> Element {
                                             td {
                                                 div { class: "operation-buttons",
                                                     button {
-                                                        onclick: move |_| start_container(c_id.clone()) ,
+                                                        onclick: { let id = c_id.clone(); move |_| container_action(id.clone(), "start") },
                                                         id: "button-start",
                                                         class: "btn btn-primary",
                                                         name: "Start",
@@ -427,11 +877,55 @@ pub fn Containers() -> Element {
                                                         " Start"
                                                     },
                                                     button {
-                                                        onclick: move |_| stop_container(c_id2.clone()) ,
+                                                        onclick: { let id = c_id.clone(); move |_| container_action(id.clone(), "stop") },
                                                         class: "btn btn-danger",
                                                         name: "Stop",
                                                         i { class: "bi bi-stop-fill" }
                                                         " Stop"
+                                                    },
+                                                    button {
+                                                        onclick: { let id = c_id.clone(); move |_| container_action(id.clone(), "restart") },
+                                                        class: "btn btn-secondary",
+                                                        name: "Restart",
+                                                        i { class: "bi bi-arrow-clockwise" }
+                                                        " Restart"
+                                                    },
+                                                    if paused {
+                                                        button {
+                                                            onclick: { let id = c_id.clone(); move |_| container_action(id.clone(), "unpause") },
+                                                            class: "btn btn-warning",
+                                                            name: "Unpause",
+                                                            i { class: "bi bi-play-circle" }
+                                                            " Unpause"
+                                                        }
+                                                    } else {
+                                                        button {
+                                                            onclick: { let id = c_id.clone(); move |_| container_action(id.clone(), "pause") },
+                                                            class: "btn btn-warning",
+                                                            name: "Pause",
+                                                            disabled: !running,
+                                                            i { class: "bi bi-pause-fill" }
+                                                            " Pause"
+                                                        }
+                                                    },
+                                                    button {
+                                                        onclick: { let id = c_id.clone(); move |_| container_action(id.clone(), "kill") },
+                                                        class: "btn btn-outline-warning",
+                                                        name: "Kill",
+                                                        title: "Send the selected kill signal",
+                                                        disabled: !running,
+                                                        i { class: "bi bi-lightning-fill" }
+                                                        " Kill"
+                                                    },
+                                                    button {
+                                                        onclick: move |_| {
+                                                            notice.set(None);
+                                                            removing.set(Some(c_target.clone()));
+                                                        },
+                                                        class: "btn btn-outline-danger",
+                                                        name: "Remove",
+                                                        i { class: "bi bi-trash" }
+                                                        " Remove"
                                                     }
                                                 }
                                             }
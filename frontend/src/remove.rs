@@ -0,0 +1,93 @@
+use dioxus::prelude::*;
+
+use crate::{error_message, host_path, send_authorized_with, ApiResponse, SelectedHost};
+
+// 删除容器前的确认对话框
+#[component]
+pub fn RemoveContainer(id: String, name: String, on_removed: EventHandler<String>, on_cancel: EventHandler<()>) -> Element {
+    let SelectedHost(host) = use_context::<SelectedHost>();
+    let mut force = use_signal(|| false);
+    let mut volumes = use_signal(|| false);
+    let mut error = use_signal(|| None::<String>);
+    let mut removing = use_signal(|| false);
+
+    let remove = {
+        let id = id.clone();
+        move |_| {
+            let id = id.clone();
+            async move {
+                removing.set(true);
+                let flags = [("force", force().to_string()), ("v", volumes().to_string())];
+                let response = send_authorized_with(reqwest::Method::DELETE, &host_path(&host.peek(), &format!("/container/{}", id)), |r| r.query(&flags)).await;
+                removing.set(false);
+                match response {
+                    Ok(response) if response.status().is_success() => {
+                        let message = match response.json::<ApiResponse>().await {
+                            Ok(body) => body.message,
+                            Err(_) => format!("Container {} removed", id),
+                        };
+                        on_removed.call(message);
+                    }
+                    // 运行中的容器未勾选强制删除时后端返回409
+                    Ok(response) => error.set(Some(error_message(response).await)),
+                    Err(e) => error.set(Some(e.to_string())),
+                }
+            }
+        }
+    };
+
+    rsx! {
+        div { class: "modal-backdrop show" }
+        div { class: "modal d-block", tabindex: "-1",
+            div { class: "modal-dialog modal-dialog-centered",
+                div { class: "modal-content",
+                    div { class: "modal-header",
+                        h5 { class: "modal-title", "Remove container" }
+                    }
+                    div { class: "modal-body",
+                        p { "Remove " strong { "{name}" } " ({id})? This cannot be undone." }
+                        if let Some(error) = error() {
+                            p { class: "text-danger", "{error}" }
+                        }
+                        div { class: "form-check",
+                            input {
+                                class: "form-check-input",
+                                r#type: "checkbox",
+                                id: "remove-force",
+                                checked: force(),
+                                onchange: move |e| force.set(e.checked())
+                            }
+                            label { class: "form-check-label", r#for: "remove-force", "Force (kill the container if it is running)" }
+                        }
+                        div { class: "form-check",
+                            input {
+                                class: "form-check-input",
+                                r#type: "checkbox",
+                                id: "remove-volumes",
+                                checked: volumes(),
+                                onchange: move |e| volumes.set(e.checked())
+                            }
+                            label { class: "form-check-label", r#for: "remove-volumes", "Remove anonymous volumes" }
+                        }
+                    }
+                    div { class: "modal-footer",
+                        button {
+                            class: "btn btn-secondary",
+                            r#type: "button",
+                            onclick: move |_| on_cancel.call(()),
+                            "Cancel"
+                        }
+                        button {
+                            class: "btn btn-danger",
+                            r#type: "button",
+                            disabled: removing(),
+                            onclick: remove,
+                            i { class: "bi bi-trash" }
+                            if removing() { " Removing..." } else { " Remove" }
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,128 @@
+use chrono::{DateTime, Utc};
+use dioxus::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{clear_session, send_authorized, Route};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SessionEntry {
+    id: String,
+    username: String,
+    user_agent: Option<String>,
+    ip: Option<String>,
+    created_at: i64,
+    last_seen_at: i64,
+    current: bool,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct SessionsResponse {
+    message: String,
+    sessions: Vec<SessionEntry>,
+}
+
+fn format_time(timestamp: i64) -> String {
+    let datetime: DateTime<Utc> = DateTime::from_timestamp(timestamp, 0).unwrap_or_default();
+    datetime.format("%Y-%m-%d %H:%M:%S").to_string()
+}
+
+#[component]
+pub fn Sessions() -> Element {
+    let navigator = use_navigator();
+    let mut error = use_signal(|| None::<String>);
+
+    let mut sessions = use_resource(move || async move {
+        let response = send_authorized(reqwest::Method::GET, "/sessions")
+            .await
+            .map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(response.text().await.unwrap_or_default());
+        }
+        response
+            .json::<SessionsResponse>()
+            .await
+            .map(|r| r.sessions)
+            .map_err(|e| e.to_string())
+    });
+
+    // 注销当前会话后回到登录页
+    let sign_out = move |id: String, current: bool| async move {
+        match send_authorized(reqwest::Method::DELETE, &format!("/sessions/{}", id)).await {
+            Ok(response) if response.status().is_success() => {
+                if current {
+                    clear_session();
+                    navigator.push(Route::Login {});
+                } else {
+                    error.set(None);
+                    sessions.restart();
+                }
+            }
+            Ok(response) => error.set(Some(response.text().await.unwrap_or_default())),
+            Err(e) => error.set(Some(e.to_string())),
+        }
+    };
+
+    rsx! {
+        div {
+            class: "container-list",
+            h2 { "Sessions" }
+            if let Some(error) = error() {
+                p { class: "error", "{error}" }
+            }
+
+            match &*sessions.read_unchecked() {
+                Some(Ok(sessions)) => rsx! {
+                    table {
+                        class: "container-table",
+                        thead {
+                            tr {
+                                th { "User" }
+                                th { "Device" }
+                                th { "IP" }
+                                th { "Signed in" }
+                                th { "Last seen" }
+                                th { "" }
+                            }
+                        }
+                        tbody {
+                            for session in sessions.iter() {
+                                {
+                                    let id = session.id.clone();
+                                    let current = session.current;
+                                    rsx! {
+                                        tr {
+                                            td { "{session.username}" }
+                                            td {
+                                                {session.user_agent.clone().unwrap_or_default()}
+                                                if current {
+                                                    span { class: "badge bg-primary ms-2", "This device" }
+                                                }
+                                            }
+                                            td { {session.ip.clone().unwrap_or_default()} }
+                                            td { {format_time(session.created_at)} }
+                                            td { {format_time(session.last_seen_at)} }
+                                            td {
+                                                button {
+                                                    class: "btn btn-sm btn-outline-danger",
+                                                    onclick: move |_| sign_out(id.clone(), current),
+                                                    i { class: "bi bi-box-arrow-right" }
+                                                    " Sign out"
+                                                }
+                                            }
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                    }
+                },
+                Some(Err(error)) => rsx! {
+                    p { class: "error", "{error}" }
+                },
+                None => rsx! {
+                    div { "Loading..." }
+                },
+            }
+        }
+    }
+}